@@ -0,0 +1,326 @@
+//! The `aggregate` crate provides group-by aggregations over CSV records. Aggregates can be
+//! computed independently on multiple threads and merged into one result afterwards.
+extern crate csv;
+extern crate csv_filter_config as config;
+extern crate hashbrown;
+
+use config::{Aggregate, AggregateFunction, Aggregation, ValueType};
+use csv::StringRecord;
+use hashbrown::{HashMap, HashSet};
+
+/// A typed column value that is used to determine minimum and maximum values.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub enum Value {
+    Text(String),
+    Number(f64),
+}
+
+impl Value {
+    fn to_output(&self) -> String {
+        match self {
+            Value::Text(t) => t.clone(),
+            Value::Number(n) => n.to_string(),
+        }
+    }
+}
+
+/// A sum that stays an exact integer as long as all added values are integers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sum {
+    Integer(i128),
+    Float(f64),
+}
+
+impl Sum {
+    /// Parses a column value as number. Returns `None` if the value is not a finite number.
+    fn parse(value: &str) -> Option<Sum> {
+        let value = value.trim();
+        match value.parse::<i128>() {
+            Ok(n) => Some(Sum::Integer(n)),
+            Err(_) => value
+                .parse::<f64>()
+                .ok()
+                .filter(|n| n.is_finite())
+                .map(Sum::Float),
+        }
+    }
+
+    /// Adds two sums. The result is only a floating point number if one of the sums is, or if
+    /// the integer sum overflows.
+    fn add(self, other: Sum) -> Sum {
+        match (self, other) {
+            (Sum::Integer(a), Sum::Integer(b)) => a
+                .checked_add(b)
+                .map_or(Sum::Float(a as f64 + b as f64), Sum::Integer),
+            (a, b) => Sum::Float(a.as_f64() + b.as_f64()),
+        }
+    }
+
+    fn as_f64(self) -> f64 {
+        match self {
+            Sum::Integer(n) => n as f64,
+            Sum::Float(n) => n,
+        }
+    }
+
+    fn to_output(self) -> String {
+        match self {
+            Sum::Integer(n) => n.to_string(),
+            Sum::Float(n) => n.to_string(),
+        }
+    }
+}
+
+/// The intermediate state of one [`Aggregation`] for one group.
+#[derive(Debug, Clone)]
+pub enum AggregateState {
+    Count(u64),
+    Sum(Sum),
+    Min(Option<Value>),
+    Max(Option<Value>),
+    Avg(Sum, u64),
+    CountDistinct(HashSet<String>),
+}
+
+impl AggregateState {
+    /// Creates the initial state for an aggregation function.
+    fn new(function: AggregateFunction) -> Self {
+        match function {
+            AggregateFunction::Count => AggregateState::Count(0),
+            AggregateFunction::Sum => AggregateState::Sum(Sum::Integer(0)),
+            AggregateFunction::Min => AggregateState::Min(None),
+            AggregateFunction::Max => AggregateState::Max(None),
+            AggregateFunction::Avg => AggregateState::Avg(Sum::Integer(0), 0),
+            AggregateFunction::CountDistinct => AggregateState::CountDistinct(HashSet::new()),
+        }
+    }
+
+    /// Adds one column value to the state. Empty values are ignored by all functions. Returns
+    /// `false` if the value was skipped, because a numeric aggregation cannot use it.
+    ///
+    /// # Arguments
+    /// * `value` - The column value or `None` if the aggregation has no column (i.e. rows are counted)
+    /// * `aggregation` - The aggregation this state belongs to
+    fn add(&mut self, value: Option<&str>, aggregation: &Aggregation) -> bool {
+        let value = match value {
+            Some("") => return true,
+            Some(v) => v,
+            None => {
+                if let AggregateState::Count(n) = self {
+                    *n += 1;
+                }
+                return true;
+            }
+        };
+
+        match self {
+            AggregateState::Count(n) => *n += 1,
+            AggregateState::Sum(sum) => match Sum::parse(value) {
+                Some(n) => *sum = sum.add(n),
+                None => return false,
+            },
+            AggregateState::Min(min) => match typed_value(value, aggregation) {
+                Some(v) => update_min(min, v),
+                None => return false,
+            },
+            AggregateState::Max(max) => match typed_value(value, aggregation) {
+                Some(v) => update_max(max, v),
+                None => return false,
+            },
+            AggregateState::Avg(sum, count) => match Sum::parse(value) {
+                Some(n) => {
+                    *sum = sum.add(n);
+                    *count += 1;
+                }
+                None => return false,
+            },
+            AggregateState::CountDistinct(values) => {
+                if !values.contains(value) {
+                    values.insert(value.to_string());
+                }
+            }
+        }
+        true
+    }
+
+    /// Merges the state of another thread into this state.
+    fn merge(&mut self, other: AggregateState) {
+        match (self, other) {
+            (AggregateState::Count(a), AggregateState::Count(b)) => *a += b,
+            (AggregateState::Sum(a), AggregateState::Sum(b)) => *a = a.add(b),
+            (AggregateState::Min(a), AggregateState::Min(Some(b))) => update_min(a, b),
+            (AggregateState::Max(a), AggregateState::Max(Some(b))) => update_max(a, b),
+            (AggregateState::Avg(sum_a, count_a), AggregateState::Avg(sum_b, count_b)) => {
+                *sum_a = sum_a.add(sum_b);
+                *count_a += count_b;
+            }
+            (AggregateState::CountDistinct(a), AggregateState::CountDistinct(b)) => a.extend(b),
+            _ => {}
+        }
+    }
+
+    /// Returns the final value of the aggregation as it is written to the output file.
+    fn to_output(&self) -> String {
+        match self {
+            AggregateState::Count(n) => n.to_string(),
+            AggregateState::Sum(sum) => sum.to_output(),
+            AggregateState::Min(v) | AggregateState::Max(v) => {
+                v.as_ref().map(Value::to_output).unwrap_or_default()
+            }
+            AggregateState::Avg(_, 0) => String::new(),
+            AggregateState::Avg(sum, count) => (sum.as_f64() / *count as f64).to_string(),
+            AggregateState::CountDistinct(values) => values.len().to_string(),
+        }
+    }
+}
+
+/// Holds the aggregation states of all groups of one aggregated output file.
+#[derive(Debug, Default)]
+pub struct Aggregates {
+    groups: HashMap<Vec<String>, Vec<AggregateState>>,
+    skipped_values: usize,
+}
+
+impl Aggregates {
+    /// Creates an empty set of aggregates.
+    pub fn new() -> Self {
+        Aggregates::default()
+    }
+
+    /// Adds a CSV record to the group it belongs to. Values of numeric aggregations that are not
+    /// numbers are skipped and counted, see [`Aggregates::skipped_values`].
+    ///
+    /// # Arguments
+    /// * `aggregate` - The aggregation configuration
+    /// * `csv_record` - The record to add
+    /// * `headers` - Maps a CSV column name to its index in the current CSV file
+    ///
+    /// # Panics
+    /// This function will panic if a column is not part of the CSV file. Use
+    /// [`check_columns`] to make sure all columns exist before adding records.
+    pub fn add(
+        &mut self,
+        aggregate: &Aggregate,
+        csv_record: &StringRecord,
+        headers: &HashMap<String, usize>,
+    ) {
+        let group: Vec<String> = aggregate
+            .group_by
+            .iter()
+            .map(|c| csv_record[column_index(c, headers)].to_string())
+            .collect();
+
+        let states = self.groups.entry(group).or_insert_with(|| {
+            aggregate
+                .aggregations
+                .iter()
+                .map(|a| AggregateState::new(a.function))
+                .collect()
+        });
+
+        for (state, aggregation) in states.iter_mut().zip(&aggregate.aggregations) {
+            let value = aggregation
+                .column
+                .as_ref()
+                .map(|c| &csv_record[column_index(c, headers)]);
+            if !state.add(value, aggregation) {
+                self.skipped_values += 1;
+            }
+        }
+    }
+
+    /// Returns the number of values that were skipped, because they are not numbers.
+    pub fn skipped_values(&self) -> usize {
+        self.skipped_values
+    }
+
+    /// Merges the aggregates computed by another thread into these aggregates.
+    pub fn merge(&mut self, other: Aggregates) {
+        self.skipped_values += other.skipped_values;
+        for (group, other_states) in other.groups {
+            match self.groups.get_mut(&group) {
+                Some(states) => {
+                    for (state, other_state) in states.iter_mut().zip(other_states) {
+                        state.merge(other_state);
+                    }
+                }
+                None => {
+                    self.groups.insert(group, other_states);
+                }
+            }
+        }
+    }
+
+    /// Returns all rows of the aggregated table ordered by their group values.
+    pub fn into_rows(self) -> Vec<Vec<String>> {
+        let mut rows: Vec<Vec<String>> = self
+            .groups
+            .into_iter()
+            .map(|(group, states)| {
+                group
+                    .into_iter()
+                    .chain(states.iter().map(AggregateState::to_output))
+                    .collect()
+            })
+            .collect();
+        rows.sort();
+        rows
+    }
+}
+
+/// Checks that all group-by and aggregation columns are part of the CSV file. Returns the first
+/// column that is missing as error.
+///
+/// # Arguments
+/// * `aggregate` - The aggregation configuration
+/// * `headers` - Maps a CSV column name to its index in the current CSV file
+pub fn check_columns(
+    aggregate: &Aggregate,
+    headers: &HashMap<String, usize>,
+) -> Result<(), String> {
+    let columns = aggregate.group_by.iter().chain(
+        aggregate
+            .aggregations
+            .iter()
+            .filter_map(|a| a.column.as_ref()),
+    );
+    for column in columns {
+        if !headers.contains_key(column) {
+            return Err(column.clone());
+        }
+    }
+    Ok(())
+}
+
+/// Looks up the index of a column in the CSV file.
+///
+/// # Panics
+/// This function will panic if the column is not part of the CSV file.
+fn column_index(column: &str, headers: &HashMap<String, usize>) -> usize {
+    *headers
+        .get(column)
+        .unwrap_or_else(|| panic!("Cannot find index of '{}' header", column))
+}
+
+/// Replaces the current minimum if the value is smaller.
+fn update_min(min: &mut Option<Value>, value: Value) {
+    if min.as_ref().is_none_or(|m| value < *m) {
+        *min = Some(value);
+    }
+}
+
+/// Replaces the current maximum if the value is greater.
+fn update_max(max: &mut Option<Value>, value: Value) {
+    if max.as_ref().is_none_or(|m| value > *m) {
+        *max = Some(value);
+    }
+}
+
+/// Converts a column value into the type configured for an aggregation. Returns `None` if a
+/// numeric value is not a number.
+fn typed_value(value: &str, aggregation: &Aggregation) -> Option<Value> {
+    match aggregation.value_type {
+        ValueType::String => Some(Value::Text(value.to_string())),
+        ValueType::Number => Sum::parse(value).map(|n| Value::Number(n.as_f64())),
+    }
+}
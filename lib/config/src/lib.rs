@@ -1,25 +1,788 @@
+extern crate csv;
+extern crate csv_filter_expression as expression;
+extern crate csv_filter_util as util;
 extern crate hashbrown;
+extern crate json5;
 extern crate serde_json;
+extern crate serde_yaml;
+extern crate toml;
 
+use expression::Expression;
 use hashbrown::HashSet;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use util::bloom::BloomFilter;
+
+/// Refers to a column of a CSV file whose values are loaded into a value list.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ValuesFrom {
+    pub file: String,
+    pub column: String,
+}
+
+/// Represents the settings of a bloom filter that quickly rejects values which are not listed.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct BloomFilterSettings {
+    /// The targeted probability of the bloom filter letting a value pass that is not listed.
+    pub false_positive_rate: f64,
+    /// Replaces the value list with the bloom filter instead of checking values that pass the
+    /// bloom filter against the list. This bounds memory consumption, but lets roughly
+    /// `false_positive_rate` of the values pass that are not listed, i.e. rows are written
+    /// that do not match the filter.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_false_positives: bool,
+}
 
 /// Represents detailed column configuration of a filter configuration.
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct ColumnFilter {
     pub column: String,
     pub include: bool,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_sorted"
+    )]
     pub values: Option<HashSet<String>>,
+    /// A text file containing one allowed value per line.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub values_file: Option<String>,
+    /// A column of a CSV file containing the allowed values.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub values_from: Option<ValuesFrom>,
+    /// If present, allowed values are additionally kept in a bloom filter, which rejects most
+    /// values that are not listed before they are looked up in the value set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bloom_filter: Option<BloomFilterSettings>,
+    /// The bloom filter holding the allowed values. Only set after [`ColumnFilter::load_values`].
+    /// If false positives are allowed, it replaces `values`.
+    #[serde(skip)]
+    pub values_bloom_filter: Option<BloomFilter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub min: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub max: Option<String>,
 }
 
+impl ColumnFilter {
+    /// Checks if the filter restricts the column to a list of allowed values.
+    pub fn has_values(&self) -> bool {
+        self.values.is_some() || self.values_file.is_some() || self.values_from.is_some()
+    }
+
+    /// Loads the values from `values_file` and `values_from` into `values`. If a bloom filter is
+    /// configured, all values (including inline ones) are inserted into the bloom filter as well.
+    /// If the bloom filter allows false positives, the values are moved into the bloom filter
+    /// instead.
+    ///
+    /// # Panics
+    /// This function will panic if a value file cannot be read.
+    pub fn load_values(&mut self) {
+        match &self.bloom_filter {
+            Some(settings) if settings.allow_false_positives => {
+                let inline_values = self.values.take().unwrap_or_default();
+                let mut count = inline_values.len();
+                self.for_each_file_value(|_| count += 1);
+
+                let mut bloom_filter = BloomFilter::new(count, settings.false_positive_rate);
+                for value in &inline_values {
+                    bloom_filter.insert(value);
+                }
+                self.for_each_file_value(|v| bloom_filter.insert(&v));
+                self.values_bloom_filter = Some(bloom_filter);
+            }
+            _ => {
+                if self.values_file.is_some() || self.values_from.is_some() {
+                    let mut values = self.values.take().unwrap_or_default();
+                    self.for_each_file_value(|v| {
+                        values.insert(v);
+                    });
+                    self.values = Some(values);
+                }
+
+                if let (Some(settings), Some(values)) = (&self.bloom_filter, &self.values) {
+                    let mut bloom_filter =
+                        BloomFilter::new(values.len(), settings.false_positive_rate);
+                    for value in values {
+                        bloom_filter.insert(value);
+                    }
+                    self.values_bloom_filter = Some(bloom_filter);
+                }
+            }
+        }
+    }
+
+    /// Reads all values from `values_file` and `values_from` without collecting them.
+    ///
+    /// # Panics
+    /// This function will panic if a value file cannot be read.
+    fn for_each_file_value<F: FnMut(String)>(&self, mut f: F) {
+        if let Some(path) = &self.values_file {
+            let content = fs::read_to_string(path)
+                .unwrap_or_else(|e| panic!("Cannot read values file '{}': {}", path, e));
+            content
+                .lines()
+                .map(|l| l.trim_end_matches('\r'))
+                .filter(|l| !l.is_empty())
+                .for_each(|l| f(l.to_string()));
+        }
+
+        if let Some(values_from) = &self.values_from {
+            let mut reader = csv::Reader::from_path(&values_from.file).unwrap_or_else(|e| {
+                panic!("Cannot read values file '{}': {}", values_from.file, e)
+            });
+            let index = reader
+                .headers()
+                .ok()
+                .and_then(|h| h.iter().position(|c| c == values_from.column))
+                .unwrap_or_else(|| {
+                    panic!(
+                        "Cannot find column '{}' in values file '{}'",
+                        values_from.column, values_from.file
+                    )
+                });
+            for record in reader.records() {
+                let record = record.unwrap_or_else(|e| {
+                    panic!("Cannot parse values file '{}': {}", values_from.file, e)
+                });
+                f(record[index].to_string());
+            }
+        }
+    }
+}
+
+/// Defines which of two rows with the same deduplication key is kept in an output file. First
+/// and last refer to the order of the rows in the output file, which is the input order only if
+/// the filter stage uses a single thread. Sorted output files are deduplicated in sort order.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Keep {
+    #[default]
+    First,
+    Last,
+}
+
+/// Represents the row deduplication settings of a filter configuration.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Distinct {
+    /// Output columns that make up the deduplication key. If absent, the whole row is the key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub columns: Option<Vec<String>>,
+    #[serde(default)]
+    pub keep: Keep,
+    /// Partitions rows into temporary bucket files instead of keeping all keys in memory.
+    #[serde(default)]
+    pub spill_to_disk: bool,
+}
+
+/// The functions that can be used to aggregate the values of a column.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+    CountDistinct,
+}
+
+impl AggregateFunction {
+    /// Returns the name of the function as it is used in configuration files.
+    pub fn name(self) -> &'static str {
+        match self {
+            AggregateFunction::Count => "count",
+            AggregateFunction::Sum => "sum",
+            AggregateFunction::Min => "min",
+            AggregateFunction::Max => "max",
+            AggregateFunction::Avg => "avg",
+            AggregateFunction::CountDistinct => "count_distinct",
+        }
+    }
+}
+
+/// The type column values are interpreted as when they are compared.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueType {
+    #[default]
+    String,
+    Number,
+}
+
+/// Represents one aggregated column of an aggregated output file.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Aggregation {
+    pub function: AggregateFunction,
+    /// The input column to aggregate. Only `count` may omit it, in which case rows are counted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<String>,
+    /// The type used to compare values for `min` and `max`. `sum` and `avg` are always numeric.
+    #[serde(rename = "type", default)]
+    pub value_type: ValueType,
+    /// The name of the output column. Defaults to `<function>_<column>`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+impl Aggregation {
+    /// Returns the name of the column this aggregation is written to.
+    pub fn output_column(&self) -> String {
+        match (&self.output, &self.column) {
+            (Some(output), _) => output.clone(),
+            (None, Some(column)) => format!("{}_{}", self.function.name(), column),
+            (None, None) => self.function.name().to_string(),
+        }
+    }
+}
+
+/// Represents the group-by settings of an aggregated output file.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Aggregate {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub group_by: Vec<String>,
+    pub aggregations: Vec<Aggregation>,
+}
+
+/// Defines what happens to rows without a matching row in a lookup file.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum JoinType {
+    /// Rows without a match are kept and the joined columns are left empty.
+    #[default]
+    Left,
+    /// Rows without a match are dropped.
+    Inner,
+}
+
+/// Represents a lookup join that adds columns of a secondary CSV file to each row. Joined
+/// columns can be referenced by name like any input column.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Lookup {
+    /// Path of the secondary CSV file.
+    pub file: String,
+    /// The input column whose value is looked up.
+    pub key: String,
+    /// The column of the lookup file that is matched against `key`. Defaults to `key`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lookup_key: Option<String>,
+    /// The columns of the lookup file that are added to each row.
+    pub columns: Vec<String>,
+    #[serde(default)]
+    pub join: JoinType,
+}
+
+impl Lookup {
+    /// Returns the name of the lookup file column that is matched against `key`.
+    pub fn lookup_key_column(&self) -> &str {
+        self.lookup_key.as_deref().unwrap_or(&self.key)
+    }
+}
+
+/// Represents a column whose value is computed from other columns. Computed columns can be
+/// referenced by name like any input column, e.g. in a [`ColumnFilter`].
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ComputedColumn {
+    pub name: String,
+    pub expression: Expression,
+}
+
+/// Represents one column of an output file. Its value is either copied from a source column
+/// (an input or computed column) or computed by an expression.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct OutputColumn {
+    /// The name of the column in the output file.
+    pub name: String,
+    /// The column to copy the value from. Defaults to `name` if no expression is defined.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expression: Option<Expression>,
+}
+
+impl OutputColumn {
+    /// Returns the name of the column the value is copied from.
+    pub fn source_column(&self) -> &str {
+        self.source.as_deref().unwrap_or(&self.name)
+    }
+}
+
+/// A transform that replaces the value of an output column, e.g. to scrub personal data before
+/// output files are shared.
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Transform {
+    /// Replaces every value with a fixed placeholder.
+    Redact {
+        #[serde(default = "default_placeholder")]
+        placeholder: String,
+    },
+    /// Replaces all but the last `keep_last` characters with `mask_char`.
+    Mask {
+        keep_last: usize,
+        #[serde(default = "default_mask_char")]
+        mask_char: char,
+    },
+    /// Replaces every value with its hex encoded HMAC-SHA256. The key is read from an environment
+    /// variable or from a file.
+    Hash {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        key_env: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        key_file: Option<String>,
+    },
+    /// Replaces every value with a token. Tokens are persisted to a lookup file, so that the same
+    /// value is replaced by the same token across runs.
+    Tokenize {
+        lookup_file: String,
+        #[serde(default = "default_token_prefix")]
+        prefix: String,
+    },
+}
+
+/// Serializes a value list in alphabetical order, so that serialized configurations are stable.
+fn serialize_sorted<S: Serializer>(
+    values: &Option<HashSet<String>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut sorted: Vec<&String> = values.iter().flatten().collect();
+    sorted.sort();
+    sorted.serialize(serializer)
+}
+
+fn default_placeholder() -> String {
+    "[REDACTED]".to_string()
+}
+
+fn default_mask_char() -> char {
+    '*'
+}
+
+fn default_token_prefix() -> String {
+    "token_".to_string()
+}
+
+/// Represents a transform applied to one output column.
+#[derive(Deserialize, Serialize, Debug)]
+pub struct ColumnTransform {
+    /// The name of the output column to transform.
+    pub column: String,
+    pub transform: Transform,
+    /// Sorts by the original value instead of the transformed value if the column is a sort
+    /// column.
+    #[serde(default)]
+    pub sort_by_original: bool,
+}
+
 /// Contains all data of one filter configuration item from a configuration file.
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct FilterConfig {
     pub filters: Vec<ColumnFilter>,
+    /// Lookup joins that are applied before computed columns, in the order they are declared.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lookups: Vec<Lookup>,
+    /// Columns that are computed before filters are applied, in the order they are declared.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub computed_columns: Vec<ComputedColumn>,
+    /// Path of a Rhai script defining `filter(row)` and/or `map(row)`, which are applied to rows
+    /// that pass all filters.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub script: Option<String>,
     pub output: String,
+    /// Explicitly defines name and order of all output columns. If present, the `include`
+    /// attribute of all filters is ignored.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_columns: Option<Vec<OutputColumn>>,
+    /// Transforms applied to output column values. Filters always see the original values.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transforms: Vec<ColumnTransform>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub sort_columns: Option<Vec<String>>,
+    /// Sort columns whose values are sorted in descending instead of ascending order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub descending_columns: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distinct: Option<Distinct>,
+    /// If present, matching rows are aggregated and only the aggregated table is written out.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aggregate: Option<Aggregate>,
+}
+
+impl FilterConfig {
+    /// Returns the names of all columns of the output file in the order they are written.
+    pub fn output_column_names(&self) -> Vec<String> {
+        if let Some(output_columns) = &self.output_columns {
+            return output_columns.iter().map(|c| c.name.clone()).collect();
+        }
+
+        match &self.aggregate {
+            Some(aggregate) => aggregate
+                .group_by
+                .iter()
+                .cloned()
+                .chain(aggregate.aggregations.iter().map(|a| a.output_column()))
+                .collect(),
+            None => self
+                .filters
+                .iter()
+                .filter(|f| f.include)
+                .map(|f| f.column.to_string())
+                .collect(),
+        }
+    }
+
+    /// Resolves all relative paths of files referenced by the configuration item (value files,
+    /// lookup files, the script and key and token files of transforms) against a directory,
+    /// which usually is the directory of the configuration file. Absolute paths are kept.
+    ///
+    /// # Arguments
+    /// * `base_dir` - The directory relative paths are resolved against
+    pub fn resolve_paths(&mut self, base_dir: &Path) {
+        let resolve = |path: &mut String| *path = resolve_path(base_dir, path);
+
+        for column_filter in &mut self.filters {
+            column_filter.values_file.iter_mut().for_each(resolve);
+            if let Some(values_from) = &mut column_filter.values_from {
+                resolve(&mut values_from.file);
+            }
+        }
+        for lookup in &mut self.lookups {
+            resolve(&mut lookup.file);
+        }
+        self.script.iter_mut().for_each(resolve);
+        for column_transform in &mut self.transforms {
+            match &mut column_transform.transform {
+                Transform::Hash { key_file, .. } => key_file.iter_mut().for_each(resolve),
+                Transform::Tokenize { lookup_file, .. } => resolve(lookup_file),
+                Transform::Redact { .. } | Transform::Mask { .. } => {}
+            }
+        }
+    }
+
+    /// Returns the sort columns that are sorted by their original value although their output
+    /// values are transformed. Their original values are written to hidden columns that follow
+    /// the output columns until the output file has been sorted.
+    pub fn original_sort_columns(&self) -> Vec<String> {
+        self.sort_columns
+            .iter()
+            .flatten()
+            .filter(|c| {
+                self.transforms
+                    .iter()
+                    .any(|t| t.sort_by_original && &t.column == *c)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// Resolves a path against a directory unless the path is absolute.
+///
+/// # Arguments
+/// * `base_dir` - The directory a relative path is resolved against
+/// * `path` - The path to resolve
+pub fn resolve_path(base_dir: &Path, path: &str) -> String {
+    if Path::new(path).is_absolute() {
+        return path.to_string();
+    }
+    base_dir.join(path).to_string_lossy().into_owned()
+}
+
+/// The file formats configuration files can be written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Json,
+    /// JSON5, which is a superset of JSON that also covers JSONC (JSON with comments).
+    Json5,
+    Yaml,
+    Toml,
+}
+
+impl ConfigFormat {
+    /// Determines the format of a configuration file from its extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .and_then(|e| e.parse().ok())
+    }
+
+    /// Returns the JSON path of a configuration item in a file of this format.
+    pub fn item_path(self, index: usize) -> String {
+        match self {
+            ConfigFormat::Toml => format!("$.config[{}]", index),
+            _ => format!("$[{}]", index),
+        }
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "json" => Ok(ConfigFormat::Json),
+            "json5" | "jsonc" => Ok(ConfigFormat::Json5),
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            "toml" => Ok(ConfigFormat::Toml),
+            _ => Err(format!(
+                "Unknown configuration format '{}' (expected json, json5, jsonc, yaml or toml)",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ConfigFormat::Json => "JSON",
+            ConfigFormat::Json5 => "JSON5",
+            ConfigFormat::Yaml => "YAML",
+            ConfigFormat::Toml => "TOML",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The severity of a [`Diagnostic`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    /// The configuration cannot be used.
+    Error,
+    /// The configuration can be used, but most likely does not do what was intended.
+    Warning,
+}
+
+/// A problem found in a configuration file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The JSON path of the offending element, e.g. `$[0].filters[1].min`.
+    pub path: String,
+    pub message: String,
+    /// The line and column (both starting at 1) of the offending content, if known.
+    pub location: Option<(usize, usize)>,
+}
+
+impl Diagnostic {
+    /// Creates an error diagnostic.
+    pub fn error(path: String, message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            path,
+            message,
+            location: None,
+        }
+    }
+
+    /// Creates a warning diagnostic.
+    pub fn warning(path: String, message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            path,
+            message,
+            location: None,
+        }
+    }
+
+    /// Checks if this diagnostic is an error.
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{} at {}", severity, self.path)?;
+        if let Some((line, column)) = self.location {
+            write!(f, " (line {}, column {})", line, column)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// The result of deserializing a configuration file.
+#[derive(Debug)]
+pub struct ParsedConfig {
+    pub configs: Vec<FilterConfig>,
+    /// Problems that did not prevent deserialization, such as unknown keys.
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// The root of TOML configuration files, since TOML documents cannot be arrays.
+#[derive(Deserialize)]
+struct TomlConfig {
+    config: Vec<FilterConfig>,
+}
+
+/// Deserializes a configuration file and returns a list of [`FilterConfig`]. TOML files contain
+/// the configuration items as array of tables named `config` (i.e. `[[config]]`). Unknown keys
+/// are ignored and reported as warning diagnostics. If deserialization fails, all diagnostics found so far
+/// are returned along with the cause of the failure.
+///
+/// # Arguments
+/// * `source` - The full configuration content
+/// * `format` - The format of the configuration content
+pub fn deserialize_format(
+    source: &str,
+    format: ConfigFormat,
+) -> Result<ParsedConfig, Vec<Diagnostic>> {
+    let mut diagnostics = Vec::new();
+
+    let result = match format {
+        ConfigFormat::Json => {
+            let mut deserializer = serde_json::Deserializer::from_str(source);
+            deserialize_tracked(&mut deserializer, &mut diagnostics)
+                .and_then(|configs| {
+                    deserializer
+                        .end()
+                        .map(|_| configs)
+                        .map_err(|e| (e, String::from("$")))
+                })
+                .map_err(|(e, path)| {
+                    let location = Some((e.line(), e.column()));
+                    parse_error(strip_location(e.to_string()), path, location)
+                })
+        }
+        ConfigFormat::Json5 => json5::Deserializer::from_str(source)
+            .map_err(|e| (e, String::from("$")))
+            .and_then(|mut deserializer| deserialize_tracked(&mut deserializer, &mut diagnostics))
+            .map_err(|(e, path)| match e {
+                json5::Error::Message { msg, location } => {
+                    parse_error(msg, path, location.map(|l| (l.line, l.column)))
+                }
+            }),
+        ConfigFormat::Yaml => {
+            deserialize_tracked(serde_yaml::Deserializer::from_str(source), &mut diagnostics)
+                .map_err(|(e, path)| {
+                    let location = e.location().map(|l| (l.line(), l.column()));
+                    let message = strip_location(e.to_string());
+                    // serde_yaml prefixes messages with its own path, which is already known
+                    let message = match message.split_once(": ") {
+                        Some((prefix, rest)) if prefix.starts_with('.') => rest.to_string(),
+                        _ => message,
+                    };
+                    parse_error(message, path, location)
+                })
+        }
+        ConfigFormat::Toml => {
+            deserialize_tracked::<_, TomlConfig>(toml::Deserializer::new(source), &mut diagnostics)
+                .map(|c| c.config)
+                .map_err(|(e, path)| {
+                    let location = e.span().map(|span| line_and_column(source, span.start));
+                    parse_error(e.message().to_string(), path, location)
+                })
+        }
+    };
+
+    match result {
+        Ok(configs) => Ok(ParsedConfig {
+            configs,
+            diagnostics,
+        }),
+        Err(error) => {
+            diagnostics.push(error);
+            Err(diagnostics)
+        }
+    }
+}
+
+/// Deserializes a value while tracking the path of the element that is being deserialized.
+/// Unknown keys are added to the diagnostics. On failure, the error is returned along with the
+/// JSON path of the element that failed.
+fn deserialize_tracked<'de, D, T>(
+    deserializer: D,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Result<T, (D::Error, String)>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    let mut unknown_key = |path: serde_ignored::Path| {
+        diagnostics.push(Diagnostic::warning(
+            json_path(&path),
+            "Unknown key".to_string(),
+        ))
+    };
+    let ignored = serde_ignored::Deserializer::new(deserializer, &mut unknown_key);
+
+    serde_path_to_error::deserialize(ignored).map_err(|e| {
+        let path = match e.path().to_string().as_str() {
+            "." => String::from("$"),
+            p if p.starts_with('[') => format!("${}", p),
+            p => format!("$.{}", p),
+        };
+        (e.into_inner(), path)
+    })
+}
+
+/// Converts a path of an ignored key into a JSON path.
+fn json_path(path: &serde_ignored::Path) -> String {
+    match path {
+        serde_ignored::Path::Root => String::from("$"),
+        serde_ignored::Path::Seq { parent, index } => format!("{}[{}]", json_path(parent), index),
+        serde_ignored::Path::Map { parent, key } => format!("{}.{}", json_path(parent), key),
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => json_path(parent),
+    }
+}
+
+/// Creates an error diagnostic for a deserialization error.
+fn parse_error(message: String, path: String, location: Option<(usize, usize)>) -> Diagnostic {
+    Diagnostic {
+        location,
+        ..Diagnostic::error(path, message)
+    }
+}
+
+/// Removes a location in the form of " at line <line> column <column>" from the end of an error
+/// message, as produced by `serde_json` and `serde_yaml`.
+fn strip_location(message: String) -> String {
+    match message.rfind(" at line ") {
+        Some(index) => message[..index].to_string(),
+        None => message,
+    }
+}
+
+/// Converts a byte offset into a line and column, both starting at 1.
+fn line_and_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+    (line, column)
+}
+
+/// The root of serialized TOML configuration files. See [`TomlConfig`].
+#[derive(Serialize)]
+struct TomlConfigRef<'a> {
+    config: &'a [FilterConfig],
+}
+
+/// Serializes configuration items into the content of a configuration file. JSON5 files are
+/// written as plain JSON, which is valid JSON5.
+///
+/// # Arguments
+/// * `configs` - The configuration items
+/// * `format` - The format of the configuration content
+///
+/// # Panics
+/// This function will panic if the configuration items cannot be serialized.
+pub fn serialize_format(configs: &[FilterConfig], format: ConfigFormat) -> String {
+    match format {
+        ConfigFormat::Json | ConfigFormat::Json5 => {
+            serde_json::to_string_pretty(configs).expect("Cannot serialize JSON config") + "\n"
+        }
+        ConfigFormat::Yaml => serde_yaml::to_string(configs).expect("Cannot serialize YAML config"),
+        ConfigFormat::Toml => toml::to_string(&TomlConfigRef { config: configs })
+            .expect("Cannot serialize TOML config"),
+    }
 }
 
 /// Deserializes the JSON configuration file and returns a list of [`FilterConfig`].
@@ -30,5 +793,7 @@ pub struct FilterConfig {
 /// # Panics
 /// This function will panic on any error.
 pub fn deserialize(json: &str) -> Vec<FilterConfig> {
-    serde_json::from_str(json.trim()).expect("Cannot deserialize JSON config")
+    deserialize_format(json, ConfigFormat::Json)
+        .unwrap_or_else(|d| panic!("Cannot deserialize JSON config: {}", d[d.len() - 1]))
+        .configs
 }
@@ -0,0 +1,442 @@
+//! The `distinct` crate provides a CSV file processor that is able to remove duplicate rows
+//! from CSV files.
+extern crate crossbeam;
+extern crate csv;
+extern crate csv_filter_config as config;
+extern crate csv_filter_util as util;
+extern crate hashbrown;
+extern crate log;
+extern crate tempfile;
+
+use config::{Distinct, FilterConfig, Keep};
+use crossbeam::channel::bounded as bounded_channel;
+
+use csv::{ReaderBuilder, StringRecord, Writer};
+use hashbrown::{HashMap, HashSet};
+use log::info;
+use std::cmp::Reverse;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+// These type definitions are only here for abbreviation
+type DistinctConfig = Vec<(PathBuf, Arc<FilterConfig>)>;
+
+/// The number of bucket files rows are partitioned into when spilling to disk.
+const SPILL_BUCKETS: u64 = 64;
+
+/// Removes duplicate rows from all output CSV files that define a `distinct` configuration.
+///
+/// # Arguments
+/// * `all_filter_configs` - A vector containing all configuration items
+/// * `output_dir_path` - Path to the directory containing all output files
+/// * `skip_sorted` - If files with sort columns should be skipped, because duplicates have
+///   already been removed from them while sorting
+/// * `max_threads` - The maximum number of threads to use
+/// * `cancel` - Once set, no more rows are read and files that have not been deduplicated
+///   completely are left unchanged
+///
+/// # Panics
+/// This function will panic on any error.
+pub fn distinct_output_files(
+    all_filter_configs: &[Arc<FilterConfig>],
+    output_dir_path: &str,
+    skip_sorted: bool,
+    max_threads: usize,
+    cancel: Option<&AtomicBool>,
+) {
+    let files = read_distinct_config(all_filter_configs, output_dir_path, skip_sorted);
+
+    let (channel_sender, channel_receiver) = bounded_channel::<(PathBuf, Arc<FilterConfig>)>(256);
+    crossbeam::scope(|scope| {
+        // The following will create channel consumer threads that will be consuming file paths.
+        for _ in 0..max_threads {
+            let channel_receiver = channel_receiver.clone();
+
+            scope.spawn(move |_| {
+                for (path, config) in &channel_receiver {
+                    if util::is_cancelled(cancel) {
+                        break;
+                    }
+                    if let Some(distinct) = &config.distinct {
+                        info!(
+                            "Removing duplicates from file '{}'",
+                            util::path_to_string(&path)
+                        );
+                        distinct_csv_file(&path, distinct, cancel);
+                    }
+                }
+            });
+        }
+
+        for path_and_config in files {
+            channel_sender
+                .send(path_and_config)
+                .expect("Error sending record to channel");
+        }
+
+        // Stopping the channel, so that all threads finish once all files have been processed
+        drop(channel_sender);
+    })
+    .expect("Cannot join thread.");
+}
+
+/// Removes duplicate records from a list of records. The relative order of the remaining
+/// records is preserved.
+///
+/// # Arguments
+/// * `records` - The records to remove duplicates from
+/// * `key_indices` - The indexes of the columns that make up the deduplication key
+/// * `keep` - Which of the records with the same key should be kept
+pub fn distinct_records(
+    records: Vec<StringRecord>,
+    key_indices: &[usize],
+    keep: Keep,
+) -> Vec<StringRecord> {
+    let mut seen = HashSet::new();
+    match keep {
+        Keep::First => records
+            .into_iter()
+            .filter(|r| seen.insert(record_key(r, key_indices)))
+            .collect(),
+        Keep::Last => {
+            let mut distinct: Vec<StringRecord> = records
+                .into_iter()
+                .rev()
+                .filter(|r| seen.insert(record_key(r, key_indices)))
+                .collect();
+            distinct.reverse();
+            distinct
+        }
+    }
+}
+
+/// Creates a vector holding the indexes of all columns that make up the deduplication key.
+///
+/// # Arguments
+/// * `header_row` - The header row from the CSV file holding the column names.
+/// * `distinct` - The deduplication configuration.
+///
+/// # Panics
+/// This function will panic if a key column is not part of the header row.
+pub fn get_key_indices(header_row: &[String], distinct: &Distinct) -> Vec<usize> {
+    match &distinct.columns {
+        Some(columns) => columns
+            .iter()
+            .map(|c| {
+                header_row
+                    .iter()
+                    .position(|h| h == c)
+                    .unwrap_or_else(|| panic!("Cannot find distinct column '{}'", c))
+            })
+            .collect(),
+        None => (0..header_row.len()).collect(),
+    }
+}
+
+/// Collects all output files that need to be deduplicated.
+///
+/// # Arguments
+/// * `all_filter_configs` - A vector containing all configuration items
+/// * `output_dir_path` - Path to the directory containing all output files
+/// * `skip_sorted` - If files with sort columns should be skipped
+///
+/// # Panics
+/// This function will panic if a CSV output file specified in one of the provided
+/// configurations cannot be found.
+fn read_distinct_config(
+    all_filter_configs: &[Arc<FilterConfig>],
+    output_dir_path: &str,
+    skip_sorted: bool,
+) -> DistinctConfig {
+    let mut files = DistinctConfig::new();
+    for cfg in all_filter_configs {
+        if cfg.distinct.is_none() || (skip_sorted && cfg.sort_columns.is_some()) {
+            continue;
+        }
+
+        let path = Path::new(output_dir_path).join(&cfg.output);
+        if !path.exists() {
+            panic!(
+                "Cannot remove duplicates from file '{:?}' because it does not exists.",
+                path
+            );
+        }
+
+        files.push((path, cfg.clone()));
+    }
+    files
+}
+
+/// Removes duplicate rows from a CSV file. The result is written to a temporary file first,
+/// which then replaces the original file.
+///
+/// # Arguments
+/// * `path` - Path to the file to remove duplicates from
+/// * `distinct` - The deduplication configuration
+/// * `cancel` - Once set, no more rows are read and the file is left unchanged
+///
+/// # Panics
+/// This function will panic on any error.
+pub fn distinct_csv_file(path: &Path, distinct: &Distinct, cancel: Option<&AtomicBool>) {
+    let mut csv_reader = get_reader(path, true);
+    let header_row: Vec<String> = csv_reader
+        .headers()
+        .expect("Cannot read CSV headers")
+        .iter()
+        .map(|h| h.to_string())
+        .collect();
+    let key_indices = get_key_indices(&header_row, distinct);
+
+    let dir = path.parent().expect("Cannot resolve output directory");
+    let temp_file = tempfile::NamedTempFile::new_in(dir).expect("Cannot create temporary file");
+    {
+        let mut writer = Writer::from_writer(temp_file.as_file());
+        writer
+            .write_record(&header_row)
+            .expect("Error writing headers to output CSV file");
+
+        if distinct.spill_to_disk {
+            distinct_spilled(
+                csv_reader,
+                &key_indices,
+                distinct.keep,
+                dir,
+                &mut writer,
+                cancel,
+            );
+        } else {
+            drop(csv_reader);
+            distinct_in_memory(path, &key_indices, distinct.keep, &mut writer, cancel);
+        }
+
+        writer.flush().expect("Error flushing output CSV file");
+    }
+
+    // The temporary file is removed when it is dropped
+    if util::is_cancelled(cancel) {
+        return;
+    }
+
+    temp_file.persist(path).unwrap_or_else(|e| {
+        panic!(
+            "Cannot replace output file '{}': {:?}",
+            util::path_to_string(path),
+            e
+        )
+    });
+}
+
+/// Removes duplicate rows from a CSV file by keeping all keys in memory. For [`Keep::Last`]
+/// the file is read twice: once to find the last occurrence of each key, and once to write
+/// out exactly those rows.
+///
+/// # Arguments
+/// * `path` - Path to the file to remove duplicates from
+/// * `key_indices` - The indexes of the columns that make up the deduplication key
+/// * `keep` - Which of the records with the same key should be kept
+/// * `writer` - The writer to write all remaining rows to
+/// * `cancel` - Once set, no more rows are read
+fn distinct_in_memory(
+    path: &Path,
+    key_indices: &[usize],
+    keep: Keep,
+    writer: &mut Writer<&File>,
+    cancel: Option<&AtomicBool>,
+) {
+    let not_cancelled = |_: &_| !util::is_cancelled(cancel);
+    match keep {
+        Keep::First => {
+            let mut seen = HashSet::new();
+            for record in get_reader(path, true).records().take_while(not_cancelled) {
+                let record = record.expect("Cannot parse CSV record");
+                if seen.insert(record_key(&record, key_indices)) {
+                    write_record(writer, &record);
+                }
+            }
+        }
+        Keep::Last => {
+            let mut last_occurrences = HashMap::new();
+            let records = get_reader(path, true).into_records();
+            for (index, record) in records.take_while(not_cancelled).enumerate() {
+                let record = record.expect("Cannot parse CSV record");
+                last_occurrences.insert(record_key(&record, key_indices), index);
+            }
+
+            let records = get_reader(path, true).into_records();
+            for (index, record) in records.take_while(not_cancelled).enumerate() {
+                let record = record.expect("Cannot parse CSV record");
+                if last_occurrences[&record_key(&record, key_indices)] == index {
+                    write_record(writer, &record);
+                }
+            }
+        }
+    }
+}
+
+/// Removes duplicate rows from a CSV file without keeping all keys in memory at once. Rows are
+/// partitioned by the hash of their key into temporary bucket files, so that all duplicates of
+/// a row end up in the same bucket. Each bucket is deduplicated on its own and the remaining
+/// rows of all buckets are merged back into their original order. Only the distinct keys of one
+/// bucket are held in memory at a time, which is about 1/64 of all distinct keys however often
+/// single keys are repeated.
+///
+/// # Arguments
+/// * `csv_reader` - The reader of the file to remove duplicates from
+/// * `key_indices` - The indexes of the columns that make up the deduplication key
+/// * `keep` - Which of the records with the same key should be kept
+/// * `temp_dir_path` - Directory to create the temporary bucket files in
+/// * `writer` - The writer to write all remaining rows to
+/// * `cancel` - Once set, no more rows are read
+fn distinct_spilled(
+    mut csv_reader: csv::Reader<File>,
+    key_indices: &[usize],
+    keep: Keep,
+    temp_dir_path: &Path,
+    writer: &mut Writer<&File>,
+    cancel: Option<&AtomicBool>,
+) {
+    let not_cancelled = |_: &_| !util::is_cancelled(cancel);
+    let temp_dir = tempfile::tempdir_in(temp_dir_path).expect("Cannot create temporary directory");
+    let bucket_path = |bucket: u64| temp_dir.path().join(format!("bucket_{}.csv", bucket));
+    let survivors_path = |bucket: u64| temp_dir.path().join(format!("survivors_{}.csv", bucket));
+
+    // Each spilled row is prefixed with its original row index, so that the original order
+    // can be restored when the buckets are merged again.
+    let mut bucket_writers: Vec<Writer<File>> = (0..SPILL_BUCKETS)
+        .map(|b| Writer::from_path(bucket_path(b)).expect("Cannot create bucket file"))
+        .collect();
+    for (index, record) in csv_reader.records().take_while(not_cancelled).enumerate() {
+        let record = record.expect("Cannot parse CSV record");
+        let bucket = hash_key(&record_key(&record, key_indices)) % SPILL_BUCKETS;
+        write_record(
+            &mut bucket_writers[bucket as usize],
+            &indexed_record(index, &record),
+        );
+    }
+    for mut w in bucket_writers {
+        w.flush().expect("Error flushing bucket file");
+    }
+
+    // All duplicates of a key are located in the same bucket, hence every bucket can be
+    // deduplicated independently. Only the keys of one bucket are held in memory at a time, so
+    // memory use does not grow with the number of duplicates of a key.
+    let shifted_key_indices: Vec<usize> = key_indices.iter().map(|i| i + 1).collect();
+    for b in 0..SPILL_BUCKETS {
+        let mut kept_indices = HashMap::new();
+        for record in get_reader(&bucket_path(b), false)
+            .records()
+            .take_while(not_cancelled)
+        {
+            let record = record.expect("Cannot parse bucket record");
+            let key = record_key(&record, &shifted_key_indices);
+            let index = record_index(&record);
+            match keep {
+                Keep::First => {
+                    kept_indices.entry(key).or_insert(index);
+                }
+                Keep::Last => {
+                    kept_indices.insert(key, index);
+                }
+            }
+        }
+
+        let mut survivors_writer =
+            Writer::from_path(survivors_path(b)).expect("Cannot create bucket file");
+        for record in get_reader(&bucket_path(b), false)
+            .records()
+            .take_while(not_cancelled)
+        {
+            let record = record.expect("Cannot parse bucket record");
+            if kept_indices[&record_key(&record, &shifted_key_indices)] == record_index(&record) {
+                write_record(&mut survivors_writer, &record);
+            }
+        }
+        survivors_writer
+            .flush()
+            .expect("Error flushing bucket file");
+    }
+
+    // Every survivors file is ordered by row index, so a k-way merge restores the original order.
+    let mut survivors: Vec<_> = (0..SPILL_BUCKETS)
+        .map(|b| get_reader(&survivors_path(b), false).into_records())
+        .collect();
+    let mut heads: Vec<Option<StringRecord>> = survivors
+        .iter_mut()
+        .map(|records| {
+            records
+                .next()
+                .map(|r| r.expect("Cannot parse bucket record"))
+        })
+        .collect();
+    let mut heap: BinaryHeap<Reverse<(usize, usize)>> = heads
+        .iter()
+        .enumerate()
+        .filter_map(|(b, head)| head.as_ref().map(|r| Reverse((record_index(r), b))))
+        .collect();
+    while let Some(Reverse((_, b))) = heap.pop() {
+        if util::is_cancelled(cancel) {
+            break;
+        }
+        let record = heads[b].take().expect("Missing bucket record");
+        write_record(writer, &record.iter().skip(1).collect());
+        if let Some(next) = survivors[b].next() {
+            let next = next.expect("Cannot parse bucket record");
+            heap.push(Reverse((record_index(&next), b)));
+            heads[b] = Some(next);
+        }
+    }
+}
+
+/// Creates a CSV file reader for the file at the provided location.
+///
+/// # Arguments
+/// * `path` - The path to the CSV file.
+/// * `has_headers` - If the first row of the file is a header row.
+fn get_reader(path: &Path, has_headers: bool) -> csv::Reader<File> {
+    ReaderBuilder::new()
+        .has_headers(has_headers)
+        .from_path(path)
+        .expect("Cannot read CSV file")
+}
+
+/// Writes a record to a CSV writer.
+///
+/// # Panics
+/// This function will panic if the record cannot be written.
+fn write_record<W: std::io::Write>(writer: &mut Writer<W>, record: &StringRecord) {
+    writer
+        .write_record(record)
+        .expect("Error writing record to output file");
+}
+
+/// Extracts the deduplication key from a record.
+fn record_key(record: &StringRecord, key_indices: &[usize]) -> Vec<String> {
+    key_indices.iter().map(|&i| record[i].to_string()).collect()
+}
+
+/// Calculates a stable hash of a deduplication key.
+fn hash_key(key: &[String]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Creates a copy of a record that is prefixed with its row index.
+fn indexed_record(index: usize, record: &StringRecord) -> StringRecord {
+    let mut indexed = StringRecord::new();
+    indexed.push_field(&index.to_string());
+    indexed.extend(record.iter());
+    indexed
+}
+
+/// Reads the row index from a record created by [`indexed_record`].
+fn record_index(record: &StringRecord) -> usize {
+    record[0]
+        .parse()
+        .expect("Cannot parse row index of bucket record")
+}
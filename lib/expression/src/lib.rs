@@ -0,0 +1,556 @@
+//! The `expression` crate provides a small expression language that is used to compute
+//! derived column values from the columns of a CSV record.
+//!
+//! Expressions support string and number literals (`'text'`, `1.5`), column references
+//! (`amount` or `` `my-column` ``), arithmetic (`+`, `-`, `*`, `/`), comparisons
+//! (`=`, `!=`, `<`, `<=`, `>`, `>=`), value lists (`country in ('DE', 'AT')`), ranges
+//! (`amount between 1 and 10`), boolean logic (`and`, `or`, `not`) and the functions listed in
+//! [`Function`].
+extern crate chrono;
+extern crate serde;
+
+mod parser;
+
+pub use parser::{parse, tokenize, ParseError, Token};
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::de::{self, Deserialize, Deserializer};
+use serde::{Serialize, Serializer};
+use std::fmt;
+
+/// The value an expression evaluates to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Text(String),
+    Number(f64),
+    Bool(bool),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Text(t) => write!(f, "{}", t),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+/// The static type of an expression.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    Text,
+    Number,
+    Bool,
+    /// A raw CSV column value. It can be used as text, or as number where a number is expected.
+    Field,
+}
+
+impl Type {
+    fn is_text(self) -> bool {
+        self == Type::Text || self == Type::Field
+    }
+
+    fn is_number(self) -> bool {
+        self == Type::Number || self == Type::Field
+    }
+}
+
+/// Operators with one operand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOperator {
+    Negate,
+    Not,
+}
+
+/// Operators with two operands.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+}
+
+/// The built-in functions of the expression language.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    /// `concat(a, b, ...)` joins the text of all arguments.
+    Concat,
+    /// `substring(text, start[, length])` returns a part of a text (`start` is zero-based).
+    Substring,
+    /// `upper(text)` converts a text to upper case.
+    Upper,
+    /// `lower(text)` converts a text to lower case.
+    Lower,
+    /// `trim(text)` removes leading and trailing whitespace.
+    Trim,
+    /// `date_format(text, input_format, output_format)` reformats a date using `strftime` formats.
+    DateFormat,
+    /// `if(condition, then, else)` returns one of two values depending on a condition.
+    If,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Function> {
+        match name.to_lowercase().as_str() {
+            "concat" => Some(Function::Concat),
+            "substring" => Some(Function::Substring),
+            "upper" => Some(Function::Upper),
+            "lower" => Some(Function::Lower),
+            "trim" => Some(Function::Trim),
+            "date_format" => Some(Function::DateFormat),
+            "if" => Some(Function::If),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the function as it is used in expressions.
+    pub fn name(self) -> &'static str {
+        match self {
+            Function::Concat => "concat",
+            Function::Substring => "substring",
+            Function::Upper => "upper",
+            Function::Lower => "lower",
+            Function::Trim => "trim",
+            Function::DateFormat => "date_format",
+            Function::If => "if",
+        }
+    }
+
+    /// Returns the minimum and maximum (if any) number of arguments of the function.
+    fn arity(self) -> (usize, Option<usize>) {
+        match self {
+            Function::Concat => (1, None),
+            Function::Substring => (2, Some(3)),
+            Function::Upper | Function::Lower | Function::Trim => (1, Some(1)),
+            Function::DateFormat | Function::If => (3, Some(3)),
+        }
+    }
+}
+
+/// A parsed expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Literal(Value),
+    Column(String),
+    Unary(UnaryOperator, Box<Expression>),
+    Binary(BinaryOperator, Box<Expression>, Box<Expression>),
+    Function(Function, Vec<Expression>),
+}
+
+impl Expression {
+    /// Returns the names of all columns referenced by the expression.
+    pub fn columns(&self) -> Vec<&str> {
+        match self {
+            Expression::Literal(_) => vec![],
+            Expression::Column(name) => vec![name.as_str()],
+            Expression::Unary(_, operand) => operand.columns(),
+            Expression::Binary(_, left, right) => {
+                let mut columns = left.columns();
+                columns.extend(right.columns());
+                columns
+            }
+            Expression::Function(_, arguments) => {
+                arguments.iter().flat_map(|a| a.columns()).collect()
+            }
+        }
+    }
+
+    /// Determines the type of the expression and makes sure all operators and functions are
+    /// applied to operands of a suitable type.
+    ///
+    /// # Arguments
+    /// * `column_type` - Returns the type of a referenced column
+    pub fn type_check(&self, column_type: &dyn Fn(&str) -> Type) -> Result<Type, String> {
+        match self {
+            Expression::Literal(Value::Text(_)) => Ok(Type::Text),
+            Expression::Literal(Value::Number(_)) => Ok(Type::Number),
+            Expression::Literal(Value::Bool(_)) => Ok(Type::Bool),
+            Expression::Column(name) => Ok(column_type(name)),
+            Expression::Unary(UnaryOperator::Negate, operand) => {
+                expect_type(
+                    operand.type_check(column_type)?,
+                    Type::is_number,
+                    "-",
+                    "a number",
+                )?;
+                Ok(Type::Number)
+            }
+            Expression::Unary(UnaryOperator::Not, operand) => {
+                expect_type(
+                    operand.type_check(column_type)?,
+                    is_bool,
+                    "not",
+                    "a boolean",
+                )?;
+                Ok(Type::Bool)
+            }
+            Expression::Binary(operator, left, right) => {
+                let left = left.type_check(column_type)?;
+                let right = right.type_check(column_type)?;
+                type_check_binary(*operator, left, right)
+            }
+            Expression::Function(function, arguments) => {
+                if *function == Function::DateFormat {
+                    for format in &arguments[1..] {
+                        if let Expression::Literal(Value::Text(f)) = format {
+                            check_date_format(f)?;
+                        }
+                    }
+                }
+                let types = arguments
+                    .iter()
+                    .map(|a| a.type_check(column_type))
+                    .collect::<Result<Vec<Type>, String>>()?;
+                type_check_function(*function, &types)
+            }
+        }
+    }
+
+    /// Evaluates the expression.
+    ///
+    /// # Arguments
+    /// * `column_value` - Returns the value of a referenced column
+    pub fn evaluate<'a>(
+        &self,
+        column_value: &dyn Fn(&str) -> Option<&'a str>,
+    ) -> Result<Value, String> {
+        match self {
+            Expression::Literal(value) => Ok(value.clone()),
+            Expression::Column(name) => column_value(name)
+                .map(|v| Value::Text(v.to_string()))
+                .ok_or_else(|| format!("Unknown column '{}'", name)),
+            Expression::Unary(UnaryOperator::Negate, operand) => {
+                Ok(Value::Number(-to_number(&operand.evaluate(column_value)?)?))
+            }
+            Expression::Unary(UnaryOperator::Not, operand) => {
+                Ok(Value::Bool(!to_bool(&operand.evaluate(column_value)?)?))
+            }
+            Expression::Binary(BinaryOperator::And, left, right) => Ok(Value::Bool(
+                to_bool(&left.evaluate(column_value)?)? && to_bool(&right.evaluate(column_value)?)?,
+            )),
+            Expression::Binary(BinaryOperator::Or, left, right) => Ok(Value::Bool(
+                to_bool(&left.evaluate(column_value)?)? || to_bool(&right.evaluate(column_value)?)?,
+            )),
+            Expression::Binary(operator, left, right) => {
+                let left = left.evaluate(column_value)?;
+                let right = right.evaluate(column_value)?;
+                evaluate_binary(*operator, left, right)
+            }
+            Expression::Function(Function::If, arguments) => {
+                if to_bool(&arguments[0].evaluate(column_value)?)? {
+                    arguments[1].evaluate(column_value)
+                } else {
+                    arguments[2].evaluate(column_value)
+                }
+            }
+            Expression::Function(function, arguments) => {
+                let values = arguments
+                    .iter()
+                    .map(|a| a.evaluate(column_value))
+                    .collect::<Result<Vec<Value>, String>>()?;
+                evaluate_function(*function, &values)
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Expression {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let source = String::deserialize(deserializer)?;
+        parse(&source)
+            .map_err(|e| de::Error::custom(format!("invalid expression '{}': {}", source, e)))
+    }
+}
+
+impl Serialize for Expression {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl BinaryOperator {
+    /// Returns the operator as it is written in expressions.
+    fn symbol(self) -> &'static str {
+        match self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::Equal => "=",
+            BinaryOperator::NotEqual => "!=",
+            BinaryOperator::Less => "<",
+            BinaryOperator::LessEqual => "<=",
+            BinaryOperator::Greater => ">",
+            BinaryOperator::GreaterEqual => ">=",
+            BinaryOperator::And => "and",
+            BinaryOperator::Or => "or",
+        }
+    }
+}
+
+/// Formats an expression as source text that parses to the same expression. Operands of
+/// operators are parenthesized, so that no knowledge of operator precedence is required.
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expression::Literal(Value::Text(t)) => write!(f, "'{}'", t.replace('\'', "''")),
+            Expression::Literal(value) => write!(f, "{}", value),
+            Expression::Column(name) if is_plain_identifier(name) => write!(f, "{}", name),
+            Expression::Column(name) => write!(f, "`{}`", name),
+            Expression::Unary(UnaryOperator::Negate, operand) => {
+                write!(f, "-{}", Operand(operand))
+            }
+            Expression::Unary(UnaryOperator::Not, operand) => {
+                write!(f, "not {}", Operand(operand))
+            }
+            Expression::Binary(operator, left, right) => write!(
+                f,
+                "{} {} {}",
+                Operand(left),
+                operator.symbol(),
+                Operand(right)
+            ),
+            Expression::Function(function, arguments) => {
+                write!(f, "{}(", function.name())?;
+                for (index, argument) in arguments.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", argument)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// The operand of an operator, which is parenthesized if it is an operation itself.
+struct Operand<'a>(&'a Expression);
+
+impl fmt::Display for Operand<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Expression::Unary(..) | Expression::Binary(..) => write!(f, "({})", self.0),
+            expression => write!(f, "{}", expression),
+        }
+    }
+}
+
+/// Checks if a column name can be written without backticks.
+fn is_plain_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    let valid = match chars.next() {
+        Some(c) => {
+            (c.is_alphabetic() || c == '_') && chars.all(|c| c.is_alphanumeric() || c == '_')
+        }
+        None => false,
+    };
+    let is_keyword = ["and", "or", "not", "true", "false"].contains(&name.to_lowercase().as_str());
+    valid && !is_keyword
+}
+
+/// Makes sure a date format only contains valid `strftime` specifiers.
+fn check_date_format(format: &str) -> Result<(), String> {
+    if StrftimeItems::new(format).any(|i| i == Item::Error) {
+        Err(format!("Invalid date format '{}'", format))
+    } else {
+        Ok(())
+    }
+}
+
+fn is_bool(t: Type) -> bool {
+    t == Type::Bool
+}
+
+/// Makes sure the type of an operand satisfies a predicate.
+fn expect_type(
+    actual: Type,
+    predicate: fn(Type) -> bool,
+    operation: &str,
+    expected: &str,
+) -> Result<Type, String> {
+    if predicate(actual) {
+        Ok(actual)
+    } else {
+        Err(format!(
+            "'{}' expects {} but got {:?}",
+            operation, expected, actual
+        ))
+    }
+}
+
+fn type_check_binary(operator: BinaryOperator, left: Type, right: Type) -> Result<Type, String> {
+    use BinaryOperator::*;
+    match operator {
+        Add | Subtract | Multiply | Divide => {
+            let name = format!("{:?}", operator).to_lowercase();
+            expect_type(left, Type::is_number, &name, "numbers")?;
+            expect_type(right, Type::is_number, &name, "numbers")?;
+            Ok(Type::Number)
+        }
+        And | Or => {
+            let name = format!("{:?}", operator).to_lowercase();
+            expect_type(left, is_bool, &name, "booleans")?;
+            expect_type(right, is_bool, &name, "booleans")?;
+            Ok(Type::Bool)
+        }
+        Equal | NotEqual | Less | LessEqual | Greater | GreaterEqual => {
+            let comparable = (left.is_number() && right.is_number())
+                || (left.is_text() && right.is_text())
+                || (left == Type::Bool && right == Type::Bool);
+            if comparable {
+                Ok(Type::Bool)
+            } else {
+                Err(format!("Cannot compare {:?} with {:?}", left, right))
+            }
+        }
+    }
+}
+
+fn type_check_function(function: Function, types: &[Type]) -> Result<Type, String> {
+    let name = function.name();
+    match function {
+        Function::Concat => {
+            for &t in types {
+                expect_type(t, |t| t != Type::Bool, name, "texts or numbers")?;
+            }
+            Ok(Type::Text)
+        }
+        Function::Substring => {
+            expect_type(types[0], Type::is_text, name, "a text")?;
+            for &t in &types[1..] {
+                expect_type(t, Type::is_number, name, "numeric positions")?;
+            }
+            Ok(Type::Text)
+        }
+        Function::Upper | Function::Lower | Function::Trim => {
+            expect_type(types[0], Type::is_text, name, "a text")?;
+            Ok(Type::Text)
+        }
+        Function::DateFormat => {
+            for &t in types {
+                expect_type(t, Type::is_text, name, "texts")?;
+            }
+            Ok(Type::Text)
+        }
+        Function::If => {
+            expect_type(types[0], is_bool, name, "a boolean condition")?;
+            match (types[1], types[2]) {
+                (a, b) if a == b => Ok(a),
+                (a, b) if a.is_text() && b.is_text() => Ok(Type::Text),
+                (a, b) if a.is_number() && b.is_number() => Ok(Type::Number),
+                (a, b) => Err(format!(
+                    "'if' branches have different types {:?} and {:?}",
+                    a, b
+                )),
+            }
+        }
+    }
+}
+
+fn evaluate_binary(operator: BinaryOperator, left: Value, right: Value) -> Result<Value, String> {
+    use BinaryOperator::*;
+    match operator {
+        Add => Ok(Value::Number(to_number(&left)? + to_number(&right)?)),
+        Subtract => Ok(Value::Number(to_number(&left)? - to_number(&right)?)),
+        Multiply => Ok(Value::Number(to_number(&left)? * to_number(&right)?)),
+        Divide => Ok(Value::Number(to_number(&left)? / to_number(&right)?)),
+        Equal | NotEqual | Less | LessEqual | Greater | GreaterEqual => {
+            // Comparing a number with a value that is not a number (e.g. an empty field) is
+            // false, so that such rows never match a numeric condition
+            let ordering = match (&left, &right) {
+                (Value::Number(_), _) | (_, Value::Number(_)) => {
+                    match (to_number(&left), to_number(&right)) {
+                        (Ok(left), Ok(right)) => left.partial_cmp(&right),
+                        _ => None,
+                    }
+                }
+                (Value::Bool(a), Value::Bool(b)) => a.partial_cmp(b),
+                _ => left.to_string().partial_cmp(&right.to_string()),
+            };
+            let ordering = match ordering {
+                Some(ordering) => ordering,
+                None => return Ok(Value::Bool(false)),
+            };
+            Ok(Value::Bool(match operator {
+                Equal => ordering.is_eq(),
+                NotEqual => ordering.is_ne(),
+                Less => ordering.is_lt(),
+                LessEqual => ordering.is_le(),
+                Greater => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            }))
+        }
+        And | Or => unreachable!("boolean operators are evaluated lazily"),
+    }
+}
+
+fn evaluate_function(function: Function, values: &[Value]) -> Result<Value, String> {
+    match function {
+        Function::Concat => Ok(Value::Text(values.iter().map(|v| v.to_string()).collect())),
+        Function::Substring => {
+            let text = values[0].to_string();
+            let start = to_number(&values[1])?.max(0.0) as usize;
+            let chars = text.chars().skip(start);
+            Ok(Value::Text(match values.get(2) {
+                Some(length) => chars.take(to_number(length)?.max(0.0) as usize).collect(),
+                None => chars.collect(),
+            }))
+        }
+        Function::Upper => Ok(Value::Text(values[0].to_string().to_uppercase())),
+        Function::Lower => Ok(Value::Text(values[0].to_string().to_lowercase())),
+        Function::Trim => Ok(Value::Text(values[0].to_string().trim().to_string())),
+        Function::DateFormat => {
+            let (text, input_format) = (values[0].to_string(), values[1].to_string());
+            let output_format = values[2].to_string();
+            if text.is_empty() {
+                return Ok(Value::Text(text));
+            }
+            check_date_format(&input_format)?;
+            check_date_format(&output_format)?;
+            let formatted = match NaiveDateTime::parse_from_str(&text, &input_format) {
+                Ok(date_time) => date_time.format(&output_format).to_string(),
+                Err(_) => NaiveDate::parse_from_str(&text, &input_format)
+                    .map_err(|_| {
+                        format!(
+                            "Cannot parse date '{}' with format '{}'",
+                            text, input_format
+                        )
+                    })?
+                    .format(&output_format)
+                    .to_string(),
+            };
+            Ok(Value::Text(formatted))
+        }
+        Function::If => unreachable!("'if' is evaluated lazily"),
+    }
+}
+
+fn to_number(value: &Value) -> Result<f64, String> {
+    match value {
+        Value::Number(n) => Ok(*n),
+        Value::Text(t) => t
+            .trim()
+            .parse()
+            .map_err(|_| format!("Value '{}' is not a number", t)),
+        Value::Bool(b) => Err(format!("Value '{}' is not a number", b)),
+    }
+}
+
+fn to_bool(value: &Value) -> Result<bool, String> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        other => Err(format!("Value '{}' is not a boolean", other)),
+    }
+}
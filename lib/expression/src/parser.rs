@@ -0,0 +1,479 @@
+use crate::{BinaryOperator, Expression, Function, UnaryOperator, Value};
+use std::fmt;
+
+/// An error that occurred while parsing an expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    /// The character offset of the offending token in the parsed source.
+    pub position: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+/// A token of the expression language.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Number(f64),
+    Text(String),
+    Identifier(String),
+    QuotedIdentifier(String),
+    LeftParen,
+    RightParen,
+    Comma,
+    Colon,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Text(t) => write!(f, "'{}'", t),
+            Token::Identifier(i) => write!(f, "{}", i),
+            Token::QuotedIdentifier(i) => write!(f, "`{}`", i),
+            Token::LeftParen => write!(f, "("),
+            Token::RightParen => write!(f, ")"),
+            Token::Comma => write!(f, ","),
+            Token::Colon => write!(f, ":"),
+            Token::Plus => write!(f, "+"),
+            Token::Minus => write!(f, "-"),
+            Token::Star => write!(f, "*"),
+            Token::Slash => write!(f, "/"),
+            Token::Equal => write!(f, "="),
+            Token::NotEqual => write!(f, "!="),
+            Token::Less => write!(f, "<"),
+            Token::LessEqual => write!(f, "<="),
+            Token::Greater => write!(f, ">"),
+            Token::GreaterEqual => write!(f, ">="),
+        }
+    }
+}
+
+/// Splits an expression into tokens. Every token is returned along with its character offset.
+///
+/// # Arguments
+/// * `source` - The expression source
+pub fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while pos < chars.len() {
+        let c = chars[pos];
+        let start = pos;
+
+        if c.is_whitespace() {
+            pos += 1;
+            continue;
+        }
+
+        let token = match c {
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '/' => Token::Slash,
+            '=' => {
+                if chars.get(pos + 1) == Some(&'=') {
+                    pos += 1;
+                }
+                Token::Equal
+            }
+            '!' if chars.get(pos + 1) == Some(&'=') => {
+                pos += 1;
+                Token::NotEqual
+            }
+            '<' => match chars.get(pos + 1) {
+                Some('=') => {
+                    pos += 1;
+                    Token::LessEqual
+                }
+                Some('>') => {
+                    pos += 1;
+                    Token::NotEqual
+                }
+                _ => Token::Less,
+            },
+            '>' => {
+                if chars.get(pos + 1) == Some(&'=') {
+                    pos += 1;
+                    Token::GreaterEqual
+                } else {
+                    Token::Greater
+                }
+            }
+            '\'' | '"' => {
+                // Strings are terminated by their opening quote. Doubling the quote escapes it.
+                let mut text = String::new();
+                loop {
+                    pos += 1;
+                    match chars.get(pos) {
+                        None => return Err(error("Unterminated string", start)),
+                        Some(&q) if q == c && chars.get(pos + 1) == Some(&c) => {
+                            text.push(c);
+                            pos += 1;
+                        }
+                        Some(&q) if q == c => break,
+                        Some(&other) => text.push(other),
+                    }
+                }
+                Token::Text(text)
+            }
+            '`' => {
+                // Backticks quote column names that are no valid identifiers (e.g. "my-column").
+                let mut name = String::new();
+                loop {
+                    pos += 1;
+                    match chars.get(pos) {
+                        None => return Err(error("Unterminated column name", start)),
+                        Some('`') => break,
+                        Some(&other) => name.push(other),
+                    }
+                }
+                Token::QuotedIdentifier(name)
+            }
+            _ if c.is_ascii_digit() || c == '.' => {
+                while pos + 1 < chars.len()
+                    && (chars[pos + 1].is_ascii_digit() || chars[pos + 1] == '.')
+                {
+                    pos += 1;
+                }
+                let number: String = chars[start..=pos].iter().collect();
+                let value = number
+                    .parse()
+                    .map_err(|_| error(&format!("Invalid number '{}'", number), start))?;
+                Token::Number(value)
+            }
+            _ if c.is_alphabetic() || c == '_' => {
+                while pos + 1 < chars.len()
+                    && (chars[pos + 1].is_alphanumeric() || chars[pos + 1] == '_')
+                {
+                    pos += 1;
+                }
+                Token::Identifier(chars[start..=pos].iter().collect())
+            }
+            _ => return Err(error(&format!("Unexpected character '{}'", c), start)),
+        };
+
+        tokens.push((token, start));
+        pos += 1;
+    }
+
+    Ok(tokens)
+}
+
+/// Parses an expression.
+///
+/// # Arguments
+/// * `source` - The expression source
+pub fn parse(source: &str) -> Result<Expression, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        pos: 0,
+        end: source.chars().count(),
+    };
+
+    let expression = parser.parse_or()?;
+    match parser.tokens.get(parser.pos) {
+        Some((token, position)) => Err(error(&format!("Unexpected token '{}'", token), *position)),
+        None => Ok(expression),
+    }
+}
+
+/// A recursive descent parser. Each `parse_*` function parses one level of operator precedence,
+/// starting with the lowest.
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn parse_or(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.parse_and()?;
+        while self.next_is_keyword("or") {
+            self.pos += 1;
+            let right = self.parse_and()?;
+            left = Expression::Binary(BinaryOperator::Or, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.parse_not()?;
+        while self.next_is_keyword("and") {
+            self.pos += 1;
+            let right = self.parse_not()?;
+            left = Expression::Binary(BinaryOperator::And, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Expression, ParseError> {
+        if self.next_is_keyword("not") {
+            self.pos += 1;
+            let operand = self.parse_not()?;
+            return Ok(Expression::Unary(UnaryOperator::Not, Box::new(operand)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expression, ParseError> {
+        let left = self.parse_additive()?;
+
+        // `in` and `between` are rewritten to comparisons, e.g. `a in (1, 2)` to
+        // `a = 1 or a = 2` and `a between 1 and 2` to `a >= 1 and a <= 2`
+        let negated = self.keyword_at(0, "not")
+            && (self.keyword_at(1, "in") || self.keyword_at(1, "between"));
+        if negated {
+            self.pos += 1;
+        }
+        if self.keyword_at(0, "in") || self.keyword_at(0, "between") {
+            let keyword_is_in = self.keyword_at(0, "in");
+            self.pos += 1;
+            let expression = match keyword_is_in {
+                true => self.parse_in(left)?,
+                false => self.parse_between(left)?,
+            };
+            return Ok(match negated {
+                true => Expression::Unary(UnaryOperator::Not, Box::new(expression)),
+                false => expression,
+            });
+        }
+
+        let operator = match self.peek() {
+            Some(Token::Equal) => BinaryOperator::Equal,
+            Some(Token::NotEqual) => BinaryOperator::NotEqual,
+            Some(Token::Less) => BinaryOperator::Less,
+            Some(Token::LessEqual) => BinaryOperator::LessEqual,
+            Some(Token::Greater) => BinaryOperator::Greater,
+            Some(Token::GreaterEqual) => BinaryOperator::GreaterEqual,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        let right = self.parse_additive()?;
+        Ok(Expression::Binary(
+            operator,
+            Box::new(left),
+            Box::new(right),
+        ))
+    }
+
+    /// Parses the list of values following `in`. The keyword is already consumed.
+    fn parse_in(&mut self, left: Expression) -> Result<Expression, ParseError> {
+        self.expect(Token::LeftParen)?;
+        let mut expression: Option<Expression> = None;
+        loop {
+            let right = self.parse_additive()?;
+            let comparison = Expression::Binary(
+                BinaryOperator::Equal,
+                Box::new(left.clone()),
+                Box::new(right),
+            );
+            expression = Some(match expression {
+                Some(e) => {
+                    Expression::Binary(BinaryOperator::Or, Box::new(e), Box::new(comparison))
+                }
+                None => comparison,
+            });
+            match self.peek() {
+                Some(Token::Comma) => self.pos += 1,
+                _ => {
+                    self.expect(Token::RightParen)?;
+                    return Ok(expression.unwrap());
+                }
+            }
+        }
+    }
+
+    /// Parses the bounds following `between`. The keyword is already consumed.
+    fn parse_between(&mut self, left: Expression) -> Result<Expression, ParseError> {
+        let low = self.parse_additive()?;
+        if !self.keyword_at(0, "and") {
+            return Err(match self.tokens.get(self.pos) {
+                Some((token, position)) => {
+                    error(&format!("Expected 'and' but found '{}'", token), *position)
+                }
+                None => error("Expected 'and'", self.end),
+            });
+        }
+        self.pos += 1;
+        let high = self.parse_additive()?;
+        Ok(Expression::Binary(
+            BinaryOperator::And,
+            Box::new(Expression::Binary(
+                BinaryOperator::GreaterEqual,
+                Box::new(left.clone()),
+                Box::new(low),
+            )),
+            Box::new(Expression::Binary(
+                BinaryOperator::LessEqual,
+                Box::new(left),
+                Box::new(high),
+            )),
+        ))
+    }
+
+    fn parse_additive(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.parse_multiplicative()?;
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Plus) => BinaryOperator::Add,
+                Some(Token::Minus) => BinaryOperator::Subtract,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.parse_multiplicative()?;
+            left = Expression::Binary(operator, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn parse_multiplicative(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.parse_unary()?;
+        loop {
+            let operator = match self.peek() {
+                Some(Token::Star) => BinaryOperator::Multiply,
+                Some(Token::Slash) => BinaryOperator::Divide,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.parse_unary()?;
+            left = Expression::Binary(operator, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, ParseError> {
+        if self.peek() == Some(&Token::Minus) {
+            self.pos += 1;
+            let operand = self.parse_unary()?;
+            return Ok(Expression::Unary(UnaryOperator::Negate, Box::new(operand)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression, ParseError> {
+        let (token, position) = match self.tokens.get(self.pos) {
+            Some((token, position)) => (token.clone(), *position),
+            None => return Err(error("Unexpected end of expression", self.end)),
+        };
+        self.pos += 1;
+
+        match token {
+            Token::Number(n) => Ok(Expression::Literal(Value::Number(n))),
+            Token::Text(t) => Ok(Expression::Literal(Value::Text(t))),
+            Token::QuotedIdentifier(name) => Ok(Expression::Column(name)),
+            Token::LeftParen => {
+                let expression = self.parse_or()?;
+                self.expect(Token::RightParen)?;
+                Ok(expression)
+            }
+            Token::Identifier(name) => {
+                if self.peek() == Some(&Token::LeftParen) {
+                    self.pos += 1;
+                    return self.parse_function(&name, position);
+                }
+                match name.to_lowercase().as_str() {
+                    "true" => Ok(Expression::Literal(Value::Bool(true))),
+                    "false" => Ok(Expression::Literal(Value::Bool(false))),
+                    _ => Ok(Expression::Column(name)),
+                }
+            }
+            other => Err(error(&format!("Unexpected token '{}'", other), position)),
+        }
+    }
+
+    /// Parses the arguments of a function call. The opening parenthesis is already consumed.
+    fn parse_function(&mut self, name: &str, position: usize) -> Result<Expression, ParseError> {
+        let function = Function::from_name(name)
+            .ok_or_else(|| error(&format!("Unknown function '{}'", name), position))?;
+
+        let mut arguments = Vec::new();
+        if self.peek() == Some(&Token::RightParen) {
+            self.pos += 1;
+        } else {
+            loop {
+                arguments.push(self.parse_or()?);
+                match self.peek() {
+                    Some(Token::Comma) => self.pos += 1,
+                    _ => {
+                        self.expect(Token::RightParen)?;
+                        break;
+                    }
+                }
+            }
+        }
+
+        let (min, max) = function.arity();
+        if arguments.len() < min || max.is_some_and(|m| arguments.len() > m) {
+            return Err(error(
+                &format!(
+                    "Wrong number of arguments for function '{}'",
+                    function.name()
+                ),
+                position,
+            ));
+        }
+
+        Ok(Expression::Function(function, arguments))
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    /// Checks if the token at the given offset from the current one is the given keyword.
+    fn keyword_at(&self, offset: usize, keyword: &str) -> bool {
+        match self.tokens.get(self.pos + offset) {
+            Some((Token::Identifier(i), _)) => i.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+
+    fn next_is_keyword(&self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Identifier(i)) => {
+                i.eq_ignore_ascii_case(keyword)
+                    && self.tokens.get(self.pos + 1).map(|(t, _)| t) != Some(&Token::LeftParen)
+            }
+            _ => false,
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ParseError> {
+        match self.tokens.get(self.pos) {
+            Some((token, _)) if *token == expected => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some((token, position)) => Err(error(
+                &format!("Expected '{}' but found '{}'", expected, token),
+                *position,
+            )),
+            None => Err(error(&format!("Expected '{}'", expected), self.end)),
+        }
+    }
+}
+
+fn error(message: &str, position: usize) -> ParseError {
+    ParseError {
+        message: message.to_string(),
+        position,
+    }
+}
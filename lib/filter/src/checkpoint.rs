@@ -0,0 +1,213 @@
+use crate::FilterStats;
+use csv::Position;
+use csv_filter_config::FilterConfig;
+use hashbrown::HashMap;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The number of bytes at the start of the input file and before a position in it that are
+/// fingerprinted to detect changed input files.
+const FINGERPRINT_WINDOW: u64 = 64 * 1024;
+
+/// Settings that control how the progress of the filter stage is persisted.
+#[derive(Debug)]
+pub struct CheckpointOptions {
+    /// Path of the file checkpoints are written to
+    pub path: PathBuf,
+    /// The number of input rows between two checkpoints
+    pub interval: usize,
+    /// The checkpoint to continue from. Output files are truncated to the lengths it records.
+    pub resume_from: Option<Checkpoint>,
+}
+
+/// The progress of the filter stage: all input rows up to the recorded position have been
+/// durably written to every output file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Path of the input file
+    pub input: String,
+    /// The size of the input file, which makes sure it has not been truncated in the meantime.
+    /// Inputs may grow, since they may be appended to.
+    pub input_bytes: u64,
+    /// SHA-256 hash of the first bytes of the input file and the bytes before the recorded
+    /// position, which makes sure the rows read so far have not been changed in the meantime
+    pub input_sha256: String,
+    /// The byte offset in the input file to continue reading from
+    pub byte: u64,
+    /// The line number in the input file to continue reading from
+    pub line: u64,
+    /// The number of records (including the header row) read so far
+    pub record: u64,
+    /// Maps a filename to the length of its output file
+    pub output_lengths: HashMap<String, u64>,
+    /// The record counts up to the checkpoint
+    pub stats: FilterStats,
+}
+
+impl Checkpoint {
+    /// Reads a checkpoint file. Returns `None` if there is no checkpoint.
+    ///
+    /// # Panics
+    /// This function will panic if the checkpoint file cannot be parsed.
+    pub fn read(path: &Path) -> Option<Checkpoint> {
+        let json = fs::read_to_string(path).ok()?;
+        Some(
+            serde_json::from_str(&json).unwrap_or_else(|e| {
+                panic!("Cannot parse checkpoint file '{}': {}", path.display(), e)
+            }),
+        )
+    }
+
+    /// Writes the checkpoint to a temporary file first, which then atomically replaces the
+    /// previous checkpoint.
+    ///
+    /// # Panics
+    /// This function will panic if the checkpoint file cannot be written.
+    fn write(&self, path: &Path) {
+        let json = serde_json::to_string_pretty(self).expect("Cannot serialize checkpoint");
+        let temp_path = PathBuf::from(format!("{}.tmp", path.display()));
+        fs::write(&temp_path, json + "\n")
+            .and_then(|_| File::open(&temp_path)?.sync_all())
+            .and_then(|_| fs::rename(&temp_path, path))
+            .unwrap_or_else(|e| panic!("Cannot write checkpoint file '{}': {}", path.display(), e));
+    }
+
+    /// Returns the position in the input file to continue reading from.
+    pub fn position(&self) -> Position {
+        let mut position = Position::new();
+        position
+            .set_byte(self.byte)
+            .set_line(self.line)
+            .set_record(self.record);
+        position
+    }
+
+    /// Makes sure the checkpoint was created for the same input file and configuration.
+    ///
+    /// # Panics
+    /// This function will panic if the input file or the output files differ, or if an output
+    /// file is aggregated.
+    pub fn check_matches(&self, csv_file_path: &str, all_filter_configs: &[Arc<FilterConfig>]) {
+        let same_input = self.input == csv_file_path
+            && input_size(csv_file_path) >= self.input_bytes
+            && input_fingerprint(csv_file_path, self.byte).ok().as_ref()
+                == Some(&self.input_sha256);
+        if !same_input {
+            panic!(
+                "Cannot resume from checkpoint, because it was created for input file '{}' with {} bytes",
+                self.input, self.input_bytes
+            );
+        }
+        let same_outputs = self.output_lengths.len() == all_filter_configs.len()
+            && all_filter_configs
+                .iter()
+                .all(|c| self.output_lengths.contains_key(&c.output));
+        if !same_outputs {
+            panic!("Cannot resume from checkpoint, because it was created for other output files");
+        }
+        if let Some(config) = all_filter_configs.iter().find(|c| c.aggregate.is_some()) {
+            panic!(
+                "Cannot resume from checkpoint, because output file '{}' is aggregated",
+                config.output
+            );
+        }
+    }
+}
+
+/// Returns the size of the input file, or zero if it cannot be determined.
+fn input_size(csv_file_path: &str) -> u64 {
+    fs::metadata(csv_file_path).map(|m| m.len()).unwrap_or(0)
+}
+
+/// Hashes the first bytes of the input file and the bytes before the given offset. Rewriting
+/// the input file changes either of them, unless the new rows are the same as the old ones.
+///
+/// # Arguments
+/// * `csv_file_path` - Path to the input file
+/// * `end` - The byte offset in the input file up to which it is fingerprinted
+pub fn input_fingerprint(csv_file_path: &str, end: u64) -> io::Result<String> {
+    let mut file = File::open(csv_file_path)?;
+    let mut hasher = Sha256::new();
+    let head_end = end.min(FINGERPRINT_WINDOW);
+    let tail_start = end.saturating_sub(FINGERPRINT_WINDOW).max(head_end);
+    for (start, end) in [(0, head_end), (tail_start, end)] {
+        let mut buffer = vec![0; (end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut buffer)?;
+        hasher.update(&buffer);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Checks if checkpoints can be written for the given configuration. Aggregates are only held
+/// in memory until all rows have been read, which is why they cannot be checkpointed.
+pub(crate) fn is_supported(all_filter_configs: &[Arc<FilterConfig>]) -> bool {
+    match all_filter_configs.iter().find(|c| c.aggregate.is_some()) {
+        Some(config) => {
+            warn!(
+                "Checkpoints are disabled, because output file '{}' is aggregated",
+                config.output
+            );
+            false
+        }
+        None => true,
+    }
+}
+
+/// Opens an output file of a resumed run. The file is truncated to the length recorded in the
+/// checkpoint, so that rows written after the checkpoint are discarded.
+///
+/// # Panics
+/// This function will panic if the output file cannot be opened.
+pub(crate) fn reopen_output_file(path: &Path, length: u64) -> File {
+    OpenOptions::new()
+        .write(true)
+        .open(path)
+        .and_then(|mut f| {
+            f.set_len(length)?;
+            f.seek(SeekFrom::End(0))?;
+            Ok(f)
+        })
+        .unwrap_or_else(|e| panic!("Cannot reopen output file '{}': {}", path.display(), e))
+}
+
+/// Persists a checkpoint at the given position. All rows up to the position must have been made
+/// durable before.
+///
+/// # Arguments
+/// * `options` - Settings that control how checkpoints are written
+/// * `csv_file_path` - Path of the input file
+/// * `output_lengths` - Maps a filename to the length of its output file
+/// * `position` - The position in the input file after the last processed record
+/// * `stats` - The record counts up to the position
+///
+/// # Panics
+/// This function will panic if the checkpoint cannot be written.
+pub(crate) fn write_checkpoint(
+    options: &CheckpointOptions,
+    csv_file_path: &str,
+    output_lengths: HashMap<String, u64>,
+    position: &Position,
+    stats: FilterStats,
+) {
+    let rows_read = stats.rows_read;
+    Checkpoint {
+        input: csv_file_path.to_string(),
+        input_bytes: input_size(csv_file_path),
+        input_sha256: input_fingerprint(csv_file_path, position.byte())
+            .unwrap_or_else(|e| panic!("Cannot fingerprint input file '{}': {}", csv_file_path, e)),
+        byte: position.byte(),
+        line: position.line(),
+        record: position.record(),
+        output_lengths,
+        stats,
+    }
+    .write(&options.path);
+    info!("Checkpoint written after {} rows", rows_read);
+}
@@ -4,302 +4,1094 @@
 
 extern crate crossbeam;
 extern crate csv;
+extern crate csv_filter_aggregate as aggregate;
 extern crate csv_filter_config;
+extern crate csv_filter_script as script;
+extern crate csv_filter_transform as transform;
 extern crate csv_filter_util as util;
 extern crate hashbrown;
+extern crate log;
+extern crate memmap2;
+extern crate serde;
+extern crate serde_json;
 
-use core::sync::atomic::{AtomicUsize, Ordering};
-use csv_filter_config::FilterConfig;
+mod checkpoint;
+mod lookup;
+mod predicate;
+mod sink;
+
+pub use checkpoint::{input_fingerprint, Checkpoint, CheckpointOptions};
+pub use predicate::{ColumnFilterPredicate, Predicate, RangePredicate, ValuePredicate};
+pub use sink::{FileSink, RecordSink};
+
+use aggregate::Aggregates;
+use csv_filter_config::{FilterConfig, JoinType};
+use std::borrow::Cow;
 use std::fs;
 use std::fs::File;
+use std::io::{Cursor, Read, Seek};
+use std::mem;
+use std::panic;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::sync::Mutex;
-use std::thread;
+use transform::{OutputTransforms, Transforms};
+use util::progress::Progress;
 
 use crossbeam::channel::bounded as bounded_channel;
-use csv::{Reader, ReaderBuilder, StringRecord};
+use csv::{Position, Reader, ReaderBuilder, StringRecord};
 use hashbrown::HashMap;
+use log::{debug, info, warn};
+use lookup::LookupTables;
+use memmap2::Mmap;
+use script::{Interpreter, Script};
+use serde::{Deserialize, Serialize};
 
 // These type definitions are only here for abbreviation
-type OutputFileMap = Arc<HashMap<String, Mutex<csv::Writer<File>>>>;
 type HeadersMap = Arc<HashMap<String, usize>>;
+type AggregatesMap = HashMap<String, Aggregates>;
+type ConfigHeadersMap = Arc<HashMap<String, HeadersMap>>;
+type ScriptMap = HashMap<String, Script>;
+
+/// File extensions of compressed inputs, which cannot be parsed from a memory-mapped region.
+const COMPRESSED_FILE_EXTENSIONS: [&str; 6] = ["gz", "bz2", "xz", "zst", "zip", "lz4"];
+
+/// Settings that control how the filter stage reads the input file.
+#[derive(Debug, Default)]
+pub struct FilterOptions<'a> {
+    /// The maximum number of threads to use
+    pub max_threads: usize,
+    /// If the input file should be memory-mapped instead of being read through a buffered
+    /// reader. Inputs that cannot be mapped are silently read the buffered way.
+    pub use_mmap: bool,
+    /// If output files will be sorted afterwards. Only then the original values of transformed
+    /// sort columns are written to hidden columns.
+    pub sort_enabled: bool,
+    /// If present, checkpoints are written periodically and processing continues from the
+    /// checkpoint to resume from. No header rows are written then, so the sink must continue
+    /// the outputs of the interrupted run, e.g. [`FileSink::resume`]. Checkpoints are not written
+    /// if any output file is aggregated.
+    pub checkpoints: Option<&'a CheckpointOptions>,
+    /// The position in the input file to start reading from. Reading starts after the header
+    /// row if absent.
+    pub start: Option<Position>,
+    /// The byte offset in the input file that records must end at or before to be read. All
+    /// records are read if absent.
+    pub end: Option<u64>,
+    /// Once set, reading stops and no further output is written. Output files are incomplete
+    /// then.
+    pub cancel: Option<&'a AtomicBool>,
+}
 
 /// Processes a CSV file according to the provided configuration.
 ///
 /// # Arguments
 /// * `csv_file_path` - Path to the CSV file that should be processed
 /// * `all_filter_configs` - A vector containing all configuration items
-/// * `output_dir_path` - Path to the directory that data should be written to
-/// * `max_threads` - The maximum number of threads to use
+/// * `predicate` - Decides which records match a configuration item, e.g.
+///   [`ColumnFilterPredicate`]
+/// * `sink` - Receives the rows of all output files, e.g. a [`FileSink`]
+/// * `options` - Settings that control how the input file is read
 ///
 /// # Panics
 /// This function will panic on any error.
-pub fn filter(
+pub fn filter<P: Predicate, S: RecordSink>(
     csv_file_path: &str,
-    all_filter_configs: &Vec<Arc<FilterConfig>>,
-    output_dir_path: &str,
-    max_threads: usize,
-) {
-    let output_files = create_output_files(all_filter_configs, output_dir_path);
-    write_headers_to_output_files(all_filter_configs, &output_files);
-    process_csv(
-        &output_files,
-        all_filter_configs,
+    all_filter_configs: &[Arc<FilterConfig>],
+    predicate: &P,
+    sink: &mut S,
+    options: &FilterOptions,
+) -> FilterStats {
+    let sort_enabled = options.sort_enabled;
+    let resume_from = options.checkpoints.and_then(|c| c.resume_from.as_ref());
+    if let Some(checkpoint) = resume_from {
+        checkpoint.check_matches(csv_file_path, all_filter_configs);
+        info!(
+            "Resuming after {} rows from checkpoint",
+            checkpoint.stats.rows_read
+        );
+    }
+    let checkpoints = options
+        .checkpoints
+        .filter(|_| checkpoint::is_supported(all_filter_configs));
+
+    let transforms = Arc::new(Transforms::new(all_filter_configs, sort_enabled));
+    let lookup_tables = lookup::load_lookup_tables(all_filter_configs);
+    let scripts = load_scripts(all_filter_configs);
+    sink.open(all_filter_configs);
+    if resume_from.is_none() {
+        write_headers(all_filter_configs, &*sink, sort_enabled);
+    }
+    let shared_sink = &*sink;
+
+    // Rows of earlier runs are only counted in the checkpoint to resume from
+    let mut stats = resume_from.map(|c| c.stats.clone()).unwrap_or_default();
+    let mut on_segment_end = |_: &ConfigHeadersMap, states: &[ThreadState], position: &Position| {
+        let mut checkpoint_stats = stats.clone();
+        for state in states {
+            checkpoint_stats.merge(state.stats.clone());
+        }
+        transforms.save();
+        match shared_sink.checkpoint() {
+            Some(output_lengths) => checkpoint::write_checkpoint(
+                checkpoints.unwrap(),
+                csv_file_path,
+                output_lengths.into_iter().collect(),
+                position,
+                checkpoint_stats,
+            ),
+            None => debug!("Skipping checkpoint, since the output does not support them"),
+        }
+    };
+    let segments = Segments {
+        start: resume_from
+            .map(Checkpoint::position)
+            .or_else(|| options.start.clone()),
+        end: options.end,
+        cancel: options.cancel,
+        size: checkpoints.map(|c| c.interval).unwrap_or(0),
+        on_segment_end: &mut on_segment_end,
+    };
+
+    let (_, thread_results, end_position) = read_records_in_segments(
         csv_file_path,
-        max_threads,
+        options.use_mmap,
+        options.max_threads,
+        segments,
+        |headers| {
+            let config_headers = create_config_headers_map(
+                create_headers_map(headers),
+                headers.len(),
+                all_filter_configs,
+            );
+            check_aggregate_columns(&config_headers, all_filter_configs);
+            config_headers
+        },
+        |config_headers, state: &mut ThreadState, csv_record| {
+            process_csv_record(
+                csv_record,
+                all_filter_configs,
+                predicate,
+                shared_sink,
+                config_headers,
+                &transforms,
+                &lookup_tables,
+                &scripts,
+                state,
+            );
+        },
     );
+
+    if options.cancel.is_some_and(|c| c.load(Ordering::Relaxed)) {
+        info!("Filter stage cancelled");
+        sink.finish();
+        return stats;
+    }
+
+    // Every thread aggregates and counts records on its own, so that no locking is required.
+    // The partial aggregates and counts of all threads are merged at the end.
+    let mut aggregates = AggregatesMap::new();
+    for state in thread_results {
+        for (output, partial) in state.aggregates {
+            aggregates
+                .entry(output)
+                .or_insert_with(Aggregates::new)
+                .merge(partial);
+        }
+        stats.merge(state.stats);
+    }
+
+    write_aggregates(aggregates, all_filter_configs, &*sink);
+    sink.finish();
+    transforms.save();
+
+    // Output files are rewritten from here on, which is why their rows cannot be resumed anymore
+    if let Some(checkpoints) = checkpoints {
+        let _ = fs::remove_file(&checkpoints.path);
+    }
+    stats.end_position = Some(end_position);
+    stats
 }
 
-/// Processes the CSV file.
+/// The state of a thread of the filter stage.
+#[derive(Default)]
+struct ThreadState {
+    /// Maps a filename to the partial aggregates of the thread
+    aggregates: AggregatesMap,
+    /// The record counts of the thread
+    stats: FilterStats,
+    /// The interpreter running the scripts of all filter configurations on this thread
+    interpreter: Interpreter,
+}
+
+/// Counts the records processed in the filter stage.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct FilterStats {
+    /// The number of rows read from the input file
+    pub rows_read: usize,
+    /// The number of rows that did not match any filter configuration
+    pub rows_rejected: usize,
+    /// Maps a filename to the number of rows that matched its filter configuration
+    pub matched_rows: HashMap<String, usize>,
+    /// The position in the input file after the last record that was read
+    #[serde(skip)]
+    pub end_position: Option<Position>,
+}
+
+impl FilterStats {
+    /// Adds the counts of another thread.
+    fn merge(&mut self, other: FilterStats) {
+        self.rows_read += other.rows_read;
+        self.rows_rejected += other.rows_rejected;
+        for (output, count) in other.matched_rows {
+            *self.matched_rows.entry(output).or_insert(0) += count;
+        }
+    }
+}
+
+/// The result of sampling the first rows of an input file, which is used to estimate the size
+/// of output files without processing the whole file.
+#[derive(Debug, Default)]
+pub struct Sample {
+    /// The number of sampled rows
+    pub rows: usize,
+    /// The estimated number of rows of the whole input file. Exact if all rows were sampled, and
+    /// the number of sampled rows if the size of the input file is unknown.
+    pub estimated_total_rows: usize,
+    /// Maps a filename to the number of sampled rows that match its filter configuration
+    pub matching_rows: HashMap<String, usize>,
+}
+
+/// Reads the header row of an input file.
 ///
 /// # Arguments
-/// * `output_files` - A map that maps a filename to its CSV file writer
-/// * `filters` - A list of filter configurations
-/// * `csv_file_path` - The path of the CSV file to read data from
-/// * `max_threads` - The maximum number of threads to use
-fn process_csv(
-    output_files: &OutputFileMap,
-    filters: &Vec<Arc<FilterConfig>>,
+/// * `csv_file_path` - Path to the CSV file
+pub fn read_input_headers(csv_file_path: &str) -> csv::Result<Vec<String>> {
+    let mut csv_reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(csv_file_path)?;
+    Ok(csv_reader
+        .headers()?
+        .iter()
+        .map(|h| h.to_string())
+        .collect())
+}
+
+/// Counts the records of a CSV file, excluding the header row.
+///
+/// # Arguments
+/// * `csv_file_path` - Path to the CSV file
+pub fn count_records(csv_file_path: &Path) -> csv::Result<usize> {
+    let mut csv_reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(csv_file_path)?;
+    let mut record = csv::ByteRecord::new();
+    let mut count = 0;
+    while csv_reader.read_byte_record(&mut record)? {
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Returns the names of all columns a filter configuration can reference, ordered by their index
+/// in processed records: input columns first, followed by joined and computed columns.
+///
+/// # Arguments
+/// * `input_headers` - The header row of the input file
+/// * `config` - The filter configuration
+pub fn resolve_columns(input_headers: &[String], config: &FilterConfig) -> Vec<String> {
+    input_headers
+        .iter()
+        .cloned()
+        .chain(
+            config
+                .lookups
+                .iter()
+                .flat_map(|l| l.columns.iter().cloned()),
+        )
+        .chain(config.computed_columns.iter().map(|c| c.name.clone()))
+        .collect()
+}
+
+/// Applies all filter configurations to the first rows of an input file without writing any
+/// output files.
+///
+/// # Arguments
+/// * `csv_file_path` - Path to the CSV file that should be sampled
+/// * `all_filter_configs` - A vector containing all configuration items
+/// * `max_rows` - The maximum number of rows to sample
+///
+/// # Panics
+/// This function will panic on any error.
+pub fn sample_input(
     csv_file_path: &str,
-    max_threads: usize,
-) {
+    all_filter_configs: &[Arc<FilterConfig>],
+    max_rows: usize,
+) -> Sample {
+    let lookup_tables = lookup::load_lookup_tables(all_filter_configs);
+    let scripts = load_scripts(all_filter_configs);
+    let interpreter = Interpreter::default();
     let mut csv_reader = ReaderBuilder::new()
         .has_headers(true)
         .from_path(csv_file_path)
         .expect("Cannot read CSV file");
-    let headers = create_headers_map(&mut csv_reader);
-    let row_counter = Arc::new(AtomicUsize::new(0));
-
-    // We use a bounded channel here to limit how many CSV records can be queued at a time.
-    // If an unbounded data structure is being used, memory consumption can become exhaustive.
-    let (channel_sender, channel_receiver) = bounded_channel(1024);
-    let mut threads = Vec::new();
-
-    // The following will create channel consumer threads that will be consuming CSV records.
-    for _ in 0..max_threads {
-        let channel_receiver = channel_receiver.clone();
-        let filters = filters.clone();
-        let output_files = output_files.clone();
-        let row_counter = row_counter.clone();
-        let headers = headers.clone();
-
-        threads.push(thread::spawn(move || {
-            for csv_record in &channel_receiver {
-                process_csv_record(csv_record, &filters, &output_files, &headers);
-
-                let num = row_counter.fetch_add(1, Ordering::Relaxed);
-                if num % 1000 == 0 {
-                    println!("Number of processed CSV rows: {}", num);
-                }
+    let header_row = csv_reader.headers().expect("Cannot read CSV headers");
+    let headers = create_config_headers_map(
+        create_headers_map(header_row),
+        header_row.len(),
+        all_filter_configs,
+    );
+    let header_bytes = csv_reader.position().byte();
+
+    let mut sample = Sample::default();
+    let mut records = csv_reader.records();
+    let mut complete = true;
+    for csv_record in records.by_ref() {
+        if sample.rows == max_rows {
+            complete = false;
+            break;
+        }
+        let csv_record = csv_record.expect("Cannot parse CSV record");
+        sample.rows += 1;
+
+        for filter_config in all_filter_configs {
+            let config_headers = &headers[&filter_config.output];
+            let tables = &lookup_tables[&filter_config.output];
+            let csv_record =
+                match add_lookup_columns(&csv_record, filter_config, config_headers, tables) {
+                    Some(record) => record,
+                    None => continue,
+                };
+            let csv_record = add_computed_columns(csv_record, filter_config, config_headers);
+            let column_value = |name: &str| {
+                config_headers
+                    .get(name)
+                    .and_then(|&idx| csv_record.get(idx))
+            };
+            if !ColumnFilterPredicate.matches(filter_config, &column_value) {
+                continue;
+            }
+            let script = scripts.get(&filter_config.output);
+            if apply_script(csv_record, script, config_headers, &interpreter).is_some() {
+                *sample
+                    .matching_rows
+                    .entry(filter_config.output.clone())
+                    .or_insert(0) += 1;
             }
-        }));
+        }
     }
 
-    // The following code will read from the CSV file record by record, and write each record into
-    // the channel. The records will then be consumed by one of the consumer threads created above.
-    for csv_record in csv_reader.records() {
-        let csv_record = csv_record.expect("Cannot parse CSV record");
-        channel_sender
-            .send(csv_record)
-            .expect("Error sending record to channel");
+    // The size of pipes and other special files is unknown, so their rows cannot be estimated
+    let sampled_bytes = records.reader().position().byte() - header_bytes;
+    let row_bytes = fs::metadata(csv_file_path)
+        .map(|m| m.len().saturating_sub(header_bytes))
+        .unwrap_or(0);
+    sample.estimated_total_rows = if complete || sampled_bytes == 0 || row_bytes == 0 {
+        sample.rows
+    } else {
+        let bytes_per_row = sampled_bytes as f64 / (sample.rows + 1) as f64;
+        (row_bytes as f64 / bytes_per_row) as usize
+    };
+
+    sample
+}
+
+/// Checks if the file at the given path can be memory-mapped. Only non-empty regular files
+/// qualify. Pipes, character devices (such as stdin) and compressed files need to be read
+/// sequentially and are therefore rejected.
+///
+/// # Arguments
+/// * `path` - The path of the input file
+fn can_memory_map(path: &Path) -> bool {
+    let is_compressed = match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => COMPRESSED_FILE_EXTENSIONS.contains(&ext.to_lowercase().as_str()),
+        None => false,
+    };
+
+    match fs::metadata(path) {
+        Ok(metadata) => metadata.is_file() && metadata.len() > 0 && !is_compressed,
+        Err(_) => false,
+    }
+}
+
+/// Reads all records of a CSV file and processes them on multiple threads. Every thread keeps
+/// its own state, so that no locking is required. Returns the context that was created from the
+/// header row along with the final states of all threads.
+///
+/// # Arguments
+/// * `csv_file_path` - Path to the CSV file that should be read
+/// * `use_mmap` - If the input file should be memory-mapped instead of being read through a
+///   buffered reader. Inputs that cannot be mapped are silently read the buffered way.
+/// * `max_threads` - The maximum number of threads to use
+/// * `create_context` - Creates the context that is shared by all threads from the header row
+/// * `process_record` - Processes one record using the shared context and the state of the
+///   current thread
+///
+/// # Panics
+/// This function will panic if the CSV file cannot be read or parsed.
+pub fn read_records_parallel<C, T, S, F>(
+    csv_file_path: &str,
+    use_mmap: bool,
+    max_threads: usize,
+    create_context: S,
+    process_record: F,
+) -> (C, Vec<T>)
+where
+    C: Sync,
+    T: Default + Send,
+    S: FnOnce(&StringRecord) -> C,
+    F: Fn(&C, &mut T, StringRecord) + Sync,
+{
+    let (context, states, _) = read_records_in_segments(
+        csv_file_path,
+        use_mmap,
+        max_threads,
+        Segments {
+            start: None,
+            end: None,
+            cancel: None,
+            size: 0,
+            on_segment_end: &mut |_, _, _| {},
+        },
+        create_context,
+        process_record,
+    );
+    (context, states)
+}
+
+/// Controls how records are read in segments. All records of a segment have been processed once
+/// the segment ends.
+struct Segments<'a, C, T> {
+    /// The position to start reading from. Reading starts after the header row if absent.
+    start: Option<Position>,
+    /// The byte offset that records must end at or before to be read. Reading stops at the
+    /// first record ending after it.
+    end: Option<u64>,
+    /// Once set, reading stops before the next record
+    cancel: Option<&'a AtomicBool>,
+    /// The number of records per segment. Zero reads all records in one segment.
+    size: usize,
+    /// Called at the end of every segment but the last one with the shared context, the states
+    /// of all threads so far and the position after the last record of the segment
+    on_segment_end: &'a mut dyn FnMut(&C, &[T], &Position),
+}
+
+/// Reads records like [`read_records_parallel`], but waits for all threads to finish processing
+/// after every segment. Additionally returns the position after the last record that was read.
+///
+/// # Arguments
+/// * `csv_file_path` - Path to the CSV file that should be read
+/// * `use_mmap` - If the input file should be memory-mapped
+/// * `max_threads` - The maximum number of threads to use
+/// * `segments` - Controls where reading starts and when segments end
+/// * `create_context` - Creates the context that is shared by all threads from the header row
+/// * `process_record` - Processes one record using the shared context and the state of the
+///   current thread
+///
+/// # Panics
+/// This function will panic if the CSV file cannot be read or parsed.
+fn read_records_in_segments<C, T, S, F>(
+    csv_file_path: &str,
+    use_mmap: bool,
+    max_threads: usize,
+    segments: Segments<C, T>,
+    create_context: S,
+    process_record: F,
+) -> (C, Vec<T>, Position)
+where
+    C: Sync,
+    T: Default + Send,
+    S: FnOnce(&StringRecord) -> C,
+    F: Fn(&C, &mut T, StringRecord) + Sync,
+{
+    let total_bytes = fs::metadata(csv_file_path).map(|m| m.len()).unwrap_or(0);
+    if use_mmap && can_memory_map(Path::new(csv_file_path)) {
+        info!(
+            "Using memory-mapped reading for input file '{}'",
+            csv_file_path
+        );
+        let file = File::open(csv_file_path).expect("Cannot read CSV file");
+        // Safety: The mapping is read-only and only lives until all records have been parsed.
+        // Modifying the input file while it is being processed is not supported.
+        let mmap = unsafe { Mmap::map(&file) }.expect("Cannot memory-map CSV file");
+        let csv_reader = ReaderBuilder::new()
+            .has_headers(true)
+            .from_reader(Cursor::new(&mmap[..]));
+        process_csv(
+            csv_reader,
+            total_bytes,
+            max_threads,
+            segments,
+            create_context,
+            process_record,
+        )
+    } else {
+        let csv_reader = ReaderBuilder::new()
+            .has_headers(true)
+            .from_path(csv_file_path)
+            .expect("Cannot read CSV file");
+        process_csv(
+            csv_reader,
+            total_bytes,
+            max_threads,
+            segments,
+            create_context,
+            process_record,
+        )
     }
+}
 
-    // Stopping the channel and wait for all threads to finish
-    drop(channel_sender);
-    for t in threads {
-        t.join().expect("Cannot join thread.")
+/// Processes the CSV file. See [`read_records_in_segments`].
+///
+/// # Arguments
+/// * `csv_reader` - The CSV reader to read data from
+/// * `total_bytes` - The size of the input file, which is used to display progress
+/// * `max_threads` - The maximum number of threads to use
+/// * `segments` - Controls where reading starts and when segments end
+/// * `create_context` - Creates the context that is shared by all threads from the header row
+/// * `process_record` - Processes one record using the shared context and the state of the
+///   current thread
+fn process_csv<R, C, T, S, F>(
+    mut csv_reader: Reader<R>,
+    total_bytes: u64,
+    max_threads: usize,
+    segments: Segments<C, T>,
+    create_context: S,
+    process_record: F,
+) -> (C, Vec<T>, Position)
+where
+    R: Read + Seek,
+    C: Sync,
+    T: Default + Send,
+    S: FnOnce(&StringRecord) -> C,
+    F: Fn(&C, &mut T, StringRecord) + Sync,
+{
+    let Segments {
+        start,
+        end,
+        cancel,
+        size,
+        on_segment_end,
+    } = segments;
+    let context = create_context(csv_reader.headers().expect("Cannot read CSV headers"));
+    if let Some(start) = start {
+        csv_reader
+            .seek(start)
+            .expect("Cannot continue reading CSV file from the given position");
     }
+    let mut end_position = csv_reader.position().clone();
+    let segment_size = if size == 0 { usize::MAX } else { size };
+
+    let states = crossbeam::scope(|scope| {
+        let mut states = Vec::new();
+        let mut progress = Progress::new(total_bytes);
+        let mut csv_record = StringRecord::new();
+        let mut num: usize = 0;
+        let mut finished = false;
+
+        while !finished {
+            // We use a bounded channel here to limit how many CSV records can be queued at a
+            // time. If an unbounded data structure is being used, memory consumption can
+            // become exhaustive.
+            let (channel_sender, channel_receiver) = bounded_channel(1024);
+
+            // The following will create channel consumer threads that will be consuming CSV
+            // records.
+            let threads: Vec<_> = (0..max_threads)
+                .map(|_| {
+                    let channel_receiver = channel_receiver.clone();
+                    let context = &context;
+                    let process_record = &process_record;
+                    scope.spawn(move |_| {
+                        let mut state = T::default();
+                        for csv_record in &channel_receiver {
+                            process_record(context, &mut state, csv_record);
+                        }
+                        state
+                    })
+                })
+                .collect();
+            // Sending fails once all threads have stopped, e.g. because of a panic
+            drop(channel_receiver);
+
+            // The following code will read from the CSV file record by record, and write each
+            // record into the channel. The records will then be consumed by one of the consumer
+            // threads created above.
+            let mut segment_records = 0;
+            while segment_records < segment_size {
+                // Records after the end are not even parsed, since they may be incomplete
+                if cancel.is_some_and(|c| c.load(Ordering::Relaxed))
+                    || end.is_some_and(|end| csv_reader.position().byte() >= end)
+                    || !csv_reader
+                        .read_record(&mut csv_record)
+                        .expect("Cannot parse CSV record")
+                    || end.is_some_and(|end| csv_reader.position().byte() > end)
+                {
+                    finished = true;
+                    break;
+                }
+                end_position = csv_reader.position().clone();
+                num += 1;
+                segment_records += 1;
+                progress.update(csv_reader.position().byte());
+                if num.is_multiple_of(100_000) {
+                    debug!("Number of read CSV rows: {}", num);
+                }
+                if channel_sender.send(mem::take(&mut csv_record)).is_err() {
+                    finished = true;
+                    break;
+                }
+            }
+
+            // Stopping the channel and wait for all threads to finish. Panics of threads are
+            // passed on, so that their messages are not lost.
+            drop(channel_sender);
+            states.extend(
+                threads
+                    .into_iter()
+                    .map(|t| t.join().unwrap_or_else(|e| panic::resume_unwind(e))),
+            );
+            if !finished {
+                on_segment_end(&context, &states, csv_reader.position());
+            }
+        }
+        progress.finish();
+        debug!("Number of read CSV rows: {}", num);
+
+        states
+    })
+    .expect("Cannot join thread.");
+
+    (context, states, end_position)
 }
 
 /// Processes one CSV record. If the record matches the criteria of any filter configuration,
-/// the row will be written out to its corresponding output file.
+/// the row will be written out to its corresponding output file, or added to the aggregates
+/// of the output file if the filter configuration is an aggregation.
 ///
 /// # Arguments
 /// * `csv_record` - The record that needs to be processed
 /// * `filters` -  A list of filter configurations
-/// * `output_files` - Maps that maps a filename to its CSV file writer
-/// * `headers` - Maps a CSV column name to its index in the current CSV file
-fn process_csv_record(
+/// * `predicate` - Decides which records match a filter configuration
+/// * `sink` - Receives the rows of all output files
+/// * `config_headers` - Maps a filename to the headers map of its filter configuration
+/// * `transforms` - The output column transforms of all filter configurations
+/// * `lookup_tables` - The lookup tables of all filter configurations
+/// * `scripts` - Maps a filename to the compiled script of its filter configuration
+/// * `state` - The partial aggregates, record counts and interpreter of the current thread
+#[allow(clippy::too_many_arguments)]
+fn process_csv_record<P: Predicate, S: RecordSink>(
     csv_record: StringRecord,
-    filters: &Vec<Arc<FilterConfig>>,
-    output_files: &OutputFileMap,
-    headers: &HeadersMap,
+    filters: &[Arc<FilterConfig>],
+    predicate: &P,
+    sink: &S,
+    config_headers: &ConfigHeadersMap,
+    transforms: &Transforms,
+    lookup_tables: &LookupTables,
+    scripts: &ScriptMap,
+    state: &mut ThreadState,
 ) {
+    let ThreadState {
+        aggregates,
+        stats,
+        interpreter,
+    } = state;
+    stats.rows_read += 1;
+    let mut matched = false;
     for filter_config in filters {
-        if record_matches_filter_config(&csv_record, &filter_config, &headers) {
-            let output_record = build_output_record(&csv_record, &filter_config, &headers);
-            write_record_to_file(output_record, &filter_config, &output_files);
+        let headers = &config_headers[&filter_config.output];
+        let tables = &lookup_tables[&filter_config.output];
+        let csv_record = match add_lookup_columns(&csv_record, filter_config, headers, tables) {
+            Some(record) => record,
+            None => continue,
+        };
+        let csv_record = add_computed_columns(csv_record, filter_config, headers);
+        let column_value = |name: &str| headers.get(name).and_then(|&idx| csv_record.get(idx));
+        if !predicate.matches(filter_config, &column_value) {
+            continue;
         }
+        let script = scripts.get(&filter_config.output);
+        if let Some(csv_record) = apply_script(csv_record, script, headers, interpreter) {
+            matched = true;
+            *stats
+                .matched_rows
+                .entry(filter_config.output.clone())
+                .or_insert(0) += 1;
+            if let Some(aggregate) = &filter_config.aggregate {
+                aggregates
+                    .entry(filter_config.output.clone())
+                    .or_insert_with(Aggregates::new)
+                    .add(aggregate, &csv_record, headers);
+            } else {
+                let output_record = build_output_record(
+                    &csv_record,
+                    filter_config,
+                    headers,
+                    transforms.get(&filter_config.output),
+                );
+                sink.write_record(&filter_config.output, output_record);
+            }
+        }
+    }
+    if !matched {
+        stats.rows_rejected += 1;
     }
 }
 
-/// Checks if a CSV record does match the filter criteria of one filter configuration item.
+/// Appends the joined columns of all lookups of a filter configuration to a CSV record. The
+/// record is only copied if the filter configuration defines lookups. Returns `None` if the
+/// record has no match in the lookup table of an inner join.
 ///
 /// # Arguments
-/// * `csv_record` - The record that needs to be checked
-/// * `config` -  The filter configuration to check the CSV record against
-/// * `headers` - Maps a CSV column name to its index in the current CSV file
-fn record_matches_filter_config(
-    csv_record: &StringRecord,
+/// * `csv_record` - The record to look up
+/// * `config` - The filter configuration defining the lookups
+/// * `headers` - Maps a column name (including joined columns) to its index in the record
+/// * `tables` - The lookup tables of the filter configuration
+///
+/// # Panics
+/// This function will panic if a lookup key column cannot be found.
+fn add_lookup_columns<'a>(
+    csv_record: &'a StringRecord,
     config: &FilterConfig,
     headers: &HeadersMap,
-) -> bool {
-    for column_filter in &config.filters {
-        if let Some(&idx) = headers.get(&column_filter.column) {
-            let column_value = csv_record[idx].to_string();
-
-            if let Some(allowed_values) = &column_filter.values {
-                if !allowed_values.contains(&column_value) {
-                    return false;
-                }
-            }
+    tables: &[Arc<lookup::LookupTable>],
+) -> Option<Cow<'a, StringRecord>> {
+    if config.lookups.is_empty() {
+        return Some(Cow::Borrowed(csv_record));
+    }
 
-            if let Some(min) = &column_filter.min {
-                if column_value < *min {
-                    return false;
-                }
-            }
+    let mut record = csv_record.clone();
+    for (lookup, table) in config.lookups.iter().zip(tables) {
+        let key_index = *headers
+            .get(&lookup.key)
+            .unwrap_or_else(|| panic!("Cannot find index of '{}' header", lookup.key));
 
-            if let Some(max) = &column_filter.max {
-                if column_value > *max {
-                    return false;
+        match table.get(&record[key_index]) {
+            Some(row) => {
+                for column in &lookup.columns {
+                    record.push_field(&row[table.column_index(column)]);
                 }
             }
+            None if lookup.join == JoinType::Inner => return None,
+            None => lookup.columns.iter().for_each(|_| record.push_field("")),
         }
     }
 
-    true
+    Some(Cow::Owned(record))
 }
 
-/// Performs the actual writing of data to an output CSV file. This function is thread-safe.
+/// Appends the values of all computed columns of a filter configuration to a CSV record. The
+/// record is only copied if the filter configuration defines computed columns. A column whose
+/// expression cannot be evaluated for the record, e.g. because of arithmetic on an empty or
+/// non-numeric value, is left empty and a warning is logged.
 ///
 /// # Arguments
-/// * `output_record` - The record that needs to be written out
-/// * `config` - The filter configuration to write the record for
-/// * `output_files` - A map that maps a filename to its CSV file writer
-fn write_record_to_file(
-    output_record: Vec<String>,
-    config: &Arc<FilterConfig>,
-    output_files: &OutputFileMap,
-) {
-    let mutex = &output_files[&config.output];
+/// * `csv_record` - The record to compute the column values from
+/// * `config` - The filter configuration defining the computed columns
+/// * `headers` - Maps a column name (including computed columns) to its index in the record
+fn add_computed_columns<'a>(
+    csv_record: Cow<'a, StringRecord>,
+    config: &FilterConfig,
+    headers: &HeadersMap,
+) -> Cow<'a, StringRecord> {
+    if config.computed_columns.is_empty() {
+        return csv_record;
+    }
+
+    let mut record = csv_record.into_owned();
+    for computed_column in &config.computed_columns {
+        let value = computed_column
+            .expression
+            .evaluate(&|name| headers.get(name).and_then(|&idx| record.get(idx)))
+            .map(|value| value.to_string())
+            .unwrap_or_else(|e| {
+                warn!(
+                    "Cannot compute value of column '{}' in line {}: {}",
+                    computed_column.name,
+                    line_number(&record),
+                    e
+                );
+                String::new()
+            });
+        record.push_field(&value);
+    }
+
+    Cow::Owned(record)
+}
+
+/// Returns the line of the input file a CSV record was read from, or 0 if it is unknown.
+///
+/// # Arguments
+/// * `csv_record` - The record that was read
+fn line_number(csv_record: &StringRecord) -> u64 {
+    csv_record.position().map_or(0, |p| p.line())
+}
+
+/// Compiles the scripts of all filter configurations that define one.
+///
+/// # Panics
+/// This function will panic if a script cannot be read or compiled.
+fn load_scripts(all_filter_configs: &[Arc<FilterConfig>]) -> ScriptMap {
+    all_filter_configs
+        .iter()
+        .filter_map(|c| {
+            c.script
+                .as_ref()
+                .map(|file| (c.output.clone(), Script::load(file)))
+        })
+        .collect()
+}
 
-    let write_result;
-    {
-        let mut writer = mutex.lock().unwrap();
-        write_result = writer.write_record(&output_record);
+/// Applies the script of a filter configuration to a CSV record, after all other filters have
+/// been applied. Returns `None` if the script rejects the record. The record is only copied if
+/// the script maps rows.
+///
+/// # Arguments
+/// * `csv_record` - The record to apply the script to
+/// * `script` - The compiled script of the filter configuration, if any
+/// * `headers` - Maps a column name (including joined and computed columns) to its index in the
+///   record
+/// * `interpreter` - The interpreter of the current thread
+///
+/// # Panics
+/// This function will panic if the script fails or its `map(row)` returns an unknown column.
+fn apply_script<'a>(
+    csv_record: Cow<'a, StringRecord>,
+    script: Option<&Script>,
+    headers: &HeadersMap,
+    interpreter: &Interpreter,
+) -> Option<Cow<'a, StringRecord>> {
+    let script = match script {
+        Some(script) => script,
+        None => return Some(csv_record),
+    };
+
+    let row = headers
+        .iter()
+        .filter_map(|(name, &idx)| csv_record.get(idx).map(|value| (name.as_str(), value)));
+    let mapped = interpreter
+        .apply(script, row)
+        .unwrap_or_else(|e| panic!("{}", e))?;
+    if mapped.is_empty() {
+        return Some(csv_record);
+    }
+
+    let mut values: Vec<String> = csv_record.iter().map(|v| v.to_string()).collect();
+    for (column, value) in mapped {
+        let idx = *headers.get(&column).unwrap_or_else(|| {
+            panic!(
+                "Script file '{}' returned unknown column '{}'",
+                script.file(),
+                column
+            )
+        });
+        values[idx] = value;
+    }
+    Some(Cow::Owned(StringRecord::from(values)))
+}
+
+/// Writes the aggregated tables of all aggregated output files.
+///
+/// # Arguments
+/// * `aggregates` - Maps a filename to the merged aggregates of all threads
+/// * `all_filter_configs` - A vector containing all configuration items
+/// * `sink` - Receives the rows of all output files
+fn write_aggregates<S: RecordSink>(
+    mut aggregates: AggregatesMap,
+    all_filter_configs: &[Arc<FilterConfig>],
+    sink: &S,
+) {
+    for cfg in all_filter_configs.iter().filter(|c| c.aggregate.is_some()) {
+        let aggregates = aggregates.remove(&cfg.output).unwrap_or_default();
+        if aggregates.skipped_values() > 0 {
+            warn!(
+                "Skipped {} values of output file '{}' that are not numbers",
+                aggregates.skipped_values(),
+                cfg.output
+            );
+        }
+        for row in aggregates.into_rows() {
+            sink.write_record(&cfg.output, row);
+        }
     }
-    write_result.expect(&format!("Error writing to CSV file '{}'", &config.output))
 }
 
-/// Creates a CSV row with all necessary column values according to a [`FilterConfig`].
+/// Creates a CSV row with all necessary column values according to a [`FilterConfig`]. Transforms
+/// are applied last, so that filters and expressions always see the original values. An output
+/// column whose expression cannot be evaluated for the record is left empty and a warning is
+/// logged.
 ///
 /// # Arguments
 /// * `csv_record` - The record that needs to be mapped to an output file row.
 /// * `config` - The configuration to use.
 /// * `headers` - Maps a CSV column name to its index in the current CSV file
+/// * `transforms` - The output column transforms of the configuration, if any
+///
+/// # Panics
+/// This function will panic if a source column cannot be found.
 fn build_output_record(
     csv_record: &StringRecord,
     config: &Arc<FilterConfig>,
     headers: &HeadersMap,
+    transforms: Option<&OutputTransforms>,
 ) -> Vec<String> {
-    let mut vec: Vec<String> = vec![];
+    let value_of = |colum_name: &str| {
+        let header_index = *headers
+            .get(colum_name)
+            .unwrap_or_else(|| panic!("Cannot find index of '{}' header", colum_name));
+        csv_record[header_index].to_string()
+    };
 
-    let output_column_names = get_output_columns(&config);
+    let values = match &config.output_columns {
+        Some(output_columns) => output_columns
+            .iter()
+            .map(|c| match &c.expression {
+                Some(expression) => expression
+                    .evaluate(&|name| headers.get(name).and_then(|&idx| csv_record.get(idx)))
+                    .map(|value| value.to_string())
+                    .unwrap_or_else(|e| {
+                        warn!(
+                            "Cannot compute value of output column '{}' in line {}: {}",
+                            c.name,
+                            line_number(csv_record),
+                            e
+                        );
+                        String::new()
+                    }),
+                None => value_of(c.source_column()),
+            })
+            .collect(),
+        None => get_output_columns(config)
+            .iter()
+            .map(|c| value_of(c))
+            .collect(),
+    };
 
-    for colum_name in output_column_names {
-        let header_index = *headers
-            .get(&colum_name)
-            .expect(&format!("Cannot find index of '{}' header", colum_name));
-        let v = csv_record[header_index].to_string();
-        vec.push(v);
+    match transforms {
+        Some(t) => t.apply(values),
+        None => values,
     }
-
-    vec
 }
 
-/// Retrieves all columns from a [`FilterConfig`] which should be included in the output.
+/// Retrieves the names of all columns of the output file of a [`FilterConfig`].
 ///
 /// # Arguments
 /// * `config` - The configuration to read the columns from.
 fn get_output_columns(config: &FilterConfig) -> Vec<String> {
-    config
-        .filters
-        .iter()
-        .filter(|f| f.include)
-        .map(|f| f.column.to_string())
-        .collect()
+    config.output_column_names()
 }
 
-/// Creates an output file for each filter configuration. The output file is expected to be a CSV file.
+/// Writes the header rows of all output files according to the corresponding configuration.
 ///
 /// # Arguments
-/// * `all_filter_configs` - A list of all filter configurations.
-/// * `output_dir_path` - Path of the output directory where all files need to be written to.
-fn create_output_files(
-    all_filter_configs: &Vec<Arc<FilterConfig>>,
-    output_dir_path: &str,
-) -> OutputFileMap {
-    if !util::path_exists(output_dir_path) {
-        fs::create_dir_all(output_dir_path).expect(&format!(
-            "Creating output directory '{}' failed:",
-            output_dir_path
-        ));
+/// * `all_filter_configs` - A vector containing all configuration items
+/// * `sink` - Receives the rows of all output files
+/// * `sort_enabled` - If headers of hidden original value columns should be written
+fn write_headers<S: RecordSink>(
+    all_filter_configs: &[Arc<FilterConfig>],
+    sink: &S,
+    sort_enabled: bool,
+) {
+    for cfg in all_filter_configs {
+        let mut headers_record = get_output_columns(cfg);
+        if sort_enabled {
+            headers_record.extend(
+                cfg.original_sort_columns()
+                    .iter()
+                    .map(|c| format!("original:{}", c)),
+            );
+        }
+        sink.write_headers(&cfg.output, headers_record);
     }
+}
 
+/// Creates a map that maps a filename to the headers map of its filter configuration. Joined
+/// columns and computed columns are appended to the input columns in the order they are declared.
+///
+/// # Arguments
+/// * `headers` - Maps a CSV column name to its index in the current CSV file
+/// * `input_columns` - The number of columns of the header row, which may be more than the
+///   number of entries in `headers` if column names are repeated
+/// * `all_filter_configs` - A list of all filter configurations
+///
+/// # Panics
+/// This function will panic if a joined or computed column has the same name as another column.
+fn create_config_headers_map(
+    headers: HeadersMap,
+    input_columns: usize,
+    all_filter_configs: &[Arc<FilterConfig>],
+) -> ConfigHeadersMap {
     let mut map = HashMap::new();
 
-    for config in all_filter_configs {
-        let path = Path::new(output_dir_path).join(&config.output);
-        util::create_file(&path);
-        let writer = csv::Writer::from_path(&path)
-            .expect(&format!("Error opening output file '{:?}'", path));
-        map.insert(config.output.clone(), Mutex::new(writer));
+    for cfg in all_filter_configs {
+        if cfg.lookups.is_empty() && cfg.computed_columns.is_empty() {
+            map.insert(cfg.output.clone(), headers.clone());
+            continue;
+        }
+
+        let mut config_headers = (*headers).clone();
+        let mut index = input_columns;
+        for column in cfg.lookups.iter().flat_map(|l| &l.columns) {
+            if config_headers.contains_key(column) {
+                panic!(
+                    "Joined column '{}' of output file '{}' conflicts with another column",
+                    column, cfg.output
+                );
+            }
+            config_headers.insert(column.clone(), index);
+            index += 1;
+        }
+        for computed_column in &cfg.computed_columns {
+            if config_headers.contains_key(&computed_column.name) {
+                panic!(
+                    "Computed column '{}' of output file '{}' conflicts with another column",
+                    computed_column.name, cfg.output
+                );
+            }
+            config_headers.insert(computed_column.name.clone(), index);
+            index += 1;
+        }
+        map.insert(cfg.output.clone(), Arc::new(config_headers));
     }
 
     Arc::new(map)
 }
 
-/// Writes CSV headers into all output files according to the corresponding configuration.
+/// Checks that the group-by and aggregation columns of all aggregated output files are input,
+/// joined or computed columns. This is done before any record is processed, so that missing
+/// columns are reported once instead of by every thread.
 ///
 /// # Arguments
-/// * `all_filter_configs` - A vector containing all configuration items
-/// * `output_files` - A map that maps a filename to its CSV file writer
-fn write_headers_to_output_files(
-    all_filter_configs: &Vec<Arc<FilterConfig>>,
-    output_files: &OutputFileMap,
+/// * `config_headers` - Maps a filename to the headers map of its filter configuration
+/// * `all_filter_configs` - A list of all filter configurations
+///
+/// # Panics
+/// This function will panic if a column of an aggregation is missing.
+fn check_aggregate_columns(
+    config_headers: &ConfigHeadersMap,
+    all_filter_configs: &[Arc<FilterConfig>],
 ) {
     for cfg in all_filter_configs {
-        let mutex = output_files.get(&cfg.output).unwrap();
-        let mut file = mutex.lock().unwrap();
-
-        let headers_record: Vec<String> = cfg
-            .filters
-            .iter()
-            .filter(|f| f.include)
-            .map(|f| f.column.to_string())
-            .collect();
-
-        file.write_record(headers_record)
-            .expect("Error writing headers to output CSV file");
-        file.flush()
-            .expect("Error flushing headers to output CSV file");
+        if let Some(aggregate) = &cfg.aggregate {
+            if let Err(column) = aggregate::check_columns(aggregate, &config_headers[&cfg.output]) {
+                panic!(
+                    "Aggregation of output file '{}' uses column '{}' which is not part of the input file",
+                    cfg.output, column
+                );
+            }
+        }
     }
 }
 
 /// Creates a map that maps a CSV column name to its index in the current CSV file.
 ///
 /// # Arguments
-/// * `csv_reader` - The CSV reader of the input CSV file.
-fn create_headers_map(csv_reader: &mut Reader<File>) -> HeadersMap {
-    let headers = csv_reader.headers().expect("Cannot read CSV headers");
+/// * `headers` - The header row of the input CSV file.
+fn create_headers_map(headers: &StringRecord) -> HeadersMap {
     let mut map = HashMap::new();
 
-    let mut index = 0;
-    for h in headers {
+    for (index, h) in headers.iter().enumerate() {
         map.insert(h.to_string(), index);
-        index += 1;
     }
 
     Arc::new(map)
@@ -0,0 +1,110 @@
+use csv::{ReaderBuilder, StringRecord};
+use csv_filter_config::FilterConfig;
+use hashbrown::HashMap;
+use std::sync::Arc;
+
+/// The rows of a lookup file, indexed by the values of one key column.
+#[derive(Debug)]
+pub struct LookupTable {
+    file: String,
+    headers: HashMap<String, usize>,
+    rows: HashMap<String, StringRecord>,
+}
+
+impl LookupTable {
+    /// Reads a lookup file into memory. If multiple rows have the same key, the first one is used.
+    ///
+    /// # Arguments
+    /// * `file` - Path of the lookup file
+    /// * `key_column` - The column the rows are indexed by
+    ///
+    /// # Panics
+    /// This function will panic if the lookup file cannot be read or has no key column.
+    fn read(file: &str, key_column: &str) -> Self {
+        let mut reader = ReaderBuilder::new()
+            .has_headers(true)
+            .from_path(file)
+            .unwrap_or_else(|e| panic!("Cannot read lookup file '{}': {}", file, e));
+
+        let headers: HashMap<String, usize> = reader
+            .headers()
+            .unwrap_or_else(|e| panic!("Cannot read headers of lookup file '{}': {}", file, e))
+            .iter()
+            .enumerate()
+            .map(|(index, h)| (h.to_string(), index))
+            .collect();
+        let key_index = *headers.get(key_column).unwrap_or_else(|| {
+            panic!(
+                "Cannot find key column '{}' in lookup file '{}'",
+                key_column, file
+            )
+        });
+
+        let mut rows = HashMap::new();
+        for record in reader.records() {
+            let record =
+                record.unwrap_or_else(|e| panic!("Cannot parse lookup file '{}': {}", file, e));
+            rows.entry(record[key_index].to_string()).or_insert(record);
+        }
+
+        LookupTable {
+            file: file.to_string(),
+            headers,
+            rows,
+        }
+    }
+
+    /// Returns the row with the given key.
+    pub fn get(&self, key: &str) -> Option<&StringRecord> {
+        self.rows.get(key)
+    }
+
+    /// Returns the index of a column of the lookup file.
+    ///
+    /// # Panics
+    /// This function will panic if the column is not part of the lookup file.
+    pub fn column_index(&self, column: &str) -> usize {
+        *self.headers.get(column).unwrap_or_else(|| {
+            panic!(
+                "Cannot find column '{}' in lookup file '{}'",
+                column, self.file
+            )
+        })
+    }
+}
+
+/// Maps a filename to the lookup tables of its filter configuration, in the order the lookups
+/// are declared.
+pub type LookupTables = Arc<HashMap<String, Vec<Arc<LookupTable>>>>;
+
+/// Loads the lookup files of all filter configurations. Every combination of file and key column
+/// is only loaded once, even if multiple filter configurations use it.
+///
+/// # Arguments
+/// * `all_filter_configs` - A list of all filter configurations
+///
+/// # Panics
+/// This function will panic if a lookup file cannot be read or does not contain a joined column.
+pub fn load_lookup_tables(all_filter_configs: &[Arc<FilterConfig>]) -> LookupTables {
+    let mut loaded: HashMap<(&str, &str), Arc<LookupTable>> = HashMap::new();
+    let mut map = HashMap::new();
+
+    for cfg in all_filter_configs {
+        let mut tables = Vec::new();
+        for lookup in &cfg.lookups {
+            let table = loaded
+                .entry((lookup.file.as_str(), lookup.lookup_key_column()))
+                .or_insert_with(|| {
+                    Arc::new(LookupTable::read(&lookup.file, lookup.lookup_key_column()))
+                })
+                .clone();
+            for column in &lookup.columns {
+                table.column_index(column);
+            }
+            tables.push(table);
+        }
+        map.insert(cfg.output.clone(), tables);
+    }
+
+    Arc::new(map)
+}
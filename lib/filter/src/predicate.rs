@@ -0,0 +1,87 @@
+use csv_filter_config::{ColumnFilter, FilterConfig};
+
+/// Decides which records match a configuration item. Predicates are called from multiple
+/// threads.
+pub trait Predicate: Sync {
+    /// Checks if a record matches a configuration item.
+    ///
+    /// # Arguments
+    /// * `config` - The configuration item to check the record against
+    /// * `column_value` - Returns the value of an input, joined or computed column of the record
+    fn matches<'a>(
+        &self,
+        config: &FilterConfig,
+        column_value: &dyn Fn(&str) -> Option<&'a str>,
+    ) -> bool;
+}
+
+/// The built-in predicate used by the tool, which combines [`ValuePredicate`] and
+/// [`RangePredicate`]. Custom predicates can call it to extend these checks.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ColumnFilterPredicate;
+
+impl Predicate for ColumnFilterPredicate {
+    fn matches<'a>(
+        &self,
+        config: &FilterConfig,
+        column_value: &dyn Fn(&str) -> Option<&'a str>,
+    ) -> bool {
+        ValuePredicate.matches(config, column_value) && RangePredicate.matches(config, column_value)
+    }
+}
+
+/// Checks the `values` and value bloom filters of all filters of a configuration item.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ValuePredicate;
+
+impl Predicate for ValuePredicate {
+    fn matches<'a>(
+        &self,
+        config: &FilterConfig,
+        column_value: &dyn Fn(&str) -> Option<&'a str>,
+    ) -> bool {
+        all_filters_match(config, column_value, |column_filter, value| {
+            // The bloom filter rejects most values that are not listed cheaply. Values it lets
+            // pass are still checked against the value set, unless false positives are allowed
+            // and the bloom filter replaces the set.
+            let bloom_filter = column_filter.values_bloom_filter.as_ref();
+            let values = column_filter.values.as_ref();
+            bloom_filter.is_none_or(|b| b.contains(value))
+                && values.is_none_or(|v| v.contains(value))
+        })
+    }
+}
+
+/// Checks the `min` and `max` of all filters of a configuration item. Values are compared as
+/// text.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RangePredicate;
+
+impl Predicate for RangePredicate {
+    fn matches<'a>(
+        &self,
+        config: &FilterConfig,
+        column_value: &dyn Fn(&str) -> Option<&'a str>,
+    ) -> bool {
+        all_filters_match(config, column_value, |column_filter, value| {
+            let min = column_filter.min.as_deref();
+            let max = column_filter.max.as_deref();
+            min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+        })
+    }
+}
+
+/// Checks the value of every filtered column that is part of the record. Columns that are not
+/// part of the record are not filtered.
+fn all_filters_match<'a, F>(
+    config: &FilterConfig,
+    column_value: &dyn Fn(&str) -> Option<&'a str>,
+    matches: F,
+) -> bool
+where
+    F: Fn(&ColumnFilter, &str) -> bool,
+{
+    config.filters.iter().all(|column_filter| {
+        column_value(&column_filter.column).is_none_or(|value| matches(column_filter, value))
+    })
+}
@@ -0,0 +1,170 @@
+use crate::checkpoint::{self, Checkpoint};
+use csv::Writer;
+use csv_filter_config::FilterConfig;
+use hashbrown::HashMap;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Receives the rows of all output files. The filter stage opens the sink, writes the header
+/// row of every output, all matching rows and finally finishes the sink. Rows are written from
+/// multiple threads.
+pub trait RecordSink: Sync {
+    /// Prepares the outputs of all configuration items before anything is written.
+    ///
+    /// # Arguments
+    /// * `all_filter_configs` - A vector containing all configuration items
+    fn open(&mut self, _all_filter_configs: &[Arc<FilterConfig>]) {}
+
+    /// Writes the header row of an output.
+    ///
+    /// # Arguments
+    /// * `output` - The output file name of the configuration item
+    /// * `headers` - The names of all columns
+    fn write_headers(&self, output: &str, headers: Vec<String>);
+
+    /// Writes a row to an output.
+    ///
+    /// # Arguments
+    /// * `output` - The output file name of the configuration item
+    /// * `record` - The values of all columns
+    fn write_record(&self, output: &str, record: Vec<String>);
+
+    /// Makes all rows written so far durable and returns the file name and length of every
+    /// output, which checkpoints record. Sinks that return `None` are not checkpointed.
+    fn checkpoint(&self) -> Option<Vec<(String, u64)>> {
+        None
+    }
+
+    /// Called once all rows have been written, or once the filter stage has been cancelled.
+    fn finish(&mut self) {}
+}
+
+/// Writes every output to a CSV file in an output directory.
+#[derive(Debug)]
+pub struct FileSink {
+    output_dir_path: String,
+    files: HashMap<String, Mutex<Writer<File>>>,
+    /// Maps a filename to the length its output file is truncated to, if an interrupted run is
+    /// continued
+    resume_lengths: Option<HashMap<String, u64>>,
+}
+
+impl FileSink {
+    /// Creates a sink writing to the given directory. Files are created once the sink is opened.
+    ///
+    /// # Arguments
+    /// * `output_dir_path` - Path of the output directory where all files need to be written to
+    pub fn new(output_dir_path: &str) -> Self {
+        FileSink {
+            output_dir_path: output_dir_path.to_string(),
+            files: HashMap::new(),
+            resume_lengths: None,
+        }
+    }
+
+    /// Creates a sink continuing the output files of an interrupted run. Once the sink is
+    /// opened, every file is truncated to the length recorded in the checkpoint, so that rows
+    /// written after the checkpoint are discarded. The checkpoint must be passed to the filter
+    /// stage as well, which then continues after the rows it records.
+    ///
+    /// # Arguments
+    /// * `output_dir_path` - Path of the output directory containing the files of the run
+    /// * `checkpoint` - The checkpoint the run is resumed from
+    pub fn resume(output_dir_path: &str, checkpoint: &Checkpoint) -> Self {
+        FileSink {
+            resume_lengths: Some(checkpoint.output_lengths.clone()),
+            ..FileSink::new(output_dir_path)
+        }
+    }
+
+    /// Returns the writer of an output file.
+    ///
+    /// # Panics
+    /// This function will panic if the sink has not been opened for the output file.
+    fn writer(&self, output: &str) -> &Mutex<Writer<File>> {
+        self.files
+            .get(output)
+            .unwrap_or_else(|| panic!("Output file '{}' has not been opened", output))
+    }
+}
+
+impl RecordSink for FileSink {
+    /// Creates an output file for each configuration item, or reopens it if the sink resumes an
+    /// interrupted run. The output directory is created if necessary.
+    ///
+    /// # Panics
+    /// This function will panic if the output directory or an output file cannot be created.
+    fn open(&mut self, all_filter_configs: &[Arc<FilterConfig>]) {
+        if !util::path_exists(&self.output_dir_path) {
+            std::fs::create_dir_all(&self.output_dir_path).unwrap_or_else(|e| {
+                panic!(
+                    "Creating output directory '{}' failed: {:?}",
+                    self.output_dir_path, e
+                )
+            });
+        }
+
+        for config in all_filter_configs {
+            let path = Path::new(&self.output_dir_path).join(&config.output);
+            let file = match &self.resume_lengths {
+                Some(lengths) => checkpoint::reopen_output_file(&path, lengths[&config.output]),
+                None => util::create_file(&path),
+            };
+            self.files
+                .insert(config.output.clone(), Mutex::new(Writer::from_writer(file)));
+        }
+    }
+
+    /// # Panics
+    /// This function will panic if the header row cannot be written.
+    fn write_headers(&self, output: &str, headers: Vec<String>) {
+        let mut writer = self.writer(output).lock().unwrap();
+        writer
+            .write_record(headers)
+            .expect("Error writing headers to output CSV file");
+        writer
+            .flush()
+            .expect("Error flushing headers to output CSV file");
+    }
+
+    /// # Panics
+    /// This function will panic if the row cannot be written.
+    fn write_record(&self, output: &str, record: Vec<String>) {
+        let write_result = self.writer(output).lock().unwrap().write_record(&record);
+        write_result.unwrap_or_else(|_| panic!("Error writing to CSV file '{}'", output))
+    }
+
+    /// Flushes all output files to disk and returns their lengths.
+    ///
+    /// # Panics
+    /// This function will panic if an output file cannot be flushed.
+    fn checkpoint(&self) -> Option<Vec<(String, u64)>> {
+        let mut output_lengths = Vec::new();
+        for (output, mutex) in self.files.iter() {
+            let mut writer = mutex.lock().unwrap();
+            writer
+                .flush()
+                .unwrap_or_else(|e| panic!("Cannot flush output file '{}': {}", output, e));
+            let file = writer.get_ref();
+            file.sync_data()
+                .unwrap_or_else(|e| panic!("Cannot sync output file '{}': {}", output, e));
+            output_lengths.push((output.clone(), file.metadata().map(|m| m.len()).unwrap()));
+        }
+        Some(output_lengths)
+    }
+
+    /// Flushes and closes all output files.
+    ///
+    /// # Panics
+    /// This function will panic if an output file cannot be flushed.
+    fn finish(&mut self) {
+        for (output, mutex) in self.files.drain() {
+            mutex
+                .into_inner()
+                .unwrap()
+                .flush()
+                .unwrap_or_else(|e| panic!("Cannot flush output file '{}': {}", output, e));
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! The `script` crate runs Rhai scripts that filter and map rows, for rules that cannot be
+//! expressed by value lists, ranges or expressions. Scripts are sandboxed: they cannot access
+//! files, the network or other modules.
+extern crate log;
+extern crate rhai;
+
+use log::{debug, info};
+use rhai::module_resolvers::DummyModuleResolver;
+use rhai::{CallFnOptions, Dynamic, Engine, Map, Scope, AST};
+use std::fs;
+
+/// The maximum number of operations a single function call may perform, which stops scripts
+/// that loop endlessly.
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// The maximum length of a string in bytes, which stops a single row from making a script
+/// allocate without bound. The same applies to the limits below.
+const MAX_STRING_SIZE: usize = 1_000_000;
+
+/// The maximum number of elements of an array
+const MAX_ARRAY_SIZE: usize = 100_000;
+
+/// The maximum number of properties of an object map, e.g. of the row
+const MAX_MAP_SIZE: usize = 100_000;
+
+/// The maximum depth of nested function calls, which stops endless recursion
+const MAX_CALL_LEVELS: usize = 64;
+
+/// A compiled script defining a `filter(row)` function, a `map(row)` function or both. Rows are
+/// passed as object maps from column names to values.
+#[derive(Debug, Clone)]
+pub struct Script {
+    file: String,
+    ast: AST,
+    has_filter: bool,
+    has_map: bool,
+}
+
+impl Script {
+    /// Reads and compiles a script file.
+    ///
+    /// # Arguments
+    /// * `file` - Path of the script file
+    ///
+    /// # Panics
+    /// This function will panic if the script file cannot be read or compiled.
+    pub fn load(file: &str) -> Self {
+        let source = fs::read_to_string(file)
+            .unwrap_or_else(|e| panic!("Cannot read script file '{}': {}", file, e));
+        Script::compile(file, &source).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Compiles a script. Returns an error if the script cannot be parsed or defines neither
+    /// `filter(row)` nor `map(row)`.
+    ///
+    /// # Arguments
+    /// * `file` - Path of the script file, which errors refer to
+    /// * `source` - The contents of the script file
+    pub fn compile(file: &str, source: &str) -> Result<Self, String> {
+        let ast = sandboxed_engine()
+            .compile(source)
+            .map_err(|e| format!("Cannot compile script file '{}': {}", file, e))?;
+        let defines = |name: &str| {
+            ast.iter_functions()
+                .any(|f| f.name == name && f.params.len() == 1)
+        };
+        let (has_filter, has_map) = (defines("filter"), defines("map"));
+        if !has_filter && !has_map {
+            return Err(format!(
+                "Script file '{}' defines neither 'filter(row)' nor 'map(row)'",
+                file
+            ));
+        }
+
+        Ok(Script {
+            file: file.to_string(),
+            ast,
+            has_filter,
+            has_map,
+        })
+    }
+
+    /// Returns the path of the script file.
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    /// Returns if the script defines `filter(row)`.
+    pub fn has_filter(&self) -> bool {
+        self.has_filter
+    }
+
+    /// Returns if the script defines `map(row)`.
+    pub fn has_map(&self) -> bool {
+        self.has_map
+    }
+}
+
+/// Runs compiled scripts. Every thread uses its own interpreter, so that scripts run in parallel
+/// without any locking.
+pub struct Interpreter {
+    engine: Engine,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter {
+            engine: sandboxed_engine(),
+        }
+    }
+}
+
+impl Interpreter {
+    /// Applies a script to a row. Returns `None` if `filter(row)` rejects the row. Otherwise,
+    /// returns the columns and values of the row returned by `map(row)`, or no columns if the
+    /// script does not map rows.
+    ///
+    /// # Arguments
+    /// * `script` - The compiled script
+    /// * `row` - The names and values of all columns of the row
+    pub fn apply<'a, I>(
+        &self,
+        script: &Script,
+        row: I,
+    ) -> Result<Option<Vec<(String, String)>>, String>
+    where
+        I: IntoIterator<Item = (&'a str, &'a str)>,
+    {
+        let row: Map = row
+            .into_iter()
+            .map(|(column, value)| (column.into(), Dynamic::from(value.to_string())))
+            .collect();
+
+        if script.has_filter {
+            let accepted = self.call(script, "filter", row.clone())?;
+            match accepted.as_bool() {
+                Ok(true) => {}
+                Ok(false) => return Ok(None),
+                Err(type_name) => {
+                    return Err(format!(
+                        "'filter' of script file '{}' returned {} instead of a bool",
+                        script.file, type_name
+                    ))
+                }
+            }
+        }
+        if !script.has_map {
+            return Ok(Some(Vec::new()));
+        }
+
+        let mapped = self.call(script, "map", row)?;
+        let type_name = mapped.type_name();
+        let mapped = mapped.try_cast::<Map>().ok_or_else(|| {
+            format!(
+                "'map' of script file '{}' returned {} instead of a row",
+                script.file, type_name
+            )
+        })?;
+        Ok(Some(
+            mapped
+                .into_iter()
+                .map(|(column, value)| (column.to_string(), to_field(value)))
+                .collect(),
+        ))
+    }
+
+    /// Calls a function of a script with a row. Top-level statements of the script are not
+    /// evaluated, since they would run again for every row.
+    fn call(&self, script: &Script, function: &str, row: Map) -> Result<Dynamic, String> {
+        let options = CallFnOptions::new().eval_ast(false);
+        self.engine
+            .call_fn_with_options::<Dynamic>(
+                options,
+                &mut Scope::new(),
+                &script.ast,
+                function,
+                (row,),
+            )
+            .map_err(|e| {
+                format!(
+                    "Cannot run '{}' of script file '{}': {}",
+                    function, script.file, e
+                )
+            })
+    }
+}
+
+/// Creates an engine that cannot import modules, limits the resources of every call and logs
+/// instead of printing.
+fn sandboxed_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_module_resolver(DummyModuleResolver::new())
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_string_size(MAX_STRING_SIZE)
+        .set_max_array_size(MAX_ARRAY_SIZE)
+        .set_max_map_size(MAX_MAP_SIZE)
+        .set_max_call_levels(MAX_CALL_LEVELS)
+        .on_print(|text| info!("{}", text))
+        .on_debug(|text, _, _| debug!("{}", text));
+    engine
+}
+
+/// Converts a value returned by a script into a field. Missing values become empty fields.
+fn to_field(value: Dynamic) -> String {
+    if value.is_unit() {
+        return String::new();
+    }
+    match value.clone().into_immutable_string() {
+        Ok(text) => text.to_string(),
+        Err(_) => value.to_string(),
+    }
+}
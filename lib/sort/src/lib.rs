@@ -2,22 +2,25 @@
 extern crate crossbeam;
 extern crate csv;
 extern crate csv_filter_config as config;
+extern crate csv_filter_distinct as distinct;
 extern crate csv_filter_util as util;
+extern crate log;
 
 use config::FilterConfig;
 use crossbeam::channel::bounded as bounded_channel;
 
 use csv::{ReaderBuilder, StringRecord};
+use log::info;
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fs::File;
-use std::iter::FromIterator;
+use std::mem;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
-use std::thread;
 
 // These type definitions are only here for abbreviation
-type SortConfig = HashMap<PathBuf, Option<Vec<String>>>;
+type SortConfig = HashMap<PathBuf, Arc<FilterConfig>>;
 
 /// Sorts all output CSV files according to the provided configuration.
 ///
@@ -25,46 +28,111 @@ type SortConfig = HashMap<PathBuf, Option<Vec<String>>>;
 /// * `all_filter_configs` - A vector containing all configuration items
 /// * `output_dir_path` - Path to the directory containing all output files
 /// * `max_threads` - The maximum number of threads to use
+/// * `cancel` - Once set, no more rows are read and files that have not been sorted completely
+///   are left unchanged
 ///
 /// # Panics
 /// This function will panic on any error.
 pub fn sort_output_files(
-    all_filter_configs: &Vec<Arc<FilterConfig>>,
+    all_filter_configs: &[Arc<FilterConfig>],
     output_dir_path: &str,
     max_threads: usize,
+    cancel: Option<&AtomicBool>,
 ) {
     let files = read_sort_config(all_filter_configs, output_dir_path);
 
-    let (channel_sender, channel_receiver) = bounded_channel(256);
-    let mut threads = Vec::new();
+    let (channel_sender, channel_receiver) = bounded_channel::<(PathBuf, Arc<FilterConfig>)>(256);
+    crossbeam::scope(|scope| {
+        // The following will create channel consumer threads that will be consuming CSV records.
+        for _ in 0..max_threads {
+            let channel_receiver = channel_receiver.clone();
 
-    // The following will create channel consumer threads that will be consuming CSV records.
-    for _ in 0..max_threads {
-        let channel_receiver = channel_receiver.clone();
-
-        threads.push(thread::spawn(move || {
-            for (path, sort_columns) in &channel_receiver {
-                println!("Sorting file '{}'", util::path_to_string(&path));
-                if let Some(sc) = sort_columns {
-                    sort_csv_file(&path, &sc);
+            scope.spawn(move |_| {
+                for (path, config) in &channel_receiver {
+                    if util::is_cancelled(cancel) {
+                        break;
+                    }
+                    info!("Sorting file '{}'", util::path_to_string(&path));
+                    if let Some(sc) = &config.sort_columns {
+                        sort_csv_file(&path, sc, &config, cancel);
+                    }
                 }
-            }
-        }));
-    }
+            });
+        }
 
-    // The following will iterate over all files and write pairs of (path, sort_columns) into
-    // the channel. The records will then be consumed by one of the consumer threads created above.
-    for path_and_columns in files {
-        channel_sender
-            .send(path_and_columns)
-            .expect("Error sending record to channel");
-    }
+        // The following will iterate over all files and write pairs of (path, config) into
+        // the channel. The records will then be consumed by one of the consumer threads created
+        // above.
+        for path_and_config in files {
+            channel_sender
+                .send(path_and_config)
+                .expect("Error sending record to channel");
+        }
+
+        // Stopping the channel, so that all threads finish once all files have been sorted
+        drop(channel_sender);
+    })
+    .expect("Cannot join thread.");
+}
+
+/// Merges two files that are sorted by the same columns into a new sorted file. Rows of the
+/// existing file come first if rows of both files are equal in all sort columns. Both files must
+/// have the same header row.
+///
+/// # Arguments
+/// * `existing_path` - Path to the sorted file that rows are merged into
+/// * `appended_path` - Path to the sorted file holding the rows to merge
+/// * `target_path` - Path to the file the merged rows are written to
+/// * `sort_columns` - An ordered collection of columns both files are sorted by
+/// * `descending_columns` - The sort columns that are sorted in descending order
+///
+/// # Panics
+/// This function will panic on any error.
+pub fn merge_sorted_files(
+    existing_path: &Path,
+    appended_path: &Path,
+    target_path: &Path,
+    sort_columns: &[String],
+    descending_columns: &[String],
+) {
+    let mut existing_reader = get_reader(existing_path);
+    let mut appended_reader = get_reader(appended_path);
+    let header_row = get_headers(&mut existing_reader);
+    let sort_order = get_sort_order(&header_row, sort_columns, descending_columns, &[]);
+
+    let mut writer = csv::Writer::from_path(target_path).unwrap_or_else(|e| {
+        panic!(
+            "Cannot create file '{}': {}",
+            util::path_to_string(target_path),
+            e
+        )
+    });
+    writer.write_record(&header_row).unwrap();
 
-    // Stopping the channel and wait for all threads to finish
-    drop(channel_sender);
-    for t in threads {
-        t.join().expect("Cannot join thread.")
+    let mut existing_records = existing_reader.records().map(|r| r.unwrap());
+    let mut appended_records = appended_reader.records().map(|r| r.unwrap());
+    let mut existing = existing_records.next();
+    let mut appended = appended_records.next();
+    loop {
+        let take_existing = match (&existing, &appended) {
+            (Some(a), Some(b)) => record_comparator(a, b, &sort_order) != Ordering::Greater,
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => break,
+        };
+        let record = if take_existing {
+            mem::replace(&mut existing, existing_records.next())
+        } else {
+            mem::replace(&mut appended, appended_records.next())
+        };
+        writer.write_record(&record.unwrap()).unwrap_or_else(|_| {
+            panic!(
+                "Error writing record to output file '{}'",
+                util::path_to_string(target_path)
+            )
+        });
     }
+    writer.flush().unwrap();
 }
 
 /// Reads parts from configuration relevant for sorting output files.
@@ -76,57 +144,82 @@ pub fn sort_output_files(
 /// # Panics
 /// This function will panic if a CSV output file specified in one of the provided
 /// configurations cannot be found.
-fn read_sort_config(
-    all_filter_configs: &Vec<Arc<FilterConfig>>,
-    output_dir_path: &str,
-) -> SortConfig {
+fn read_sort_config(all_filter_configs: &[Arc<FilterConfig>], output_dir_path: &str) -> SortConfig {
     let mut files = SortConfig::new();
     for cfg in all_filter_configs {
         let path = Path::new(output_dir_path).join(&cfg.output);
         if !path.exists() {
-            panic!(format!(
-                "Cannot sort file '{:?}' because it does not exists.",
-                path
-            ));
+            panic!("Cannot sort file '{:?}' because it does not exists.", path);
         }
 
-        let sort_columns: Option<Vec<String>> = match &cfg.sort_columns {
-            Some(sc) => Option::Some(Vec::from_iter(sc.iter().cloned())),
-            None => Option::None,
-        };
-
-        files.insert(path, sort_columns);
+        files.insert(path, cfg.clone());
     }
     files
 }
 
-/// Sorts a CSV file.
+/// Sorts a CSV file. If the configuration defines deduplication, duplicate rows are removed
+/// after sorting, since all rows are held in memory at this point anyway, so that `keep` refers
+/// to the sort order. The sort is stable, so rows with equal sort keys keep their order. Hidden
+/// columns holding the original values of transformed sort columns are removed after sorting.
 ///
 /// # Arguments
 /// * `path` - Path to the file to be sorted
 /// * `sort_columns` - An ordered collection of columns to sort by
+/// * `config` - The configuration of the output file
+/// * `cancel` - Once set, no more rows are read and the file is left unchanged
 ///
 /// # Panics
 /// This function will panic on any error.
-fn sort_csv_file(path: &PathBuf, sort_columns: &Vec<String>) {
+fn sort_csv_file(
+    path: &Path,
+    sort_columns: &[String],
+    config: &FilterConfig,
+    cancel: Option<&AtomicBool>,
+) {
     let mut csv_reader = get_reader(path);
 
-    let header_row = get_headers(&mut csv_reader);
-    let sort_order = get_sort_order(&header_row, sort_columns);
-
-    let mut records: Vec<StringRecord> = csv_reader.records().map(|r| r.unwrap()).collect();
-    records.sort_by(|a, b| record_comparator(a, b, &sort_order));
+    let mut header_row = get_headers(&mut csv_reader);
+    let original_columns = config.original_sort_columns();
+    let visible_columns = header_row.len() - original_columns.len();
+    header_row.truncate(visible_columns);
+    let sort_order = get_sort_order(
+        &header_row,
+        sort_columns,
+        &config.descending_columns,
+        &original_columns,
+    );
 
+    let mut records: Vec<StringRecord> = csv_reader
+        .records()
+        .take_while(|_| !util::is_cancelled(cancel))
+        .map(|r| r.unwrap())
+        .collect();
     drop(csv_reader);
+    if util::is_cancelled(cancel) {
+        return;
+    }
+
+    records.sort_by(|a, b| record_comparator(a, b, &sort_order));
+    if let Some(d) = &config.distinct {
+        let key_indices = distinct::get_key_indices(&header_row, d);
+        records = distinct::distinct_records(records, &key_indices, d.keep);
+    }
+    if util::is_cancelled(cancel) {
+        return;
+    }
 
     let mut writer = csv::Writer::from_path(path).unwrap();
     writer.write_record(header_row).unwrap();
 
     for record in records {
-        writer.write_record(&record).expect(&format!(
-            "Error writing record to output file '{}'",
-            util::path_to_string(&path)
-        ));
+        writer
+            .write_record(record.iter().take(visible_columns))
+            .unwrap_or_else(|_| {
+                panic!(
+                    "Error writing record to output file '{}'",
+                    util::path_to_string(path)
+                )
+            });
     }
 }
 
@@ -147,28 +240,40 @@ fn get_headers(csv_file_reader: &mut csv::Reader<File>) -> Vec<String> {
 ///
 /// # Arguments
 /// * `path` - The path to the CSV file.
-fn get_reader(path: &PathBuf) -> csv::Reader<File> {
+fn get_reader(path: &Path) -> csv::Reader<File> {
     ReaderBuilder::new()
         .has_headers(true)
         .from_path(path)
         .expect("Cannot read CSV file")
 }
 
-/// Creates a vector holding the column sort order in the form of column indexes.
+/// Creates a vector holding the column sort order in the form of column indexes, each paired
+/// with a flag that tells if the column is sorted in descending order.
 ///
 /// # Arguments
 /// * `header_row` - The header row from the CSV file holding the column names.
 /// * `sort_columns` - The sort order as a list of column names.
-fn get_sort_order(header_row: &Vec<String>, sort_columns: &Vec<String>) -> Vec<usize> {
+/// * `descending_columns` - The sort columns that are sorted in descending order
+/// * `original_columns` - The sort columns whose original values follow the header row columns
+fn get_sort_order(
+    header_row: &[String],
+    sort_columns: &[String],
+    descending_columns: &[String],
+    original_columns: &[String],
+) -> Vec<(usize, bool)> {
     let mut sort_order = Vec::new();
 
     for sort_column in sort_columns {
-        let mut index: usize = 0;
-        for h in header_row {
-            if sort_column.cmp(&h) == Ordering::Equal {
-                sort_order.push(index);
+        let descending = descending_columns.contains(sort_column);
+        if let Some(index) = original_columns.iter().position(|c| c == sort_column) {
+            sort_order.push((header_row.len() + index, descending));
+            continue;
+        }
+
+        for (index, h) in header_row.iter().enumerate() {
+            if sort_column.cmp(h) == Ordering::Equal {
+                sort_order.push((index, descending));
             }
-            index += 1;
         }
     }
 
@@ -180,10 +285,11 @@ fn get_sort_order(header_row: &Vec<String>, sort_columns: &Vec<String>) -> Vec<u
 /// # Arguments
 /// * `a` - First record
 /// * `b` - Second record
-/// * `header_map` - Maps a column name to its corresponding index inside both [`StringRecord`] objects.
-fn record_comparator(a: &StringRecord, b: &StringRecord, header_map: &Vec<usize>) -> Ordering {
+/// * `header_map` - The indexes of the sort columns inside both [`StringRecord`] objects, each
+///   paired with a flag that tells if the column is sorted in descending order.
+fn record_comparator(a: &StringRecord, b: &StringRecord, header_map: &[(usize, bool)]) -> Ordering {
     let mut order = Ordering::Equal;
-    for &column_index in header_map {
+    for &(column_index, descending) in header_map {
         if order != Ordering::Equal {
             return order;
         }
@@ -191,7 +297,10 @@ fn record_comparator(a: &StringRecord, b: &StringRecord, header_map: &Vec<usize>
         let column_value_a = a.get(column_index).unwrap();
         let column_value_b = b.get(column_index).unwrap();
 
-        order = order.then(column_value_a.cmp(column_value_b));
+        order = match descending {
+            true => order.then(column_value_b.cmp(column_value_a)),
+            false => order.then(column_value_a.cmp(column_value_b)),
+        };
     }
     order
 }
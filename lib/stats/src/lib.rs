@@ -0,0 +1,528 @@
+//! The `stats` crate profiles input files, so that configurations can be written without knowing
+//! their contents upfront. It reports the number of rows and, per column, the inferred type,
+//! empty and null counts, an estimate of distinct values, minimum, maximum and the most frequent
+//! values.
+extern crate chrono;
+extern crate csv;
+extern crate csv_filter_filter as filter;
+extern crate csv_filter_util as util;
+extern crate hashbrown;
+extern crate serde;
+extern crate serde_json;
+
+use chrono::NaiveDate;
+use csv::{ReaderBuilder, StringRecord};
+use hashbrown::{HashMap, HashSet};
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::fmt;
+use util::hyperloglog::HyperLogLog;
+
+/// The number of values per column whose frequencies are tracked. If a column has more distinct
+/// values, the least frequent ones are dropped and its frequent values become approximate.
+const MAX_TRACKED_VALUES: usize = 10_000;
+
+/// Settings that control how an input file is profiled.
+#[derive(Debug, Clone)]
+pub struct StatsOptions {
+    /// The number of most frequent values to report per column
+    pub top_values: usize,
+    /// The maximum number of threads to use
+    pub parallelism: usize,
+    /// If the input file should be memory-mapped instead of being read through a buffered reader
+    pub use_mmap: bool,
+}
+
+impl Default for StatsOptions {
+    fn default() -> Self {
+        StatsOptions {
+            top_values: 5,
+            parallelism: 1,
+            use_mmap: false,
+        }
+    }
+}
+
+/// The type inferred from all non-empty values of a column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnType {
+    /// The column does not contain any values
+    Empty,
+    /// All values are `true` or `false`
+    Boolean,
+    /// All values are integers
+    Integer,
+    /// All values are numbers
+    Float,
+    /// All values are dates in the form `YYYY-MM-DD`
+    Date,
+    /// Any other values
+    String,
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ColumnType::Empty => "empty",
+            ColumnType::Boolean => "boolean",
+            ColumnType::Integer => "integer",
+            ColumnType::Float => "float",
+            ColumnType::Date => "date",
+            ColumnType::String => "string",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A value along with the number of rows it appears in.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ValueCount {
+    pub value: String,
+    pub count: usize,
+}
+
+/// The profile of one column.
+#[derive(Debug, Clone, Serialize)]
+pub struct ColumnStats {
+    /// The name of the column
+    pub name: String,
+    /// The type inferred from all values that are neither empty nor null
+    #[serde(rename = "type")]
+    pub inferred_type: ColumnType,
+    /// The number of empty values
+    pub empty: usize,
+    /// The number of values that are `null` (case insensitive)
+    pub null: usize,
+    /// The number of distinct values. Estimated for columns with many distinct values.
+    pub distinct: usize,
+    /// The smallest value, compared numerically for numeric columns
+    pub min: Option<String>,
+    /// The largest value, compared numerically for numeric columns
+    pub max: Option<String>,
+    /// The most frequent values, ordered by descending frequency
+    pub top_values: Vec<ValueCount>,
+}
+
+/// The profile of an input file.
+#[derive(Debug, Clone, Serialize)]
+pub struct InputStats {
+    /// The number of rows, excluding the header row
+    pub rows: usize,
+    /// The profiles of all columns in the order of the header row
+    pub columns: Vec<ColumnStats>,
+}
+
+impl InputStats {
+    /// Serializes the profile to pretty-printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Cannot serialize stats")
+    }
+}
+
+impl fmt::Display for InputStats {
+    /// Formats the profile as a table with one line per column.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let header = [
+            "column",
+            "type",
+            "empty",
+            "null",
+            "distinct",
+            "min",
+            "max",
+            "top values",
+        ];
+        let mut lines = vec![header.iter().map(|h| h.to_string()).collect::<Vec<_>>()];
+        for column in &self.columns {
+            let top_values: Vec<String> = column
+                .top_values
+                .iter()
+                .map(|v| format!("{} ({})", v.value, v.count))
+                .collect();
+            lines.push(vec![
+                column.name.clone(),
+                column.inferred_type.to_string(),
+                column.empty.to_string(),
+                column.null.to_string(),
+                column.distinct.to_string(),
+                column.min.clone().unwrap_or_default(),
+                column.max.clone().unwrap_or_default(),
+                top_values.join(", "),
+            ]);
+        }
+
+        let widths: Vec<usize> = (0..header.len())
+            .map(|i| {
+                lines
+                    .iter()
+                    .map(|l| l[i].chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        writeln!(f, "Rows: {}", self.rows)?;
+        for line in lines {
+            let cells: Vec<String> = line
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect();
+            writeln!(f, "{}", cells.join("  ").trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// Profiles an input file. Records are read in parallel, every thread profiles its share of the
+/// records and all partial profiles are merged at the end.
+///
+/// # Arguments
+/// * `csv_file_path` - Path to the CSV file that should be profiled
+/// * `options` - Settings that control how the input file is profiled
+///
+/// # Panics
+/// This function will panic if the input file cannot be read or parsed.
+pub fn collect(csv_file_path: &str, options: &StatsOptions) -> InputStats {
+    let (headers, partial_profiles) = filter::read_records_parallel(
+        csv_file_path,
+        options.use_mmap,
+        options.parallelism,
+        |headers| {
+            headers
+                .iter()
+                .map(|h| h.to_string())
+                .collect::<Vec<String>>()
+        },
+        |headers, profile: &mut Profile, csv_record| profile.add(headers.len(), &csv_record),
+    );
+
+    let mut profile = Profile::default();
+    for partial in partial_profiles {
+        profile.merge(partial);
+    }
+
+    InputStats {
+        rows: profile.rows,
+        columns: headers
+            .into_iter()
+            .enumerate()
+            .map(|(index, name)| match profile.columns.get(index) {
+                Some(column) => column.finish(name, options.top_values),
+                None => ColumnProfile::default().finish(name, options.top_values),
+            })
+            .collect(),
+    }
+}
+
+/// The distinct values of a column, sampled from the first rows of an input file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnValues {
+    /// The name of the column
+    pub name: String,
+    /// All distinct values in alphabetical order, or `None` if the sampled rows contain more
+    /// distinct values than requested
+    pub values: Option<Vec<String>>,
+}
+
+/// Samples the distinct values of all columns from the first rows of an input file, e.g. to
+/// detect columns with only few distinct values.
+///
+/// # Arguments
+/// * `csv_file_path` - Path to the CSV file that should be sampled
+/// * `max_rows` - The maximum number of rows to sample
+/// * `max_values` - The maximum number of distinct values of a column
+///
+/// # Panics
+/// This function will panic if the input file cannot be read or parsed.
+pub fn sample_distinct_values(
+    csv_file_path: &str,
+    max_rows: usize,
+    max_values: usize,
+) -> Vec<ColumnValues> {
+    let mut csv_reader = ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(csv_file_path)
+        .expect("Cannot read CSV file");
+    let headers = csv_reader
+        .headers()
+        .expect("Cannot read CSV headers")
+        .clone();
+
+    let mut values: Vec<Option<HashSet<String>>> = vec![Some(HashSet::new()); headers.len()];
+    for csv_record in csv_reader.records().take(max_rows) {
+        let csv_record = csv_record.expect("Cannot parse CSV record");
+        for (column_values, value) in values.iter_mut().zip(csv_record.iter()) {
+            if let Some(set) = column_values {
+                set.insert(value.to_string());
+                if set.len() > max_values {
+                    *column_values = None;
+                }
+            }
+        }
+    }
+
+    headers
+        .iter()
+        .zip(values)
+        .map(|(name, values)| ColumnValues {
+            name: name.to_string(),
+            values: values.filter(|v| !v.is_empty()).map(|v| {
+                let mut sorted: Vec<String> = v.into_iter().collect();
+                sorted.sort();
+                sorted
+            }),
+        })
+        .collect()
+}
+
+/// The partial profile of the records processed by one thread.
+#[derive(Debug, Default)]
+struct Profile {
+    rows: usize,
+    columns: Vec<ColumnProfile>,
+}
+
+impl Profile {
+    /// Adds a record to the profile.
+    fn add(&mut self, num_columns: usize, csv_record: &StringRecord) {
+        if self.columns.is_empty() {
+            self.columns
+                .resize_with(num_columns, ColumnProfile::default);
+        }
+        self.rows += 1;
+        for (column, value) in self.columns.iter_mut().zip(csv_record.iter()) {
+            column.add(value);
+        }
+    }
+
+    /// Merges the profile of another thread into this one.
+    fn merge(&mut self, other: Profile) {
+        self.rows += other.rows;
+        if self.columns.is_empty() {
+            self.columns = other.columns;
+            return;
+        }
+        for (column, other) in self.columns.iter_mut().zip(other.columns) {
+            column.merge(other);
+        }
+    }
+}
+
+/// The partial profile of one column.
+#[derive(Debug)]
+struct ColumnProfile {
+    empty: usize,
+    null: usize,
+    values: usize,
+    could_be_boolean: bool,
+    could_be_integer: bool,
+    could_be_float: bool,
+    could_be_date: bool,
+    min_text: Option<String>,
+    max_text: Option<String>,
+    min_number: Option<(f64, String)>,
+    max_number: Option<(f64, String)>,
+    distinct: HyperLogLog,
+    frequencies: HashMap<String, usize>,
+    frequencies_pruned: bool,
+}
+
+impl Default for ColumnProfile {
+    fn default() -> Self {
+        ColumnProfile {
+            empty: 0,
+            null: 0,
+            values: 0,
+            could_be_boolean: true,
+            could_be_integer: true,
+            could_be_float: true,
+            could_be_date: true,
+            min_text: None,
+            max_text: None,
+            min_number: None,
+            max_number: None,
+            distinct: HyperLogLog::new(),
+            frequencies: HashMap::new(),
+            frequencies_pruned: false,
+        }
+    }
+}
+
+impl ColumnProfile {
+    /// Adds a value to the profile.
+    fn add(&mut self, value: &str) {
+        if value.trim().is_empty() {
+            self.empty += 1;
+            return;
+        }
+        if value.eq_ignore_ascii_case("null") {
+            self.null += 1;
+            return;
+        }
+        self.values += 1;
+
+        self.could_be_boolean &= value == "true" || value == "false";
+        self.could_be_integer &= value.parse::<i64>().is_ok();
+        self.could_be_date &= NaiveDate::parse_from_str(value, "%Y-%m-%d").is_ok();
+        if self.could_be_float {
+            match value.parse::<f64>() {
+                Ok(number) if number.is_finite() => self.add_number(number, value),
+                _ => self.could_be_float = false,
+            }
+        }
+
+        if self
+            .min_text
+            .as_ref()
+            .is_none_or(|min| value < min.as_str())
+        {
+            self.min_text = Some(value.to_string());
+        }
+        if self
+            .max_text
+            .as_ref()
+            .is_none_or(|max| value > max.as_str())
+        {
+            self.max_text = Some(value.to_string());
+        }
+
+        self.distinct.insert(value);
+        match self.frequencies.get_mut(value) {
+            Some(count) => *count += 1,
+            None => {
+                self.frequencies.insert(value.to_string(), 1);
+                self.prune_frequencies();
+            }
+        }
+    }
+
+    /// Updates the numeric minimum and maximum.
+    fn add_number(&mut self, number: f64, value: &str) {
+        if self
+            .min_number
+            .as_ref()
+            .is_none_or(|(min, _)| number < *min)
+        {
+            self.min_number = Some((number, value.to_string()));
+        }
+        if self
+            .max_number
+            .as_ref()
+            .is_none_or(|(max, _)| number > *max)
+        {
+            self.max_number = Some((number, value.to_string()));
+        }
+    }
+
+    /// Drops the least frequent values once twice as many values as allowed are tracked.
+    fn prune_frequencies(&mut self) {
+        if self.frequencies.len() <= 2 * MAX_TRACKED_VALUES {
+            return;
+        }
+        let mut frequencies: Vec<(String, usize)> = self.frequencies.drain().collect();
+        frequencies.sort_by(compare_frequencies);
+        frequencies.truncate(MAX_TRACKED_VALUES);
+        self.frequencies = frequencies.into_iter().collect();
+        self.frequencies_pruned = true;
+    }
+
+    /// Merges the profile of the same column from another thread into this one.
+    fn merge(&mut self, other: ColumnProfile) {
+        self.empty += other.empty;
+        self.null += other.null;
+        self.values += other.values;
+        self.could_be_boolean &= other.could_be_boolean;
+        self.could_be_integer &= other.could_be_integer;
+        self.could_be_float &= other.could_be_float;
+        self.could_be_date &= other.could_be_date;
+
+        if let Some(min) = other.min_text {
+            if self.min_text.as_ref().is_none_or(|m| min < *m) {
+                self.min_text = Some(min);
+            }
+        }
+        if let Some(max) = other.max_text {
+            if self.max_text.as_ref().is_none_or(|m| max > *m) {
+                self.max_text = Some(max);
+            }
+        }
+        if let Some((number, value)) = other.min_number {
+            self.add_number(number, &value);
+        }
+        if let Some((number, value)) = other.max_number {
+            self.add_number(number, &value);
+        }
+
+        self.distinct.merge(&other.distinct);
+        self.frequencies_pruned |= other.frequencies_pruned;
+        for (value, count) in other.frequencies {
+            *self.frequencies.entry(value).or_insert(0) += count;
+        }
+        self.prune_frequencies();
+    }
+
+    /// Creates the final statistics of the column.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the column
+    /// * `top_values` - The number of most frequent values to report
+    fn finish(&self, name: String, top_values: usize) -> ColumnStats {
+        let inferred_type = if self.values == 0 {
+            ColumnType::Empty
+        } else if self.could_be_boolean {
+            ColumnType::Boolean
+        } else if self.could_be_integer {
+            ColumnType::Integer
+        } else if self.could_be_float {
+            ColumnType::Float
+        } else if self.could_be_date {
+            ColumnType::Date
+        } else {
+            ColumnType::String
+        };
+
+        let (min, max) = match inferred_type {
+            ColumnType::Integer | ColumnType::Float => (
+                self.min_number.as_ref().map(|(_, v)| v.clone()),
+                self.max_number.as_ref().map(|(_, v)| v.clone()),
+            ),
+            _ => (self.min_text.clone(), self.max_text.clone()),
+        };
+
+        // As long as all values are tracked, the number of distinct values is exact.
+        let distinct = if self.frequencies_pruned {
+            self.distinct.estimate()
+        } else {
+            self.frequencies.len()
+        };
+
+        let mut frequencies: Vec<(String, usize)> = self
+            .frequencies
+            .iter()
+            .map(|(value, count)| (value.clone(), *count))
+            .collect();
+        frequencies.sort_by(compare_frequencies);
+
+        ColumnStats {
+            name,
+            inferred_type,
+            empty: self.empty,
+            null: self.null,
+            distinct,
+            min,
+            max,
+            top_values: frequencies
+                .into_iter()
+                .take(top_values)
+                .map(|(value, count)| ValueCount { value, count })
+                .collect(),
+        }
+    }
+}
+
+/// Orders values by descending frequency and values of the same frequency alphabetically.
+fn compare_frequencies(a: &(String, usize), b: &(String, usize)) -> Ordering {
+    b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0))
+}
@@ -0,0 +1,295 @@
+//! The `transform` crate provides transforms that scrub personal data from output column values,
+//! such as redacting, masking, keyed hashing and tokenizing.
+extern crate csv;
+extern crate csv_filter_config as config;
+extern crate hashbrown;
+extern crate hex;
+extern crate hmac;
+extern crate sha2;
+
+use config::{FilterConfig, Transform};
+use hashbrown::HashMap;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Maps values to tokens. New tokens are numbered consecutively in the order values are first
+/// seen. With more than one filter thread, this is the order in which threads process rows, so
+/// which token a new value gets may differ between runs over the same input file.
+#[derive(Debug, Default)]
+struct TokenMap {
+    tokens: HashMap<String, String>,
+    changed: bool,
+}
+
+impl TokenMap {
+    /// Reads a token lookup file. A missing file yields an empty map.
+    ///
+    /// # Panics
+    /// This function will panic if the lookup file cannot be parsed.
+    fn read(path: &str) -> Self {
+        let mut map = TokenMap::default();
+        if !Path::new(path).exists() {
+            return map;
+        }
+
+        let mut reader = csv::Reader::from_path(path)
+            .unwrap_or_else(|e| panic!("Cannot read token lookup file '{}': {}", path, e));
+        for record in reader.records() {
+            let record = record
+                .unwrap_or_else(|e| panic!("Cannot parse token lookup file '{}': {}", path, e));
+            match (record.get(0), record.get(1)) {
+                (Some(value), Some(token)) => {
+                    map.tokens.insert(value.to_string(), token.to_string());
+                }
+                _ => panic!(
+                    "Cannot parse token lookup file '{}': expected the columns 'value' and 'token'",
+                    path
+                ),
+            }
+        }
+        map
+    }
+
+    /// Returns the token of a value and creates a new token for unknown values.
+    fn token(&mut self, value: &str, prefix: &str) -> String {
+        if let Some(token) = self.tokens.get(value) {
+            return token.clone();
+        }
+
+        let token = format!("{}{}", prefix, self.tokens.len() + 1);
+        self.tokens.insert(value.to_string(), token.clone());
+        self.changed = true;
+        token
+    }
+
+    /// Writes all tokens to the lookup file, ordered by token. The tokens are written to a
+    /// temporary file that is synced to disk and then replaces the lookup file, so that the
+    /// lookup file is never left incomplete.
+    ///
+    /// # Panics
+    /// This function will panic if the lookup file cannot be written.
+    fn write(&self, path: &str) {
+        let mut entries: Vec<(&String, &String)> = self.tokens.iter().collect();
+        entries.sort_by(|a, b| (a.1.len(), a.1).cmp(&(b.1.len(), b.1)));
+
+        let temp_path = format!("{}.tmp", path);
+        let write = || -> Result<(), csv::Error> {
+            let mut writer = csv::Writer::from_path(&temp_path)?;
+            writer.write_record(["value", "token"])?;
+            for (value, token) in entries {
+                writer.write_record([value, token])?;
+            }
+            let file = writer.into_inner().map_err(|e| e.into_error())?;
+            file.sync_all()?;
+            fs::rename(&temp_path, path)?;
+            Ok(())
+        };
+        write().unwrap_or_else(|e| panic!("Cannot write token lookup file '{}': {}", path, e));
+    }
+}
+
+/// A transform of one output column that is ready to be applied, i.e. with its key or token
+/// lookup table loaded.
+#[derive(Debug)]
+enum Transformer {
+    Redact(String),
+    Mask(usize, char),
+    Hash(Vec<u8>),
+    Tokenize(Arc<Mutex<TokenMap>>, String),
+}
+
+impl Transformer {
+    fn apply(&self, value: &str) -> String {
+        match self {
+            Transformer::Redact(placeholder) => placeholder.clone(),
+            Transformer::Mask(keep_last, mask_char) => {
+                let length = value.chars().count();
+                let masked = length.saturating_sub(*keep_last);
+                value
+                    .chars()
+                    .enumerate()
+                    .map(|(i, c)| if i < masked { *mask_char } else { c })
+                    .collect()
+            }
+            Transformer::Hash(key) => {
+                let mut mac =
+                    Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+                mac.update(value.as_bytes());
+                hex::encode(mac.finalize().into_bytes())
+            }
+            Transformer::Tokenize(token_map, prefix) => {
+                token_map.lock().unwrap().token(value, prefix)
+            }
+        }
+    }
+}
+
+/// The transforms of one output file.
+#[derive(Debug, Default)]
+pub struct OutputTransforms {
+    /// The transform of every output column, in output column order.
+    columns: Vec<Option<Transformer>>,
+    /// The output column indexes of the values that are written to hidden columns for sorting.
+    original_sort_columns: Vec<usize>,
+}
+
+impl OutputTransforms {
+    /// Transforms the values of an output row. The original values of sort columns that are
+    /// sorted by original value are appended to the row.
+    ///
+    /// # Arguments
+    /// * `values` - The output row values in output column order
+    pub fn apply(&self, mut values: Vec<String>) -> Vec<String> {
+        if self.columns.is_empty() {
+            return values;
+        }
+
+        let originals: Vec<String> = self
+            .original_sort_columns
+            .iter()
+            .map(|&i| values[i].clone())
+            .collect();
+
+        for (value, transformer) in values.iter_mut().zip(&self.columns) {
+            if let Some(t) = transformer {
+                *value = t.apply(value);
+            }
+        }
+
+        values.extend(originals);
+        values
+    }
+}
+
+/// Holds the transforms of all output files.
+#[derive(Debug, Default)]
+pub struct Transforms {
+    outputs: HashMap<String, OutputTransforms>,
+    token_maps: HashMap<String, Arc<Mutex<TokenMap>>>,
+}
+
+impl Transforms {
+    /// Prepares the transforms of all filter configurations. Hash keys and token lookup files are
+    /// read once. Filter configurations that use the same lookup file share their tokens.
+    ///
+    /// # Arguments
+    /// * `all_filter_configs` - A list of all filter configurations
+    /// * `keep_original_sort_values` - If the original values of sort columns that are sorted by
+    ///   original value should be appended to output rows
+    ///
+    /// # Panics
+    /// This function will panic if a hash key or token lookup file cannot be read.
+    pub fn new(all_filter_configs: &[Arc<FilterConfig>], keep_original_sort_values: bool) -> Self {
+        let mut transforms = Transforms::default();
+
+        for cfg in all_filter_configs
+            .iter()
+            .filter(|c| !c.transforms.is_empty())
+        {
+            let output_columns = cfg.output_column_names();
+            let position = |column: &str| {
+                output_columns
+                    .iter()
+                    .position(|c| c == column)
+                    .unwrap_or_else(|| panic!("Cannot find output column '{}'", column))
+            };
+
+            let mut columns: Vec<Option<Transformer>> =
+                output_columns.iter().map(|_| None).collect();
+            for column_transform in &cfg.transforms {
+                columns[position(&column_transform.column)] =
+                    Some(transforms.create_transformer(&column_transform.transform));
+            }
+
+            let original_sort_columns = if keep_original_sort_values {
+                cfg.original_sort_columns()
+                    .iter()
+                    .map(|c| position(c))
+                    .collect()
+            } else {
+                Vec::new()
+            };
+
+            transforms.outputs.insert(
+                cfg.output.clone(),
+                OutputTransforms {
+                    columns,
+                    original_sort_columns,
+                },
+            );
+        }
+
+        transforms
+    }
+
+    /// Returns the transforms of an output file or `None` if it has no transforms.
+    pub fn get(&self, output: &str) -> Option<&OutputTransforms> {
+        self.outputs.get(output)
+    }
+
+    /// Writes all token lookup files that received new tokens.
+    ///
+    /// # Panics
+    /// This function will panic if a lookup file cannot be written.
+    pub fn save(&self) {
+        for (path, token_map) in &self.token_maps {
+            let token_map = token_map.lock().unwrap();
+            if token_map.changed {
+                token_map.write(path);
+            }
+        }
+    }
+
+    fn create_transformer(&mut self, transform: &Transform) -> Transformer {
+        match transform {
+            Transform::Redact { placeholder } => Transformer::Redact(placeholder.clone()),
+            Transform::Mask {
+                keep_last,
+                mask_char,
+            } => Transformer::Mask(*keep_last, *mask_char),
+            Transform::Hash { key_env, key_file } => {
+                Transformer::Hash(read_key(key_env.as_deref(), key_file.as_deref()))
+            }
+            Transform::Tokenize {
+                lookup_file,
+                prefix,
+            } => {
+                let token_map = self
+                    .token_maps
+                    .entry(lookup_file.clone())
+                    .or_insert_with(|| Arc::new(Mutex::new(TokenMap::read(lookup_file))));
+                Transformer::Tokenize(token_map.clone(), prefix.clone())
+            }
+        }
+    }
+}
+
+/// Reads a hash key from an environment variable or a file. Trailing line breaks of key files
+/// are ignored.
+///
+/// # Panics
+/// This function will panic if the key cannot be read or is empty.
+fn read_key(key_env: Option<&str>, key_file: Option<&str>) -> Vec<u8> {
+    let key = match (key_env, key_file) {
+        (Some(var), _) => std::env::var(var)
+            .unwrap_or_else(|_| panic!("Cannot read hash key from environment variable '{}'", var))
+            .into_bytes(),
+        (None, Some(path)) => {
+            let mut key = fs::read(path)
+                .unwrap_or_else(|e| panic!("Cannot read hash key file '{}': {}", path, e));
+            while key.last().is_some_and(|b| *b == b'\n' || *b == b'\r') {
+                key.pop();
+            }
+            key
+        }
+        (None, None) => panic!("No hash key configured"),
+    };
+
+    if key.is_empty() {
+        panic!("Hash key must not be empty");
+    }
+    key
+}
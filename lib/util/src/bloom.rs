@@ -0,0 +1,62 @@
+use std::collections::hash_map::DefaultHasher;
+use std::f64::consts::LN_2;
+use std::hash::{Hash, Hasher};
+
+/// A bloom filter, i.e. a set that may report values as contained that have never been inserted
+/// (false positives), but uses a fixed amount of memory regardless of the size of its values.
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// Creates an empty bloom filter sized for the expected number of values.
+    ///
+    /// # Arguments
+    /// * `expected_values` - The number of values that will be inserted
+    /// * `false_positive_rate` - The targeted probability of false positives (between 0 and 1)
+    pub fn new(expected_values: usize, false_positive_rate: f64) -> Self {
+        let n = expected_values.max(1) as f64;
+        let num_bits = (-n * false_positive_rate.ln() / (LN_2 * LN_2))
+            .ceil()
+            .max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / n) * LN_2).round().max(1.0) as u32;
+
+        BloomFilter {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+        }
+    }
+
+    /// Inserts a value.
+    pub fn insert(&mut self, value: &str) {
+        for index in self.bit_indexes(value) {
+            self.bits[(index / 64) as usize] |= 1 << (index % 64);
+        }
+    }
+
+    /// Checks if a value may have been inserted. Returns `false` only if it has definitely not
+    /// been inserted.
+    pub fn contains(&self, value: &str) -> bool {
+        self.bit_indexes(value)
+            .all(|index| self.bits[(index / 64) as usize] & (1 << (index % 64)) != 0)
+    }
+
+    /// Derives the bit indexes of a value from two hashes (double hashing).
+    fn bit_indexes(&self, value: &str) -> impl Iterator<Item = u64> {
+        let h1 = hash(value, 0);
+        let h2 = hash(value, 1) | 1;
+        let num_bits = self.num_bits;
+        (0..u64::from(self.num_hashes)).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+}
+
+fn hash(value: &str, seed: u8) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    seed.hash(&mut hasher);
+    value.hash(&mut hasher);
+    hasher.finish()
+}
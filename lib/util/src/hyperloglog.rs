@@ -0,0 +1,70 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// The number of hash bits that select a register.
+const PRECISION: u32 = 12;
+
+/// The number of registers.
+const NUM_REGISTERS: usize = 1 << PRECISION;
+
+/// A HyperLogLog sketch, i.e. a counter that estimates the number of distinct values using a
+/// fixed amount of memory (4 KiB) with a standard error of about 1.6%.
+#[derive(Debug, Clone)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        HyperLogLog::new()
+    }
+}
+
+impl HyperLogLog {
+    /// Creates an empty sketch.
+    pub fn new() -> Self {
+        HyperLogLog {
+            registers: vec![0; NUM_REGISTERS],
+        }
+    }
+
+    /// Inserts a value.
+    pub fn insert(&mut self, value: &str) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let index = (hash >> (64 - PRECISION)) as usize;
+        let rank = ((hash << PRECISION) | (1 << (PRECISION - 1))).leading_zeros() as u8 + 1;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+        }
+    }
+
+    /// Merges another sketch into this one. Afterwards, this sketch estimates the number of
+    /// distinct values inserted into either of both.
+    pub fn merge(&mut self, other: &HyperLogLog) {
+        for (register, other) in self.registers.iter_mut().zip(&other.registers) {
+            *register = (*register).max(*other);
+        }
+    }
+
+    /// Returns the estimated number of distinct values.
+    pub fn estimate(&self) -> usize {
+        let m = NUM_REGISTERS as f64;
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|&r| 2f64.powi(-i32::from(r)))
+            .sum();
+        let estimate = 0.7213 / (1.0 + 1.079 / m) * m * m / sum;
+
+        // Small cardinalities are estimated more accurately by linear counting.
+        let empty_registers = self.registers.iter().filter(|&&r| r == 0).count();
+        if estimate <= 2.5 * m && empty_registers > 0 {
+            (m * (m / empty_registers as f64).ln()).round() as usize
+        } else {
+            estimate.round() as usize
+        }
+    }
+}
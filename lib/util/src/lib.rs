@@ -1,6 +1,16 @@
+pub mod bloom;
+pub mod hyperloglog;
+pub mod progress;
+
 use std::fs;
 use std::fs::File;
-use std::path::{Path, PathBuf};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Checks if a run has been cancelled through its cancellation flag, if it has one.
+pub fn is_cancelled(cancel: Option<&AtomicBool>) -> bool {
+    cancel.is_some_and(|c| c.load(Ordering::Relaxed))
+}
 
 /// Checks if a directory of a file does exist on a given path.
 pub fn path_exists(path: &str) -> bool {
@@ -13,21 +23,27 @@ pub fn path_exists(path: &str) -> bool {
 /// # Panics
 /// This function panics on any error.
 pub fn create_file(file_path: &Path) -> File {
-    let parent_path = file_path.parent().expect(&format!(
-        "Output file path of config with file path '{:?}' seems to be broken:",
-        file_path
-    ));
-    fs::create_dir_all(parent_path).expect(&format!(
-        "Creating output directory for config with file path '{:?}' failed:",
-        file_path
-    ));
+    let parent_path = file_path.parent().unwrap_or_else(|| {
+        panic!(
+            "Output file path of config with file path '{:?}' seems to be broken:",
+            file_path
+        )
+    });
+    fs::create_dir_all(parent_path).unwrap_or_else(|e| {
+        panic!(
+            "Creating output directory for config with file path '{:?}' failed: {:?}",
+            file_path, e
+        )
+    });
 
-    File::create(file_path).expect(&format!(
-        "Creating output file for config with file path '{:?}' failed:",
-        file_path
-    ))
+    File::create(file_path).unwrap_or_else(|e| {
+        panic!(
+            "Creating output file for config with file path '{:?}' failed: {:?}",
+            file_path, e
+        )
+    })
 }
 
-pub fn path_to_string(path: &PathBuf) -> String {
-    path.clone().as_os_str().to_str().unwrap().to_string()
+pub fn path_to_string(path: &Path) -> String {
+    path.as_os_str().to_str().unwrap().to_string()
 }
@@ -0,0 +1,133 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+/// If progress indicators are drawn. Disabled by default, so that nothing is drawn unless the
+/// application knows that stderr is a terminal.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// If a progress indicator is currently visible on the last line of stderr.
+static DRAWN: AtomicBool = AtomicBool::new(false);
+
+/// The width of the bar in characters.
+const BAR_WIDTH: usize = 30;
+
+/// The minimum time between two redraws.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Enables or disables drawing progress indicators to stderr.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Removes a visible progress indicator, so that other output can be written to stderr. The
+/// indicator is drawn again on its next update.
+pub fn clear() {
+    if DRAWN.swap(false, Ordering::Relaxed) {
+        eprint!("\r\x1b[2K");
+    }
+}
+
+/// A progress indicator for reading a file, which shows the share of bytes consumed, the number
+/// of rows per second and the estimated time remaining.
+pub struct Progress {
+    total_bytes: u64,
+    rows: usize,
+    start: Instant,
+    last_draw: Instant,
+    enabled: bool,
+}
+
+impl Progress {
+    /// Creates a progress indicator. Nothing is drawn if progress indicators are disabled.
+    ///
+    /// # Arguments
+    /// * `total_bytes` - The size of the file that is read
+    pub fn new(total_bytes: u64) -> Self {
+        let now = Instant::now();
+        Progress {
+            total_bytes,
+            rows: 0,
+            start: now,
+            last_draw: now,
+            enabled: ENABLED.load(Ordering::Relaxed) && total_bytes > 0,
+        }
+    }
+
+    /// Records that one more row has been read.
+    ///
+    /// # Arguments
+    /// * `bytes` - The number of bytes consumed so far
+    pub fn update(&mut self, bytes: u64) {
+        self.rows += 1;
+        if !self.enabled || !self.rows.is_multiple_of(1000) {
+            return;
+        }
+        let now = Instant::now();
+        if now.duration_since(self.last_draw) < REDRAW_INTERVAL {
+            return;
+        }
+        self.last_draw = now;
+        self.draw(bytes, now.duration_since(self.start));
+    }
+
+    /// Removes the progress indicator once the file has been read.
+    pub fn finish(&mut self) {
+        if self.enabled {
+            clear();
+        }
+    }
+
+    fn draw(&self, bytes: u64, elapsed: Duration) {
+        let ratio = (bytes as f64 / self.total_bytes as f64).min(1.0);
+        let filled = (ratio * BAR_WIDTH as f64) as usize;
+        let seconds = elapsed.as_secs_f64();
+        let rows_per_second = self.rows as f64 / seconds;
+        let eta = if bytes > 0 {
+            format_duration(
+                seconds * (self.total_bytes - bytes.min(self.total_bytes)) as f64 / bytes as f64,
+            )
+        } else {
+            String::from("--:--:--")
+        };
+
+        let stderr = io::stderr();
+        let mut stderr = stderr.lock();
+        let _ = write!(
+            stderr,
+            "\r\x1b[2K[{}{}] {:>3}% {}/{} {:.0} rows/s ETA {}",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            (ratio * 100.0) as u32,
+            format_bytes(bytes),
+            format_bytes(self.total_bytes),
+            rows_per_second,
+            eta
+        );
+        let _ = stderr.flush();
+        DRAWN.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Formats a number of bytes using binary units.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Formats a number of seconds as `hh:mm:ss`.
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
@@ -0,0 +1,193 @@
+use crate::logging;
+use config::FilterConfig;
+use csv::Position;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The number of bytes read at once while searching the input file for its last line break.
+const CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Records how far an append-only input file has been consumed by previous runs.
+#[derive(Debug, Serialize, Deserialize)]
+struct IncrementalState {
+    /// Path of the input file
+    input: String,
+    /// The header row of the input file
+    headers: Vec<String>,
+    /// SHA-256 hash of the configuration file, since output files of another configuration
+    /// cannot be appended to
+    config_sha256: String,
+    /// SHA-256 hash of the first bytes of the input file and the bytes before the consumed
+    /// position
+    input_sha256: String,
+    /// The byte offset in the input file after the last consumed row
+    byte: u64,
+    /// The line number in the input file after the last consumed row
+    line: u64,
+    /// The number of records (including the header row) consumed so far
+    record: u64,
+    /// The time of the last run in RFC 3339 format
+    updated_at: String,
+}
+
+/// Decides if only the rows appended to the input file since the last run need to be processed.
+/// Returns the position to continue reading from, or `None` if the whole input file needs to be
+/// processed. The reason for processing the whole input file is logged.
+///
+/// # Arguments
+/// * `state_file_path` - Path of the state file written by the last run
+/// * `csv_file_path` - Path to the input file
+/// * `config_file_path` - Path to the configuration file
+/// * `output_dir_path` - Path to the directory containing all output files
+/// * `all_filter_configs` - A vector containing all configuration items
+pub fn resolve_start(
+    state_file_path: &str,
+    csv_file_path: &str,
+    config_file_path: &str,
+    output_dir_path: &str,
+    all_filter_configs: &[Arc<FilterConfig>],
+) -> Option<Position> {
+    let state = match read_state(state_file_path) {
+        Some(state) => state,
+        None => {
+            info!("No state of a previous run found, processing the whole input file");
+            return None;
+        }
+    };
+
+    let reason = if let Some(config) = all_filter_configs.iter().find(|c| c.aggregate.is_some()) {
+        Some(format!("output file '{}' is aggregated", config.output))
+    } else if state.input != csv_file_path {
+        Some(format!(
+            "the last run processed input file '{}'",
+            state.input
+        ))
+    } else if state.config_sha256 != file_sha256(config_file_path) {
+        Some(String::from("the configuration has changed"))
+    } else if let Some(config) = all_filter_configs
+        .iter()
+        .find(|c| !Path::new(output_dir_path).join(&c.output).exists())
+    {
+        Some(format!("output file '{}' does not exist", config.output))
+    } else if !is_same_input(&state, csv_file_path) {
+        Some(String::from("the input file has been rotated"))
+    } else {
+        None
+    };
+
+    if let Some(reason) = reason {
+        info!("Processing the whole input file, because {}", reason);
+        return None;
+    }
+
+    info!(
+        "Processing rows of input file '{}' after byte {}",
+        csv_file_path, state.byte
+    );
+    let mut position = Position::new();
+    position
+        .set_byte(state.byte)
+        .set_line(state.line)
+        .set_record(state.record);
+    Some(position)
+}
+
+/// Returns the byte offset after the last complete line of the input file, so that a row which
+/// is still being appended is left for the next run.
+///
+/// # Panics
+/// This function will panic if the input file cannot be read.
+pub fn complete_lines_end(csv_file_path: &str) -> u64 {
+    let read_end = || -> io::Result<u64> {
+        let mut file = File::open(csv_file_path)?;
+        let mut end = file.metadata()?.len();
+        let mut buffer = vec![0; CHUNK_SIZE as usize];
+        while end > 0 {
+            let start = end.saturating_sub(CHUNK_SIZE);
+            let chunk = &mut buffer[..(end - start) as usize];
+            file.seek(SeekFrom::Start(start))?;
+            file.read_exact(chunk)?;
+            if let Some(index) = chunk.iter().rposition(|&b| b == b'\n') {
+                return Ok(start + index as u64 + 1);
+            }
+            end = start;
+        }
+        Ok(0)
+    };
+    read_end().unwrap_or_else(|e| panic!("Cannot read input file '{}': {}", csv_file_path, e))
+}
+
+/// Writes the state of a successful run next to the state file and syncs it to disk. Returns the
+/// path of the staged state file, which replaces the state file once the output files are
+/// committed (see [`crate::output::OutputSet::commit`]).
+///
+/// # Arguments
+/// * `state_file_path` - Path of the state file
+/// * `csv_file_path` - Path to the input file
+/// * `config_file_path` - Path to the configuration file
+/// * `end` - The position in the input file after the last row that was processed
+///
+/// # Panics
+/// This function will panic if the state file cannot be written.
+pub fn stage_state(
+    state_file_path: &str,
+    csv_file_path: &str,
+    config_file_path: &str,
+    end: &Position,
+) -> PathBuf {
+    let state = IncrementalState {
+        input: csv_file_path.to_string(),
+        headers: filter::read_input_headers(csv_file_path).unwrap_or_default(),
+        config_sha256: file_sha256(config_file_path),
+        input_sha256: filter::input_fingerprint(csv_file_path, end.byte())
+            .unwrap_or_else(|e| panic!("Cannot read input file '{}': {}", csv_file_path, e)),
+        byte: end.byte(),
+        line: end.line(),
+        record: end.record(),
+        updated_at: logging::timestamp(),
+    };
+
+    let json = serde_json::to_string_pretty(&state).expect("Cannot serialize state");
+    let staged = PathBuf::from(format!("{}.tmp", state_file_path));
+    File::create(&staged)
+        .and_then(|mut file| {
+            file.write_all((json + "\n").as_bytes())?;
+            file.sync_all()
+        })
+        .unwrap_or_else(|e| panic!("Cannot write state file '{}': {}", staged.display(), e));
+    staged
+}
+
+/// Reads the state file. Returns `None` if it does not exist or cannot be parsed.
+fn read_state(state_file_path: &str) -> Option<IncrementalState> {
+    let json = fs::read_to_string(state_file_path).ok()?;
+    serde_json::from_str(&json)
+        .map_err(|e| warn!("Ignoring state file '{}': {}", state_file_path, e))
+        .ok()
+}
+
+/// Checks if the input file still starts with the rows consumed by the last run.
+fn is_same_input(state: &IncrementalState, csv_file_path: &str) -> bool {
+    let headers = filter::read_input_headers(csv_file_path).unwrap_or_default();
+    let size = fs::metadata(csv_file_path).map(|m| m.len()).unwrap_or(0);
+    headers == state.headers
+        && size >= state.byte
+        && filter::input_fingerprint(csv_file_path, state.byte)
+            .ok()
+            .as_ref()
+            == Some(&state.input_sha256)
+}
+
+/// Hashes the contents of a file. Returns an empty string if the file cannot be read.
+fn file_sha256(path: &str) -> String {
+    match fs::read(path) {
+        Ok(contents) => format!("{:x}", Sha256::digest(&contents)),
+        Err(_) => String::new(),
+    }
+}
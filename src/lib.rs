@@ -1,20 +1,114 @@
+extern crate chrono;
+extern crate csv;
 extern crate csv_filter_config as config;
+extern crate csv_filter_distinct as distinct;
+extern crate csv_filter_expression as expression;
 extern crate csv_filter_filter as filter;
 extern crate csv_filter_sort as sort;
+extern crate csv_filter_stats as stats;
+extern crate csv_filter_util as util;
+extern crate log;
+extern crate notify;
+extern crate serde_json;
+extern crate sha2;
 
-use config::FilterConfig;
+mod incremental;
+mod logging;
+mod output;
+mod plan;
+mod query;
+mod report;
+mod sql;
+mod validation;
+mod watch;
+
+pub use config::{ConfigFormat, Diagnostic};
+pub use logging::{init_logging, LogFormat};
+pub use output::{IfExists, MANIFEST_FILE_NAME};
+pub use plan::{InputPlan, OutputPlan, Plan};
+pub use query::{Query, QueryError};
+pub use sql::SqlQuery;
+pub use stats::{ColumnStats, ColumnType, InputStats, StatsOptions, ValueCount};
+pub use watch::watch;
+
+use config::{ColumnFilter, FilterConfig};
 use core::cmp;
+use filter::{ColumnFilterPredicate, FileSink, FilterOptions};
+use log::{info, warn};
+use output::OutputSet;
+use report::{RunReport, StageTimings};
 use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
+
+/// Settings that control how files are processed.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// If sorting output files should be disabled
+    pub no_sort: bool,
+    /// Number of threads to use in the filtering stage
+    pub filter_parallelism: usize,
+    /// Number of threads to use in the sorting stage (this implicitly sets the amount of files
+    /// that can be sorted at a time)
+    pub sort_parallelism: usize,
+    /// If the input file should be memory-mapped (falls back to buffered reading for pipes, stdin
+    /// and compressed files)
+    pub use_mmap: bool,
+    /// The format of the configuration file. Determined by the file extension if absent.
+    pub config_format: Option<ConfigFormat>,
+    /// If only the processing plan should be returned instead of processing the input file. No
+    /// files are created in dry-run mode.
+    pub dry_run: bool,
+    /// Path of a JSON file that a report with record counts and timings of the run is written to
+    pub report: Option<String>,
+    /// What happens to output files that already exist in the output directory
+    pub if_exists: IfExists,
+    /// The number of input rows between two checkpoints of the filter stage. Zero disables
+    /// checkpoints.
+    pub checkpoint_interval: usize,
+    /// If an interrupted run should be continued from its last checkpoint
+    pub resume: bool,
+    /// Path of a state file that records how far the input file has been consumed. If present,
+    /// only rows appended to the input file since the last run are processed and appended to
+    /// the output files.
+    pub state: Option<String>,
+}
+
+/// Settings that control how a starter configuration is generated.
+#[derive(Debug, Clone)]
+pub struct InitOptions {
+    /// The name of the output file of the generated configuration item
+    pub output: String,
+    /// Columns with at most this many distinct values in the sampled rows get their values
+    /// listed. Zero disables listing values.
+    pub max_values: usize,
+    /// The number of rows that are sampled to detect distinct values
+    pub sample_rows: usize,
+    /// The format of the generated configuration
+    pub format: ConfigFormat,
+}
+
+impl Default for InitOptions {
+    fn default() -> Self {
+        InitOptions {
+            output: String::from("output.csv"),
+            max_values: 0,
+            sample_rows: 10_000,
+            format: ConfigFormat::Json,
+        }
+    }
+}
 
 /// # Arguments
 /// * `csv_file_path` - Path to the CSV file that should be processed
 /// * `config_file_path` - Path to the configuration file
 /// * `output_dir_path` - Path to the directory that data should be written to
-/// * `no_sort` - If sorting output files should be disabled
-/// * `filter_parallelism` - Number of threads to use in the filtering stage.
-/// * `sort_parallelism` -  Number of threads to use in the sorting stage (this implicitly sets
-///                         the amount of files that can be sorted at a time)
+/// * `options` - Settings that control how files are processed
+///
+/// Returns the plan of how the input file would be processed in dry-run mode, in which nothing
+/// else happens. An input file that cannot be read is reported by the plan.
 ///
 /// # Panics
 /// This function will panic on any error.
@@ -22,99 +116,581 @@ pub fn process(
     csv_file_path: &str,
     config_file_path: &str,
     output_dir_path: &str,
-    no_sort: bool,
-    filter_parallelism: usize,
-    sort_parallelism: usize,
-) {
-    let filter_max_threads = cmp::max(1, filter_parallelism);
-    println!(
+    options: &Options,
+) -> Option<Plan> {
+    if options.dry_run {
+        let format = resolve_format(config_file_path, options.config_format);
+        let all_filter_configs = read_filter_configs(config_file_path, format);
+        return Some(plan::create_plan(
+            csv_file_path,
+            &all_filter_configs,
+            output_dir_path,
+            format,
+            options,
+        ));
+    }
+
+    run(
+        csv_file_path,
+        config_file_path,
+        output_dir_path,
+        options,
+        None,
+    );
+    None
+}
+
+/// Processes a query given on the command line instead of a configuration file. The query is
+/// turned into a single configuration item that is processed like one read from a file.
+///
+/// # Arguments
+/// * `csv_file_path` - Path to the CSV file that should be processed
+/// * `query` - The conditions, output columns and sort columns of the output file
+/// * `output_dir_path` - Path to the directory that data should be written to
+/// * `options` - Settings that control how files are processed
+///
+/// Returns the plan of how the input file would be processed in dry-run mode, like [`process`].
+///
+/// # Panics
+/// This function will panic on any error other than an invalid query, or if an incremental run is
+/// requested, since incremental runs depend on a configuration file.
+pub fn process_query(
+    csv_file_path: &str,
+    query: &Query,
+    output_dir_path: &str,
+    options: &Options,
+) -> Result<Option<Plan>, QueryError> {
+    let input_headers = read_query_headers(csv_file_path, options);
+    let config = query::build_config(query, &input_headers)?;
+    Ok(run_query_configs(
+        csv_file_path,
+        vec![config],
+        &input_headers,
+        output_dir_path,
+        options,
+    ))
+}
+
+/// Processes SQL-like statements instead of a configuration file. Every statement is turned into
+/// a configuration item that is processed like one read from a file.
+///
+/// # Arguments
+/// * `csv_file_path` - Path to the CSV file that should be processed
+/// * `sql` - The statements, each describing one output file
+/// * `output_dir_path` - Path to the directory that data should be written to
+/// * `options` - Settings that control how files are processed
+///
+/// Returns the plan of how the input file would be processed in dry-run mode, like [`process`].
+///
+/// # Panics
+/// This function will panic on any error other than an invalid statement, or if an incremental
+/// run is requested, since incremental runs depend on a configuration file.
+pub fn process_sql(
+    csv_file_path: &str,
+    sql: &SqlQuery,
+    output_dir_path: &str,
+    options: &Options,
+) -> Result<Option<Plan>, QueryError> {
+    let input_headers = read_query_headers(csv_file_path, options);
+    let configs = sql::compile(sql, &input_headers)?;
+    Ok(run_query_configs(
+        csv_file_path,
+        configs,
+        &input_headers,
+        output_dir_path,
+        options,
+    ))
+}
+
+/// Reads the header row that queries are checked against.
+///
+/// # Panics
+/// This function will panic if the headers cannot be read or if an incremental run is requested.
+fn read_query_headers(csv_file_path: &str, options: &Options) -> Vec<String> {
+    if options.state.is_some() {
+        panic!("Incremental runs require a configuration file");
+    }
+
+    filter::read_input_headers(csv_file_path).unwrap_or_else(|e| {
+        panic!(
+            "Cannot read headers of input file '{}': {}",
+            csv_file_path, e
+        )
+    })
+}
+
+/// Validates and processes the configuration items of a query. Returns the plan in dry-run mode.
+///
+/// # Panics
+/// This function will panic if the configuration items are invalid or on any processing error.
+fn run_query_configs(
+    csv_file_path: &str,
+    configs: Vec<FilterConfig>,
+    input_headers: &[String],
+    output_dir_path: &str,
+    options: &Options,
+) -> Option<Plan> {
+    let mut diagnostics = validation::validate_configs(&configs, ConfigFormat::Json);
+    diagnostics.extend(validation::validate_headers(
+        &configs,
+        ConfigFormat::Json,
+        input_headers,
+    ));
+    let errors: Vec<Diagnostic> = diagnostics.into_iter().filter(|d| d.is_error()).collect();
+    if !errors.is_empty() {
+        panic!("Invalid query:\n{}", join_diagnostics(&errors));
+    }
+
+    let all_filter_configs: Vec<Arc<FilterConfig>> = configs.into_iter().map(Arc::new).collect();
+    if options.dry_run {
+        return Some(plan::create_plan(
+            csv_file_path,
+            &all_filter_configs,
+            output_dir_path,
+            ConfigFormat::Json,
+            options,
+        ));
+    }
+
+    run_configs(
+        csv_file_path,
+        all_filter_configs,
+        ConfigSource::Query,
+        output_dir_path,
+        options,
+        None,
+    );
+    None
+}
+
+/// Where the configuration items of a run come from.
+#[derive(Clone, Copy)]
+enum ConfigSource<'a> {
+    /// A configuration file with its path
+    File(&'a str),
+    /// A query or SQL-like statements given instead of a configuration file
+    Query,
+}
+
+/// Processes files like [`process`]. Returns the report of the run unless nothing was processed.
+///
+/// # Arguments
+/// * `csv_file_path` - Path to the CSV file that should be processed
+/// * `config_file_path` - Path to the configuration file
+/// * `output_dir_path` - Path to the directory that data should be written to
+/// * `options` - Settings that control how files are processed
+/// * `cancel` - Once set, the run stops after the current record of the filter stage or after
+///   the current stage, leaving all output files untouched. The report is created even if no
+///   report file is written.
+///
+/// # Panics
+/// This function will panic on any error.
+fn run(
+    csv_file_path: &str,
+    config_file_path: &str,
+    output_dir_path: &str,
+    options: &Options,
+    cancel: Option<&AtomicBool>,
+) -> Option<RunReport> {
+    let format = resolve_format(config_file_path, options.config_format);
+    let all_filter_configs = read_filter_configs(config_file_path, format);
+    run_configs(
+        csv_file_path,
+        all_filter_configs,
+        ConfigSource::File(config_file_path),
+        output_dir_path,
+        options,
+        cancel,
+    )
+}
+
+/// Processes files according to the given configuration items. Returns the report of the run
+/// unless nothing was processed.
+///
+/// # Arguments
+/// * `csv_file_path` - Path to the CSV file that should be processed
+/// * `all_filter_configs` - A vector containing all configuration items
+/// * `config_source` - Where the configuration items come from
+/// * `output_dir_path` - Path to the directory that data should be written to
+/// * `options` - Settings that control how files are processed
+/// * `cancel` - Once set, the run stops early, leaving all output files untouched (see [`run`])
+///
+/// # Panics
+/// This function will panic on any error.
+fn run_configs(
+    csv_file_path: &str,
+    all_filter_configs: Vec<Arc<FilterConfig>>,
+    config_source: ConfigSource,
+    output_dir_path: &str,
+    options: &Options,
+    cancel: Option<&AtomicBool>,
+) -> Option<RunReport> {
+    let start = Instant::now();
+    let cancelled = || {
+        let cancelled = cancel.is_some_and(|c| c.load(Ordering::Relaxed));
+        if cancelled {
+            info!("Run cancelled");
+        }
+        cancelled
+    };
+    let config_file_path = match config_source {
+        ConfigSource::File(path) => Some(path),
+        ConfigSource::Query => None,
+    };
+
+    // Output files and the state of the previous run must be complete before they are used
+    output::complete_interrupted_commit(output_dir_path);
+
+    // Incremental runs append the rows of the new tail of the input file to the output files
+    let state = options.state.as_ref().zip(config_file_path);
+    let incremental_start = state.and_then(|(state_file_path, config_file_path)| {
+        incremental::resolve_start(
+            state_file_path,
+            csv_file_path,
+            config_file_path,
+            output_dir_path,
+            &all_filter_configs,
+        )
+    });
+    let options = &Options {
+        if_exists: if incremental_start.is_some() {
+            IfExists::Append
+        } else {
+            options.if_exists
+        },
+        ..options.clone()
+    };
+
+    // Output files must not escape the output directory through symbolic links inside it
+    let format = config_file_path.map_or(ConfigFormat::Json, |path| {
+        resolve_format(path, options.config_format)
+    });
+    let mut errors = validation::validate_output_dir(&all_filter_configs, format, output_dir_path);
+    if output::merges_appended_rows(options) {
+        errors.extend(validation::validate_append(&all_filter_configs, format));
+    }
+    if !errors.is_empty() {
+        panic!("Invalid output files:\n{}", join_diagnostics(&errors));
+    }
+
+    let all_filter_configs =
+        output::resolve_existing_outputs(all_filter_configs, output_dir_path, options.if_exists);
+    if all_filter_configs.is_empty() {
+        info!("All output files already exist");
+        return None;
+    }
+    let filter_max_threads = cmp::max(1, options.filter_parallelism);
+    info!(
         "Using up to {} threads for the filter stage",
         filter_max_threads
     );
 
-    let all_filter_configs = read_filter_configs(config_file_path);
+    // All stages work on staged files, so that existing output files stay intact until the new
+    // ones are complete
+    let output_set = OutputSet::new(output_dir_path, options);
+    let staging_dir_path = output_set.staging_dir_path();
+    let mut sink = match output_set
+        .checkpoints()
+        .and_then(|c| c.resume_from.as_ref())
+    {
+        Some(checkpoint) => FileSink::resume(&staging_dir_path, checkpoint),
+        None => FileSink::new(&staging_dir_path),
+    };
 
-    filter::filter(
+    let mut timings = StageTimings::default();
+    let stage_start = Instant::now();
+    let filter_stats = filter::filter(
         csv_file_path,
         &all_filter_configs,
-        output_dir_path,
-        filter_max_threads,
+        &ColumnFilterPredicate,
+        &mut sink,
+        &FilterOptions {
+            max_threads: filter_max_threads,
+            use_mmap: options.use_mmap,
+            sort_enabled: !options.no_sort,
+            checkpoints: output_set.checkpoints(),
+            start: incremental_start,
+            end: options
+                .state
+                .as_ref()
+                .map(|_| incremental::complete_lines_end(csv_file_path)),
+            cancel,
+        },
     );
+    timings.filter_ms = stage_start.elapsed().as_millis();
+    if cancelled() {
+        return None;
+    }
 
-    if !no_sort {
-        let sort_max_threads = cmp::max(1, sort_parallelism);
-        println!(
+    let sort_max_threads = cmp::max(1, options.sort_parallelism);
+    if !options.no_sort {
+        info!(
             "Using up to {} threads for the sort stage",
             sort_max_threads
         );
 
-        sort::sort_output_files(&all_filter_configs, output_dir_path, sort_max_threads);
+        let stage_start = Instant::now();
+        sort::sort_output_files(
+            &all_filter_configs,
+            &staging_dir_path,
+            sort_max_threads,
+            cancel,
+        );
+        timings.sort_ms = Some(stage_start.elapsed().as_millis());
+        if cancelled() {
+            return None;
+        }
+    }
+
+    // Sorted files have already been deduplicated in the sort stage
+    let stage_start = Instant::now();
+    distinct::distinct_output_files(
+        &all_filter_configs,
+        &staging_dir_path,
+        !options.no_sort,
+        sort_max_threads,
+        cancel,
+    );
+    timings.distinct_ms = stage_start.elapsed().as_millis();
+    if cancelled() {
+        return None;
+    }
+
+    // The state is committed along with the output files, so that rows are neither lost nor
+    // appended twice if the run is interrupted
+    let staged_state = state.map(|(state_file_path, config_file_path)| {
+        let staged = incremental::stage_state(
+            state_file_path,
+            csv_file_path,
+            config_file_path,
+            filter_stats.end_position.as_ref().unwrap(),
+        );
+        (staged, Path::new(state_file_path))
+    });
+    output_set.commit(csv_file_path, &all_filter_configs, staged_state);
+
+    // Creating the report reads all output files, which is only needed for a report file or to
+    // compare consecutive runs in watch mode
+    if options.report.is_none() && cancel.is_none() {
+        return None;
+    }
+    timings.total_ms = start.elapsed().as_millis();
+    let report = RunReport::new(
+        csv_file_path,
+        &all_filter_configs,
+        output_dir_path,
+        filter_stats,
+        (filter_max_threads, sort_max_threads),
+        timings,
+    );
+    if let Some(report_file_path) = &options.report {
+        report.write(report_file_path);
     }
+    Some(report)
 }
 
-/// Reads all filter configurations from a config file. Returns a list of [`FilterConfig`] with
-/// the contents from the config file.
+/// Validates a configuration file without processing any data. Returns all errors and warnings
+/// that were found.
 ///
 /// # Arguments
-/// * `file_path` - Path of the JSON configuration file
-fn read_filter_configs(file_path: &str) -> Vec<Arc<FilterConfig>> {
-    let json = fs::read_to_string(file_path).expect("Cannot read config file");
-    let mut read_configs = config::deserialize(&json);
+/// * `config_file_path` - Path to the configuration file
+/// * `csv_file_path` - Path to an input CSV file. If present, all columns referenced by the
+///   configuration are checked to be part of its header row.
+/// * `config_format` - The format of the configuration file. Determined by the file extension
+///   if absent.
+pub fn validate(
+    config_file_path: &str,
+    csv_file_path: Option<&str>,
+    config_format: Option<ConfigFormat>,
+) -> Vec<Diagnostic> {
+    let format = resolve_format(config_file_path, config_format);
+    let source = match fs::read_to_string(config_file_path) {
+        Ok(source) => source,
+        Err(e) => {
+            return vec![Diagnostic::error(
+                String::from("$"),
+                format!("Cannot read config file '{}': {}", config_file_path, e),
+            )]
+        }
+    };
+    let (configs, mut diagnostics) = match parse_config(&source, config_file_path, format) {
+        Ok(parsed) => parsed,
+        Err(diagnostics) => return diagnostics,
+    };
 
-    for config in &read_configs {
-        validate_config(&config).expect("Invalid configuration");
+    if let Some(path) = csv_file_path {
+        match filter::read_input_headers(path) {
+            Ok(headers) => {
+                diagnostics.extend(validation::validate_headers(&configs, format, &headers))
+            }
+            Err(e) => diagnostics.push(Diagnostic::error(
+                String::from("$"),
+                format!("Cannot read headers of input file '{}': {}", path, e),
+            )),
+        }
     }
 
-    let mut filters: Vec<Arc<FilterConfig>> = Vec::new();
-    while let Some(fc) = read_configs.pop() {
-        filters.push(Arc::new(fc))
-    }
+    diagnostics
+}
 
-    filters
+/// Profiles an input file, which helps to write configurations for it. Returns the number of rows
+/// and, per column, the inferred type, empty and null counts, the number of distinct values,
+/// minimum, maximum and the most frequent values.
+///
+/// # Arguments
+/// * `csv_file_path` - Path to the CSV file that should be profiled
+/// * `options` - Settings that control how the input file is profiled
+///
+/// # Panics
+/// This function will panic if the input file cannot be read or parsed.
+pub fn stats(csv_file_path: &str, options: &StatsOptions) -> InputStats {
+    stats::collect(
+        csv_file_path,
+        &StatsOptions {
+            parallelism: cmp::max(1, options.parallelism),
+            ..options.clone()
+        },
+    )
 }
 
-/// Validates a [`FilterConfig`].
+/// Generates a starter configuration for an input file. The configuration contains one item
+/// with an included filter for every column of the input file. Columns with only few distinct
+/// values in the first rows of the input file optionally get their values listed.
 ///
 /// # Arguments
-/// * `config` - The config to validate.
-fn validate_config(config: &FilterConfig) -> Result<(), String> {
-    // Makes sure there is at least one column that will be included per output file
-    if config.filters.iter().all(|f| f.include == false) {
-        return Err(format!(
-            "Config for output file '{}' does not contain any output columns",
-            &config.output
-        ));
+/// * `csv_file_path` - Path to the CSV file to generate the configuration for
+/// * `options` - Settings that control how the configuration is generated
+///
+/// # Panics
+/// This function will panic if the input file cannot be read or parsed.
+pub fn init(csv_file_path: &str, options: &InitOptions) -> String {
+    let columns = if options.max_values > 0 {
+        stats::sample_distinct_values(csv_file_path, options.sample_rows, options.max_values)
+    } else {
+        filter::read_input_headers(csv_file_path)
+            .unwrap_or_else(|e| {
+                panic!(
+                    "Cannot read headers of input file '{}': {}",
+                    csv_file_path, e
+                )
+            })
+            .into_iter()
+            .map(|name| stats::ColumnValues { name, values: None })
+            .collect()
+    };
+
+    let config = FilterConfig {
+        filters: columns
+            .into_iter()
+            .map(|column| ColumnFilter {
+                column: column.name,
+                include: true,
+                values: column.values.map(|v| v.into_iter().collect()),
+                values_file: None,
+                values_from: None,
+                bloom_filter: None,
+                values_bloom_filter: None,
+                min: None,
+                max: None,
+            })
+            .collect(),
+        lookups: Vec::new(),
+        computed_columns: Vec::new(),
+        script: None,
+        output: options.output.clone(),
+        output_columns: None,
+        transforms: Vec::new(),
+        sort_columns: None,
+        descending_columns: Vec::new(),
+        distinct: None,
+        aggregate: None,
+    };
+
+    config::serialize_format(&[config], options.format)
+}
+
+/// Returns the given configuration file format or determines it by the file extension, defaulting
+/// to JSON.
+fn resolve_format(file_path: &str, format: Option<ConfigFormat>) -> ConfigFormat {
+    format
+        .or_else(|| ConfigFormat::from_path(Path::new(file_path)))
+        .unwrap_or(ConfigFormat::Json)
+}
+
+/// Deserializes and validates the contents of a configuration file. Returns the configuration
+/// items along with all diagnostics, or only the diagnostics if deserialization fails. Paths of
+/// files referenced by the configuration are resolved relative to the configuration file.
+///
+/// # Arguments
+/// * `source` - The contents of the configuration file
+/// * `config_file_path` - Path of the configuration file
+/// * `format` - The format of the configuration file
+fn parse_config(
+    source: &str,
+    config_file_path: &str,
+    format: ConfigFormat,
+) -> Result<(Vec<FilterConfig>, Vec<Diagnostic>), Vec<Diagnostic>> {
+    let mut parsed = config::deserialize_format(source, format)?;
+    let config_dir = Path::new(config_file_path)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    for config in &mut parsed.configs {
+        config.resolve_paths(config_dir);
     }
+    let mut diagnostics = parsed.diagnostics;
+    diagnostics.extend(validation::validate_configs(&parsed.configs, format));
+    Ok((parsed.configs, diagnostics))
+}
 
-    // Makes sure no config uses "min"/"max" values along with explicitly defined "values".
-    for cf in &config.filters {
-        if cf.values.is_some() && (cf.max.is_some() || cf.min.is_some()) {
-            return Err(format!(
-                "Config for output file '{}' defines values and a range (min/max)",
-                &config.output
-            ));
-        }
+/// Reads all filter configurations from a config file. Returns a list of [`FilterConfig`] with
+/// the contents from the config file. Warnings are printed.
+///
+/// # Arguments
+/// * `file_path` - Path of the configuration file
+/// * `format` - The format of the configuration file
+///
+/// # Panics
+/// This function will panic if the config file cannot be read, deserialized or validated.
+fn read_filter_configs(file_path: &str, format: ConfigFormat) -> Vec<Arc<FilterConfig>> {
+    let source = fs::read_to_string(file_path).expect("Cannot read config file");
+    let (read_configs, diagnostics) =
+        parse_config(&source, file_path, format).unwrap_or_else(|diagnostics| {
+            panic!(
+                "Cannot deserialize {} config file '{}':\n{}",
+                format,
+                file_path,
+                join_diagnostics(&diagnostics)
+            )
+        });
+
+    for warning in diagnostics.iter().filter(|d| !d.is_error()) {
+        warn!("{}", warning);
+    }
+    let errors: Vec<Diagnostic> = diagnostics.into_iter().filter(|d| d.is_error()).collect();
+    if !errors.is_empty() {
+        panic!(
+            "Invalid configuration file '{}':\n{}",
+            file_path,
+            join_diagnostics(&errors)
+        );
     }
 
-    // Makes sure all configs only use sort columns that do exist in the corresponding output file
-    if let Some(sort_columns) = &config.sort_columns {
-        let included_columns: Vec<String> = config
-            .filters
-            .iter()
-            .filter(|f| f.include)
-            .map(|f| f.column.to_string())
-            .collect();
-
-        for column in sort_columns {
-            if !included_columns.contains(column) {
-                return Err(format!(
-                    "Config for output file '{}' contains sort column '{}' which is not part of the output file",
-                    &config.output,
-                    column
-                ));
+    read_configs
+        .into_iter()
+        .map(|mut config| {
+            for column_filter in &mut config.filters {
+                column_filter.load_values();
             }
-        }
-    }
+            Arc::new(config)
+        })
+        .collect()
+}
 
-    Ok(())
+/// Formats a list of diagnostics with one diagnostic per line.
+fn join_diagnostics(diagnostics: &[Diagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(|d| format!("  {}", d))
+        .collect::<Vec<String>>()
+        .join("\n")
 }
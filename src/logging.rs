@@ -0,0 +1,111 @@
+use chrono::{DateTime, SecondsFormat};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fmt;
+use std::io::{self, IsTerminal, Write};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The formats log records can be written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// One human readable line per record
+    Text,
+    /// One JSON object per line, e.g. for log shippers
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "Unknown log format '{}' (expected text or json)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Writes log records to stderr, so that stdout only ever carries data.
+struct StderrLogger {
+    level: LevelFilter,
+    format: LogFormat,
+}
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = match self.format {
+            LogFormat::Text => format!("{:<5} {}", record.level(), record.args()),
+            LogFormat::Json => serde_json::json!({
+                "timestamp": timestamp(),
+                "level": record.level().to_string(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            })
+            .to_string(),
+        };
+
+        util::progress::clear();
+        let _ = writeln!(io::stderr().lock(), "{}", line);
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+/// Returns the current time in RFC 3339 format.
+pub(crate) fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    DateTime::from_timestamp(now.as_secs() as i64, now.subsec_nanos())
+        .map(|t| t.to_rfc3339_opts(SecondsFormat::Millis, true))
+        .unwrap_or_default()
+}
+
+/// Sets up logging to stderr. Errors and warnings are always logged unless `quiet` is set, in
+/// which case only errors are logged. Progress indicators are drawn if stderr is a terminal and
+/// records are logged as text.
+///
+/// # Arguments
+/// * `quiet` - Only log errors
+/// * `verbosity` - Log informational (0), debug (1) or trace (2 or more) records
+/// * `format` - The format records are written in
+///
+/// # Panics
+/// This function will panic if logging has already been set up.
+pub fn init_logging(quiet: bool, verbosity: u8, format: LogFormat) {
+    let level = match (quiet, verbosity) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Info,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+
+    log::set_boxed_logger(Box::new(StderrLogger { level, format }))
+        .expect("Logging has already been set up");
+    log::set_max_level(level);
+
+    util::progress::set_enabled(!quiet && format == LogFormat::Text && io::stderr().is_terminal());
+}
@@ -1,41 +1,336 @@
+use std::fs;
+use std::path::Path;
+use std::process;
 use std::time::Instant;
 extern crate csv_filter;
+extern crate log;
 
+use log::{error, info};
 use structopt::StructOpt;
 
 /// Holds command line parameters provided by the user.
 #[derive(StructOpt, Debug)]
 struct CommandLineParameters {
+    /// Only log errors
+    #[structopt(short = "q", long = "quiet", raw(global = "true"))]
+    quiet: bool,
+    /// Log debug (-v) or trace (-vv) messages
+    #[structopt(
+        short = "v",
+        long = "verbose",
+        parse(from_occurrences),
+        raw(global = "true")
+    )]
+    verbose: u8,
+    /// The format of log messages (text or json)
+    #[structopt(long = "log-format", default_value = "text", raw(global = "true"))]
+    log_format: csv_filter::LogFormat,
+    #[structopt(subcommand)]
+    command: Command,
+}
+
+/// Holds the subcommand and its command line parameters.
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Filters the input file and writes all output files
+    #[structopt(name = "run")]
+    Run(RunParameters),
+    /// Validates the configuration file, optionally against the headers of an input file
+    #[structopt(name = "validate")]
+    Validate(ValidateParameters),
+    /// Profiles the columns of an input file
+    #[structopt(name = "stats")]
+    Stats(StatsParameters),
+    /// Generates a starter configuration from the header row of an input file
+    #[structopt(name = "init")]
+    Init(InitParameters),
+}
+
+/// Holds command line parameters of the `run` subcommand.
+#[derive(StructOpt, Debug)]
+struct RunParameters {
     #[structopt(short, long)]
     input: String,
     #[structopt(short, long)]
-    configuration: String,
+    configuration: Option<String>,
     #[structopt(short, long, default_value = "output")]
     output: String,
+    #[structopt(long = "where", conflicts_with = "configuration")]
+    where_clause: Option<String>,
+    #[structopt(long = "select", conflicts_with = "configuration")]
+    select: Option<String>,
+    #[structopt(long = "sort-by", conflicts_with = "configuration")]
+    sort_by: Option<String>,
+    #[structopt(
+        long = "query",
+        raw(conflicts_with_all = r#"&["configuration", "where_clause", "select", "sort_by"]"#)
+    )]
+    query: Option<String>,
+    #[structopt(long = "out", default_value = "output.csv")]
+    out: String,
     #[structopt(short = "ns", long = "no-sort")]
     no_sort: bool,
     #[structopt(short = "fp", long = "filter-parallelism", default_value = "1")]
     filter_parallelism: usize,
     #[structopt(short = "sp", long = "sort-parallelism", default_value = "1")]
     sort_parallelism: usize,
+    #[structopt(short = "m", long = "mmap")]
+    mmap: bool,
+    #[structopt(long = "config-format")]
+    config_format: Option<csv_filter::ConfigFormat>,
+    #[structopt(long = "dry-run", conflicts_with = "watch")]
+    dry_run: bool,
+    #[structopt(long = "report")]
+    report: Option<String>,
+    #[structopt(long = "if-exists", default_value = "overwrite")]
+    if_exists: csv_filter::IfExists,
+    #[structopt(long = "checkpoint-interval", default_value = "0")]
+    checkpoint_interval: usize,
+    #[structopt(long = "resume")]
+    resume: bool,
+    #[structopt(long = "state")]
+    state: Option<String>,
+    #[structopt(long = "watch")]
+    watch: bool,
+}
+
+/// Holds command line parameters of the `validate` subcommand.
+#[derive(StructOpt, Debug)]
+struct ValidateParameters {
+    #[structopt(short, long)]
+    configuration: String,
+    #[structopt(short, long)]
+    input: Option<String>,
+    #[structopt(long = "config-format")]
+    config_format: Option<csv_filter::ConfigFormat>,
+}
+
+/// Holds command line parameters of the `stats` subcommand.
+#[derive(StructOpt, Debug)]
+struct StatsParameters {
+    #[structopt(short, long)]
+    input: String,
+    #[structopt(short = "t", long = "top", default_value = "5")]
+    top: usize,
+    #[structopt(short = "p", long = "parallelism", default_value = "1")]
+    parallelism: usize,
+    #[structopt(short = "m", long = "mmap")]
+    mmap: bool,
+    #[structopt(long = "json")]
+    json: bool,
+}
+
+/// Holds command line parameters of the `init` subcommand.
+#[derive(StructOpt, Debug)]
+struct InitParameters {
+    #[structopt(short, long)]
+    input: String,
+    #[structopt(short, long)]
+    configuration: Option<String>,
+    #[structopt(long = "config-format")]
+    config_format: Option<csv_filter::ConfigFormat>,
+    #[structopt(long = "output-file", default_value = "output.csv")]
+    output_file: String,
+    #[structopt(long = "max-values", default_value = "0")]
+    max_values: usize,
+    #[structopt(long = "sample-rows", default_value = "10000")]
+    sample_rows: usize,
 }
 
 fn main() {
+    let params = CommandLineParameters::from_args();
+    csv_filter::init_logging(params.quiet, params.verbose, params.log_format);
+
+    match params.command {
+        Command::Run(params) => run(params),
+        Command::Validate(params) => validate(params),
+        Command::Stats(params) => stats(params),
+        Command::Init(params) => init(params),
+    }
+}
+
+/// Executes the `run` subcommand.
+fn run(params: RunParameters) {
     let program_start = Instant::now();
 
-    let params: CommandLineParameters = CommandLineParameters::from_args();
+    let options = csv_filter::Options {
+        no_sort: params.no_sort,
+        filter_parallelism: params.filter_parallelism,
+        sort_parallelism: params.sort_parallelism,
+        use_mmap: params.mmap,
+        config_format: params.config_format,
+        dry_run: params.dry_run,
+        report: params.report,
+        if_exists: params.if_exists,
+        checkpoint_interval: params.checkpoint_interval,
+        resume: params.resume,
+        state: params.state,
+    };
 
-    csv_filter::process(
-        &params.input,
-        &params.configuration,
-        &params.output,
-        params.no_sort,
-        params.filter_parallelism,
-        params.sort_parallelism,
-    );
+    let configuration = match params.configuration {
+        Some(configuration) => configuration,
+        None => {
+            // Without a configuration file, output files are defined by a query
+            if params.query.is_none()
+                && params.where_clause.is_none()
+                && params.select.is_none()
+                && params.sort_by.is_none()
+            {
+                error!("Either a configuration file or a query (--query, --where, --select or --sort-by) is required");
+                process::exit(1);
+            }
+            if params.watch || options.state.is_some() {
+                error!("Watch mode and incremental runs require a configuration file");
+                process::exit(1);
+            }
+            let plan = match params.query {
+                Some(query) => {
+                    let sql = read_sql_query(query, params.out);
+                    csv_filter::process_sql(&params.input, &sql, &params.output, &options)
+                }
+                None => {
+                    let query = csv_filter::Query {
+                        where_clause: params.where_clause,
+                        select: params.select,
+                        sort_by: params.sort_by,
+                        output: params.out,
+                    };
+                    csv_filter::process_query(&params.input, &query, &params.output, &options)
+                }
+            };
+            match plan {
+                Ok(Some(plan)) => print_plan(&plan),
+                Ok(None) => {}
+                Err(e) => {
+                    error!("{}", e);
+                    process::exit(1);
+                }
+            }
+            info!(
+                "Finished in {} milliseconds",
+                program_start.elapsed().as_millis()
+            );
+            return;
+        }
+    };
+
+    if params.watch {
+        csv_filter::watch(&params.input, &configuration, &params.output, &options);
+    }
 
-    println!(
+    if let Some(plan) = csv_filter::process(&params.input, &configuration, &params.output, &options)
+    {
+        print_plan(&plan);
+    }
+
+    info!(
         "Finished in {} milliseconds",
         program_start.elapsed().as_millis()
     )
 }
+
+/// Prints the plan of a dry run. Exits with status code 1 if the input file cannot be processed.
+fn print_plan(plan: &csv_filter::Plan) {
+    println!("{}", plan);
+    if plan.has_errors() {
+        process::exit(1);
+    }
+}
+
+/// Returns the statements of the `--query` parameter, which are read from a file if the
+/// parameter is the path of an `.sql` file. Exits with status code 1 if the file cannot be read.
+fn read_sql_query(query: String, output: String) -> csv_filter::SqlQuery {
+    if !query.trim_end().to_lowercase().ends_with(".sql") {
+        return csv_filter::SqlQuery {
+            name: String::from("--query"),
+            statements: query,
+            output,
+        };
+    }
+
+    match fs::read_to_string(query.trim_end()) {
+        Ok(statements) => csv_filter::SqlQuery {
+            name: query.trim_end().to_string(),
+            statements,
+            output,
+        },
+        Err(e) => {
+            error!("Cannot read query file '{}': {}", query.trim_end(), e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Executes the `validate` subcommand. Exits with status code 1 if the configuration is invalid.
+fn validate(params: ValidateParameters) {
+    let diagnostics = csv_filter::validate(
+        &params.configuration,
+        params.input.as_deref(),
+        params.config_format,
+    );
+
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
+    }
+
+    if diagnostics.iter().any(|d| d.is_error()) {
+        println!("Configuration is invalid");
+        process::exit(1);
+    }
+    println!("Configuration is valid");
+}
+
+/// Executes the `stats` subcommand.
+fn stats(params: StatsParameters) {
+    let stats = csv_filter::stats(
+        &params.input,
+        &csv_filter::StatsOptions {
+            top_values: params.top,
+            parallelism: params.parallelism,
+            use_mmap: params.mmap,
+        },
+    );
+
+    if params.json {
+        println!("{}", stats.to_json());
+    } else {
+        print!("{}", stats);
+    }
+}
+
+/// Executes the `init` subcommand. The configuration is written to the given file, or printed
+/// if no file is given. Existing files are never overwritten.
+fn init(params: InitParameters) {
+    let format = params
+        .config_format
+        .or_else(|| {
+            params
+                .configuration
+                .as_ref()
+                .and_then(|path| csv_filter::ConfigFormat::from_path(Path::new(path)))
+        })
+        .unwrap_or(csv_filter::ConfigFormat::Json);
+
+    let config = csv_filter::init(
+        &params.input,
+        &csv_filter::InitOptions {
+            output: params.output_file,
+            max_values: params.max_values,
+            sample_rows: params.sample_rows,
+            format,
+        },
+    );
+
+    match params.configuration {
+        Some(path) if Path::new(&path).exists() => {
+            error!("Configuration file '{}' already exists", path);
+            process::exit(1);
+        }
+        Some(path) => {
+            fs::write(&path, config)
+                .unwrap_or_else(|e| panic!("Cannot write config file '{}': {}", path, e));
+            info!("Configuration written to '{}'", path);
+        }
+        None => print!("{}", config),
+    }
+}
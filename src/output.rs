@@ -0,0 +1,631 @@
+use crate::logging;
+use crate::Options;
+use config::FilterConfig;
+use filter::{Checkpoint, CheckpointOptions};
+use log::{debug, info, trace};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// The name of the directory inside the output directory that output files are written to until
+/// all stages have finished.
+pub const STAGING_DIR_NAME: &str = ".csv-filter-staging";
+
+/// The name of the manifest file, which is only present in the output directory if all output
+/// files of the last run are complete.
+pub const MANIFEST_FILE_NAME: &str = "_MANIFEST.json";
+
+/// The name of the file in the output directory that the progress of an unfinished run is
+/// persisted to.
+pub const CHECKPOINT_FILE_NAME: &str = "_CHECKPOINT.json";
+
+/// The name of the file in the output directory that lists the steps of a commit while output
+/// files are moved into place, so that an interrupted commit is completed by the next run.
+pub const JOURNAL_FILE_NAME: &str = "_JOURNAL.json";
+
+/// What happens to output files that already exist in the output directory.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IfExists {
+    /// Fail before processing the input file
+    Error,
+    /// Replace existing files
+    #[default]
+    Overwrite,
+    /// Append rows to existing files. Rows are merged into files that are sorted.
+    Append,
+    /// Leave existing files untouched and skip their configuration items
+    Skip,
+}
+
+impl FromStr for IfExists {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "error" => Ok(IfExists::Error),
+            "overwrite" => Ok(IfExists::Overwrite),
+            "append" => Ok(IfExists::Append),
+            "skip" => Ok(IfExists::Skip),
+            _ => Err(format!(
+                "Unknown mode '{}' (expected error, overwrite, append or skip)",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for IfExists {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IfExists::Error => write!(f, "error"),
+            IfExists::Overwrite => write!(f, "overwrite"),
+            IfExists::Append => write!(f, "append"),
+            IfExists::Skip => write!(f, "skip"),
+        }
+    }
+}
+
+/// Applies the [`IfExists`] mode to all configuration items whose output file already exists.
+/// Returns the configuration items that should be processed.
+///
+/// # Arguments
+/// * `all_filter_configs` - A vector containing all configuration items
+/// * `output_dir_path` - Path to the directory containing all output files
+/// * `if_exists` - What happens to output files that already exist
+///
+/// # Panics
+/// This function will panic if an output file exists in mode [`IfExists::Error`], or if the
+/// header row of an existing file does not match its configuration in mode [`IfExists::Append`].
+pub fn resolve_existing_outputs(
+    all_filter_configs: Vec<Arc<FilterConfig>>,
+    output_dir_path: &str,
+    if_exists: IfExists,
+) -> Vec<Arc<FilterConfig>> {
+    all_filter_configs
+        .into_iter()
+        .filter(|config| {
+            let path = Path::new(output_dir_path).join(&config.output);
+            if !path.exists() {
+                return true;
+            }
+            match if_exists {
+                IfExists::Error => panic!("Output file '{}' already exists", path.display()),
+                IfExists::Overwrite => true,
+                IfExists::Append => {
+                    check_headers(&path, config);
+                    true
+                }
+                IfExists::Skip => {
+                    info!("Skipping existing output file '{}'", path.display());
+                    false
+                }
+            }
+        })
+        .collect()
+}
+
+/// Checks if rows may be merged into existing sorted output files, which is the case if sorting is
+/// enabled and rows are appended, either explicitly or by incremental runs.
+///
+/// # Arguments
+/// * `options` - Settings that control how files are processed
+pub fn merges_appended_rows(options: &Options) -> bool {
+    !options.no_sort && (options.if_exists == IfExists::Append || options.state.is_some())
+}
+
+/// Resolves the symbolic links of a path inside the output directory and makes sure it does not
+/// lead outside of it. Returns the resolved path, which files are written to instead of the
+/// original one, so that links changed in the meantime are not followed. The deepest existing
+/// directory of the path is resolved, since missing directories are created inside the location
+/// it resolves to. The file itself is only resolved to check where it leads, since moving a file
+/// replaces a link instead of following it.
+///
+/// # Arguments
+/// * `output_dir_path` - Path to the directory containing all output files
+/// * `path` - The path of an output file inside the output directory
+pub(crate) fn resolve_confined(output_dir_path: &str, path: &Path) -> Result<PathBuf, String> {
+    let output_dir = match fs::canonicalize(output_dir_path) {
+        Ok(output_dir) => output_dir,
+        // Everything will be created inside the output directory if it does not exist yet
+        Err(_) => return Ok(path.to_path_buf()),
+    };
+    let escapes = || {
+        format!(
+            "Output file '{}' escapes the output directory '{}' through a symbolic link",
+            path.display(),
+            output_dir.display()
+        )
+    };
+
+    let is_link = fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink());
+    if is_link && fs::canonicalize(path).is_ok_and(|target| !target.starts_with(&output_dir)) {
+        return Err(escapes());
+    }
+
+    let (parent, file_name) = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(file_name)) => (parent, file_name),
+        _ => return Ok(path.to_path_buf()),
+    };
+    let existing = match parent.ancestors().find(|p| p.exists()) {
+        Some(existing) => existing,
+        None => return Ok(path.to_path_buf()),
+    };
+    let resolved = fs::canonicalize(existing)
+        .map_err(|e| format!("Cannot resolve directory '{}': {}", existing.display(), e))?;
+    if !resolved.starts_with(&output_dir) {
+        return Err(escapes());
+    }
+    let missing = parent
+        .strip_prefix(existing)
+        .unwrap_or_else(|_| Path::new(""));
+    Ok(resolved.join(missing).join(file_name))
+}
+
+/// Checks that the header row of an existing output file matches the output columns of its
+/// configuration, so that rows can be appended.
+///
+/// # Panics
+/// This function will panic if the header rows do not match.
+fn check_headers(path: &Path, config: &FilterConfig) {
+    let existing: Vec<String> = csv::Reader::from_path(path)
+        .and_then(|mut r| r.headers().map(|h| h.iter().map(String::from).collect()))
+        .unwrap_or_else(|e| panic!("Cannot read output file '{}': {}", path.display(), e));
+    let expected = config.output_column_names();
+    if existing != expected {
+        panic!(
+            "Cannot append to output file '{}' because its columns [{}] do not match the configured columns [{}]",
+            path.display(),
+            existing.join(", "),
+            expected.join(", ")
+        );
+    }
+}
+
+/// The manifest of a completed output set.
+#[derive(Debug, Serialize)]
+struct Manifest<'a> {
+    /// Path of the input file the output files were created from
+    input: &'a str,
+    /// The time all output files were completed in RFC 3339 format
+    completed_at: String,
+    outputs: Vec<ManifestEntry>,
+}
+
+/// The entry of one output file in the manifest.
+#[derive(Debug, Serialize)]
+struct ManifestEntry {
+    /// The name of the output file as defined in the configuration
+    output: String,
+    bytes: u64,
+}
+
+/// A set of output files that are written to a staging directory and are moved to the output
+/// directory only once all of them are complete. Existing output files stay untouched until then.
+pub struct OutputSet<'a> {
+    output_dir_path: &'a str,
+    staging_dir: PathBuf,
+    append: bool,
+    sort_enabled: bool,
+    checkpoints: Option<CheckpointOptions>,
+}
+
+impl<'a> OutputSet<'a> {
+    /// Prepares the staging directory. Files left over from an interrupted run are removed, as
+    /// well as the manifest of the previous run, since the output directory is about to change.
+    /// If the run should be resumed and a checkpoint exists, the staged files are kept instead.
+    ///
+    /// # Arguments
+    /// * `output_dir_path` - Path to the directory that output files are moved to
+    /// * `options` - Settings that control how files are processed
+    ///
+    /// # Panics
+    /// This function will panic if the staging directory cannot be prepared.
+    pub fn new(output_dir_path: &'a str, options: &Options) -> Self {
+        let staging_dir = Path::new(output_dir_path).join(STAGING_DIR_NAME);
+        let checkpoint_path = Path::new(output_dir_path).join(CHECKPOINT_FILE_NAME);
+        let resume_from = if options.resume && staging_dir.exists() {
+            Checkpoint::read(&checkpoint_path)
+        } else {
+            None
+        };
+        if options.resume && resume_from.is_none() {
+            info!("No checkpoint found, processing the whole input file");
+        }
+
+        if resume_from.is_none() && staging_dir.exists() {
+            fs::remove_dir_all(&staging_dir).unwrap_or_else(|e| {
+                panic!(
+                    "Cannot remove staging directory '{}': {}",
+                    staging_dir.display(),
+                    e
+                )
+            });
+        }
+        fs::create_dir_all(&staging_dir).unwrap_or_else(|e| {
+            panic!(
+                "Cannot create staging directory '{}': {}",
+                staging_dir.display(),
+                e
+            )
+        });
+        trace!("Staging output files in '{}'", staging_dir.display());
+
+        let manifest = Path::new(output_dir_path).join(MANIFEST_FILE_NAME);
+        if manifest.exists() {
+            fs::remove_file(&manifest).unwrap_or_else(|e| {
+                panic!("Cannot remove manifest '{}': {}", manifest.display(), e)
+            });
+        }
+
+        let checkpoints = if options.checkpoint_interval > 0 || resume_from.is_some() {
+            Some(CheckpointOptions {
+                path: checkpoint_path,
+                interval: options.checkpoint_interval,
+                resume_from,
+            })
+        } else {
+            None
+        };
+
+        OutputSet {
+            output_dir_path,
+            staging_dir,
+            append: options.if_exists == IfExists::Append,
+            sort_enabled: !options.no_sort,
+            checkpoints,
+        }
+    }
+
+    /// Returns the settings for writing checkpoints in the filter stage, if enabled.
+    pub fn checkpoints(&self) -> Option<&CheckpointOptions> {
+        self.checkpoints.as_ref()
+    }
+
+    /// Returns the path of the staging directory, which all stages write to.
+    pub fn staging_dir_path(&self) -> String {
+        util::path_to_string(&self.staging_dir)
+    }
+
+    /// Moves all output files from the staging directory to the output directory and writes the
+    /// manifest. Every file is synced to disk before it atomically replaces a previous version.
+    /// In append mode, the rows of existing files are combined with the staged rows first. The
+    /// steps are journaled before any file is moved, so that an interrupted commit can be
+    /// completed by the next run (see [`complete_interrupted_commit`]).
+    ///
+    /// # Arguments
+    /// * `csv_file_path` - Path to the input file
+    /// * `all_filter_configs` - A vector containing all configuration items
+    /// * `staged_state` - The staged state file of an incremental run and the path of the state
+    ///   file it replaces once all output files have been moved
+    ///
+    /// # Panics
+    /// This function will panic if an output file cannot be moved.
+    pub fn commit(
+        self,
+        csv_file_path: &str,
+        all_filter_configs: &[Arc<FilterConfig>],
+        staged_state: Option<(PathBuf, &Path)>,
+    ) {
+        let mut steps = Vec::new();
+        for config in all_filter_configs {
+            let mut staged = self.staging_dir.join(&config.output);
+            let target = Path::new(self.output_dir_path).join(&config.output);
+            let target = resolve_confined(self.output_dir_path, &target)
+                .unwrap_or_else(|message| panic!("{}", message));
+            let append_at = if self.append && target.exists() && self.appends_in_place(config) {
+                Some(fs::metadata(&target).map(|m| m.len()).unwrap_or(0))
+            } else {
+                if self.append && target.exists() {
+                    staged = self.append_to_existing(&staged, &target, config);
+                }
+                None
+            };
+            sync_file(&staged);
+            steps.push(CommitStep {
+                output: Some(config.output.clone()),
+                source: staged,
+                target,
+                append_at,
+            });
+        }
+        if let Some((staged, state_file_path)) = staged_state {
+            steps.push(CommitStep {
+                output: None,
+                source: staged,
+                target: state_file_path.to_path_buf(),
+                append_at: None,
+            });
+        }
+
+        let journal = Journal {
+            input: csv_file_path.to_string(),
+            steps,
+        };
+        journal.write(self.output_dir_path);
+        journal.complete(self.output_dir_path);
+    }
+
+    /// Checks if the staged rows of a configuration item can be appended to its existing output
+    /// file in place. Otherwise, the existing file is rewritten, which takes time proportional to
+    /// its size: sorted rows need to be merged, and duplicates need to be removed across the
+    /// existing and the appended rows.
+    fn appends_in_place(&self, config: &FilterConfig) -> bool {
+        let sorted = self.sort_enabled && config.sort_columns.is_some();
+        !sorted && config.distinct.is_none()
+    }
+
+    /// Combines the rows of an existing output file with the rows of its staged file into a new
+    /// staged file, which is returned. Staged rows are merged into the existing rows if the file
+    /// is sorted and appended to them otherwise. The header row is written only once. If the
+    /// configuration item removes duplicates, they are removed from the combined rows, so that
+    /// `keep` decides between existing and appended rows as well.
+    ///
+    /// # Panics
+    /// This function will panic if a file cannot be read or written.
+    fn append_to_existing(&self, staged: &Path, target: &Path, config: &FilterConfig) -> PathBuf {
+        let combined = PathBuf::from(format!("{}.append", staged.display()));
+        match &config.sort_columns {
+            Some(sort_columns) if self.sort_enabled => {
+                debug!("Merging rows into sorted file '{}'", target.display());
+                sort::merge_sorted_files(
+                    target,
+                    staged,
+                    &combined,
+                    sort_columns,
+                    &config.descending_columns,
+                );
+            }
+            _ => {
+                debug!("Appending rows to file '{}'", target.display());
+                concat_files(target, staged, &combined);
+            }
+        }
+        if let Some(distinct) = &config.distinct {
+            debug!(
+                "Removing duplicates from appended file '{}'",
+                target.display()
+            );
+            distinct::distinct_csv_file(&combined, distinct, None);
+        }
+        combined
+    }
+}
+
+/// The steps of a commit, which are written to the output directory before any output file is
+/// moved into place. Every step can be repeated, so that a commit that was interrupted can be
+/// completed by performing all of its steps again.
+#[derive(Debug, Serialize, Deserialize)]
+struct Journal {
+    /// Path of the input file the output files were created from
+    input: String,
+    steps: Vec<CommitStep>,
+}
+
+/// Moves a complete staged file into place, or appends its rows to an existing file.
+#[derive(Debug, Serialize, Deserialize)]
+struct CommitStep {
+    /// The name of the output file as defined in the configuration, or `None` for the state file
+    /// of an incremental run
+    output: Option<String>,
+    /// The staged file, which is removed once the commit is complete
+    source: PathBuf,
+    /// The file in the output directory that is replaced or appended to
+    target: PathBuf,
+    /// The length of the target before rows are appended in place, or `None` if the target is
+    /// replaced
+    append_at: Option<u64>,
+}
+
+impl Journal {
+    /// Reads the journal of an interrupted commit. Returns `None` if there is none.
+    ///
+    /// # Panics
+    /// This function will panic if the journal cannot be parsed.
+    fn read(output_dir_path: &str) -> Option<Self> {
+        let path = Path::new(output_dir_path).join(JOURNAL_FILE_NAME);
+        let json = fs::read_to_string(&path).ok()?;
+        Some(
+            serde_json::from_str(&json)
+                .unwrap_or_else(|e| panic!("Cannot parse journal '{}': {}", path.display(), e)),
+        )
+    }
+
+    /// Writes the journal to the output directory and syncs it to disk.
+    ///
+    /// # Panics
+    /// This function will panic if the journal cannot be written.
+    fn write(&self, output_dir_path: &str) {
+        let json = serde_json::to_string_pretty(self).expect("Cannot serialize journal");
+        write_file(&Path::new(output_dir_path).join(JOURNAL_FILE_NAME), json);
+    }
+
+    /// Performs all steps of the commit, removes the staging directory and the checkpoint and
+    /// writes the manifest. Steps whose staged file has already been removed are complete.
+    /// Appending starts at the recorded length of the target, so that rows which were only
+    /// partially appended by an interrupted commit are replaced.
+    ///
+    /// # Panics
+    /// This function will panic if an output file cannot be moved or appended to.
+    fn complete(&self, output_dir_path: &str) {
+        let mut outputs = Vec::new();
+        for step in &self.steps {
+            if step.source.exists() {
+                match step.append_at {
+                    Some(length) => append_in_place(&step.source, &step.target, length),
+                    None => move_file(&step.source, &step.target),
+                }
+            }
+            if let Some(output) = &step.output {
+                outputs.push(ManifestEntry {
+                    output: output.clone(),
+                    bytes: fs::metadata(&step.target).map(|m| m.len()).unwrap_or(0),
+                });
+            }
+        }
+
+        let staging_dir = Path::new(output_dir_path).join(STAGING_DIR_NAME);
+        if staging_dir.exists() {
+            fs::remove_dir_all(&staging_dir).unwrap_or_else(|e| {
+                panic!(
+                    "Cannot remove staging directory '{}': {}",
+                    staging_dir.display(),
+                    e
+                )
+            });
+        }
+        let _ = fs::remove_file(Path::new(output_dir_path).join(CHECKPOINT_FILE_NAME));
+
+        // The manifest is written last, so that it is only present if all files are complete
+        let manifest = Manifest {
+            input: &self.input,
+            completed_at: logging::timestamp(),
+            outputs,
+        };
+        let json = serde_json::to_string_pretty(&manifest).expect("Cannot serialize manifest");
+        write_file(&Path::new(output_dir_path).join(MANIFEST_FILE_NAME), json);
+
+        let journal = Path::new(output_dir_path).join(JOURNAL_FILE_NAME);
+        fs::remove_file(&journal)
+            .unwrap_or_else(|e| panic!("Cannot remove journal '{}': {}", journal.display(), e));
+    }
+}
+
+/// Completes the commit of a previous run that was interrupted while output files were moved
+/// into place, so that no output file is left with partially appended rows.
+///
+/// # Arguments
+/// * `output_dir_path` - Path to the directory containing all output files
+///
+/// # Panics
+/// This function will panic if the interrupted commit cannot be completed.
+pub fn complete_interrupted_commit(output_dir_path: &str) {
+    if let Some(journal) = Journal::read(output_dir_path) {
+        info!(
+            "Completing the output files of the interrupted run of input file '{}'",
+            journal.input
+        );
+        journal.complete(output_dir_path);
+    }
+}
+
+/// Appends all rows of a staged file to an existing output file, without copying the existing
+/// rows. The output file is truncated to the given length first, which removes rows that were
+/// partially appended by an interrupted commit, and it is synced once all rows have been
+/// appended. Staged files are synced before the commit starts.
+///
+/// # Arguments
+/// * `staged` - The staged file, whose header row is skipped
+/// * `target` - The existing output file
+/// * `length` - The length of the output file before any rows were appended
+///
+/// # Panics
+/// This function will panic if a file cannot be read or written.
+fn append_in_place(staged: &Path, target: &Path, length: u64) {
+    debug!("Appending rows to file '{}' in place", target.display());
+
+    // The header row of the staged file is skipped, since the existing file has one already
+    let mut reader = csv::Reader::from_path(staged)
+        .unwrap_or_else(|e| panic!("Cannot read file '{}': {}", staged.display(), e));
+    reader
+        .headers()
+        .unwrap_or_else(|e| panic!("Cannot read file '{}': {}", staged.display(), e));
+    let rows_start = reader.position().byte();
+
+    let append = || -> io::Result<()> {
+        let mut file = OpenOptions::new().read(true).append(true).open(target)?;
+        file.set_len(length)?;
+        // Files that were edited by hand may lack a line break after their last row
+        let mut last_byte = [0; 1];
+        if length > 0 {
+            file.seek(SeekFrom::Start(length - 1))?;
+            file.read_exact(&mut last_byte)?;
+            if last_byte[0] != b'\n' {
+                file.write_all(b"\n")?;
+            }
+        }
+        let mut rows = File::open(staged)?;
+        rows.seek(SeekFrom::Start(rows_start))?;
+        io::copy(&mut rows, &mut file)?;
+        file.sync_all()
+    };
+    append().unwrap_or_else(|e| panic!("Cannot append to file '{}': {}", target.display(), e));
+}
+
+/// Writes the header row and all rows of the first file followed by all rows of the second file.
+///
+/// # Panics
+/// This function will panic if a file cannot be read or written.
+fn concat_files(first: &Path, second: &Path, target: &Path) {
+    let mut writer = csv::Writer::from_path(target)
+        .unwrap_or_else(|e| panic!("Cannot create file '{}': {}", target.display(), e));
+    let mut first_reader = csv::Reader::from_path(first)
+        .unwrap_or_else(|e| panic!("Cannot read file '{}': {}", first.display(), e));
+    writer
+        .write_record(first_reader.headers().unwrap())
+        .unwrap();
+
+    let second_reader = csv::Reader::from_path(second)
+        .unwrap_or_else(|e| panic!("Cannot read file '{}': {}", second.display(), e));
+    for record in first_reader
+        .into_records()
+        .chain(second_reader.into_records())
+    {
+        writer
+            .write_record(&record.unwrap())
+            .unwrap_or_else(|e| panic!("Cannot write file '{}': {}", target.display(), e));
+    }
+    writer.flush().unwrap();
+}
+
+/// Writes a file next to its final path, syncs it to disk and moves it into place, so that the
+/// file is either complete or absent.
+///
+/// # Panics
+/// This function will panic if the file cannot be written.
+fn write_file(path: &Path, contents: String) {
+    let staged = PathBuf::from(format!("{}.tmp", path.display()));
+    fs::write(&staged, contents + "\n")
+        .unwrap_or_else(|e| panic!("Cannot write file '{}': {}", staged.display(), e));
+    sync_file(&staged);
+    move_file(&staged, path);
+}
+
+/// Flushes a file to disk.
+///
+/// # Panics
+/// This function will panic if the file cannot be synced.
+fn sync_file(path: &Path) {
+    File::open(path)
+        .and_then(|f| f.sync_all())
+        .unwrap_or_else(|e| panic!("Cannot sync file '{}': {}", path.display(), e));
+}
+
+/// Atomically replaces the target with the source file and syncs the containing directory, so
+/// that the rename survives a crash.
+///
+/// # Panics
+/// This function will panic if the file cannot be moved.
+fn move_file(source: &Path, target: &Path) {
+    let target_dir = target.parent().unwrap_or_else(|| Path::new("."));
+    fs::create_dir_all(target_dir)
+        .unwrap_or_else(|e| panic!("Cannot create directory '{}': {}", target_dir.display(), e));
+    fs::rename(source, target).unwrap_or_else(|e| {
+        panic!(
+            "Cannot move '{}' to '{}': {}",
+            source.display(),
+            target.display(),
+            e
+        )
+    });
+    debug!("Completed output file '{}'", target.display());
+
+    // Directories cannot be synced on every platform, which is why errors are ignored
+    if let Ok(dir) = File::open(target_dir) {
+        let _ = dir.sync_all();
+    }
+}
@@ -0,0 +1,334 @@
+use crate::output;
+use crate::validation;
+use crate::{ConfigFormat, Diagnostic, Options};
+use config::{ColumnFilter, FilterConfig};
+use core::cmp;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The number of input rows that are processed to estimate the size of output files.
+const SAMPLE_ROWS: usize = 1000;
+
+/// Describes how an input file would be processed, as determined by a dry run.
+#[derive(Debug)]
+pub struct Plan {
+    /// Problems found when checking the configuration against the input file. Nothing else is
+    /// planned if any of them is an error.
+    pub diagnostics: Vec<Diagnostic>,
+    /// The input file, unless it cannot be processed
+    pub input: Option<InputPlan>,
+    /// All output files in the order they are configured
+    pub outputs: Vec<OutputPlan>,
+}
+
+/// Describes the input file and the stages of a [`Plan`].
+#[derive(Debug)]
+pub struct InputPlan {
+    /// Path of the input file
+    pub path: String,
+    /// The number of columns of the input file
+    pub columns: usize,
+    /// The number of rows of the input file, estimated from the sampled rows
+    pub estimated_rows: usize,
+    /// The number of rows that were sampled
+    pub sampled_rows: usize,
+    /// The maximum number of threads of the filter stage
+    pub filter_threads: usize,
+    /// The maximum number of threads of the sort stage, unless sorting is disabled
+    pub sort_threads: Option<usize>,
+}
+
+/// Describes one output file of a [`Plan`].
+#[derive(Debug)]
+pub struct OutputPlan {
+    /// Path of the output file
+    pub path: String,
+    /// The name of every output column along with where its value comes from
+    pub columns: Vec<(String, String)>,
+    /// The conditions of all filters
+    pub filters: Vec<String>,
+    /// Path of the script applied to matching rows
+    pub script: Option<String>,
+    /// The sort keys, unless the output file is not sorted
+    pub sort_keys: Option<Vec<String>>,
+    /// The deduplication key, if duplicates are removed
+    pub distinct: Option<String>,
+    /// The number of matching rows, estimated from the sampled rows
+    pub estimated_rows: usize,
+    /// The number of sampled rows that match
+    pub matching_sampled_rows: usize,
+}
+
+impl Plan {
+    /// Checks if the input file cannot be processed with the configuration.
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|d| d.is_error())
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Dry run: no files will be created")?;
+        for diagnostic in &self.diagnostics {
+            writeln!(f, "{}", diagnostic)?;
+        }
+        let input = match &self.input {
+            Some(input) => input,
+            None => {
+                return write!(
+                    f,
+                    "The input file cannot be processed with this configuration"
+                )
+            }
+        };
+
+        write!(
+            f,
+            "Input file '{}': {} columns, {} rows (estimated from the first {} rows)",
+            input.path, input.columns, input.estimated_rows, input.sampled_rows
+        )?;
+        write!(f, "\nFilter stage: up to {} threads", input.filter_threads)?;
+        if let Some(sort_threads) = input.sort_threads {
+            write!(f, "\nSort stage: up to {} threads", sort_threads)?;
+        }
+
+        for output in &self.outputs {
+            write!(f, "\nOutput file '{}'", output.path)?;
+            write!(f, "\n  Columns:")?;
+            for (name, source) in &output.columns {
+                write!(f, "\n    {} <- {}", name, source)?;
+            }
+            if !output.filters.is_empty() {
+                write!(f, "\n  Filters:")?;
+                for condition in &output.filters {
+                    write!(f, "\n    {}", condition)?;
+                }
+            }
+            if let Some(script) = &output.script {
+                write!(f, "\n  Script: '{}'", script)?;
+            }
+            if let Some(sort_keys) = &output.sort_keys {
+                write!(f, "\n  Sort keys: {}", sort_keys.join(", "))?;
+            }
+            if let Some(distinct) = &output.distinct {
+                write!(f, "\n  Distinct: {}", distinct)?;
+            }
+            write!(
+                f,
+                "\n  Estimated matching rows: {} ({} of {} sampled rows match)",
+                output.estimated_rows, output.matching_sampled_rows, input.sampled_rows
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Determines how the input file would be processed, without creating any files. Returns a plan
+/// whose diagnostics contain an error if the input file cannot be read.
+///
+/// # Arguments
+/// * `csv_file_path` - Path to the CSV file that would be processed
+/// * `all_filter_configs` - A vector containing all configuration items
+/// * `output_dir_path` - Path to the directory that data would be written to
+/// * `format` - The format of the configuration file
+/// * `options` - Settings that control how files would be processed
+pub fn create_plan(
+    csv_file_path: &str,
+    all_filter_configs: &[Arc<FilterConfig>],
+    output_dir_path: &str,
+    format: ConfigFormat,
+    options: &Options,
+) -> Plan {
+    let mut plan = Plan {
+        diagnostics: Vec::new(),
+        input: None,
+        outputs: Vec::new(),
+    };
+    let input_headers = match filter::read_input_headers(csv_file_path) {
+        Ok(input_headers) => input_headers,
+        Err(e) => {
+            plan.diagnostics.push(Diagnostic::error(
+                String::from("$"),
+                format!(
+                    "Cannot read headers of input file '{}': {}",
+                    csv_file_path, e
+                ),
+            ));
+            return plan;
+        }
+    };
+
+    plan.diagnostics = validation::validate_output_dir(all_filter_configs, format, output_dir_path);
+    if output::merges_appended_rows(options) {
+        plan.diagnostics
+            .extend(validation::validate_append(all_filter_configs, format));
+    }
+    plan.diagnostics.extend(validation::validate_headers(
+        all_filter_configs,
+        format,
+        &input_headers,
+    ));
+    if plan.has_errors() {
+        return plan;
+    }
+
+    let sample = filter::sample_input(csv_file_path, all_filter_configs, SAMPLE_ROWS);
+    plan.input = Some(InputPlan {
+        path: csv_file_path.to_string(),
+        columns: input_headers.len(),
+        estimated_rows: sample.estimated_total_rows,
+        sampled_rows: sample.rows,
+        filter_threads: cmp::max(1, options.filter_parallelism),
+        sort_threads: match options.no_sort {
+            true => None,
+            false => Some(cmp::max(1, options.sort_parallelism)),
+        },
+    });
+
+    for config in all_filter_configs {
+        let columns = filter::resolve_columns(&input_headers, config);
+        let describe_column = |name: &str| describe_column(name, &columns, &input_headers, config);
+
+        let output_columns = match (&config.output_columns, &config.aggregate) {
+            (Some(output_columns), _) => output_columns
+                .iter()
+                .map(|output_column| {
+                    let source = match &output_column.expression {
+                        Some(_) => String::from("expression"),
+                        None => describe_column(output_column.source_column()),
+                    };
+                    (output_column.name.clone(), source)
+                })
+                .collect(),
+            (None, Some(aggregate)) => {
+                let group_by = aggregate.group_by.iter().map(|column| {
+                    (
+                        column.clone(),
+                        format!("group by {}", describe_column(column)),
+                    )
+                });
+                let aggregations = aggregate.aggregations.iter().map(|aggregation| {
+                    let source = match &aggregation.column {
+                        Some(column) => describe_column(column),
+                        None => String::from("rows"),
+                    };
+                    (
+                        aggregation.output_column(),
+                        format!("{} of {}", aggregation.function.name(), source),
+                    )
+                });
+                group_by.chain(aggregations).collect()
+            }
+            (None, None) => config
+                .output_column_names()
+                .into_iter()
+                .map(|column| {
+                    let source = describe_column(&column);
+                    (column, source)
+                })
+                .collect(),
+        };
+
+        let filters = config
+            .filters
+            .iter()
+            .filter_map(|f| describe_condition(f).map(|c| (f, c)))
+            .map(|(f, condition)| format!("{} {}", describe_column(&f.column), condition))
+            .collect();
+
+        let sort_keys = match (&config.sort_columns, options.no_sort) {
+            (Some(sort_columns), false) => Some(
+                sort_columns
+                    .iter()
+                    .map(|c| match config.descending_columns.contains(c) {
+                        true => format!("{} (descending)", c),
+                        false => c.clone(),
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        };
+        let distinct = config
+            .distinct
+            .as_ref()
+            .map(|distinct| match &distinct.columns {
+                Some(columns) => columns.join(", "),
+                None => String::from("whole row"),
+            });
+
+        let matching_rows = sample
+            .matching_rows
+            .get(&config.output)
+            .copied()
+            .unwrap_or(0);
+        plan.outputs.push(OutputPlan {
+            path: Path::new(output_dir_path)
+                .join(&config.output)
+                .display()
+                .to_string(),
+            columns: output_columns,
+            filters,
+            script: config.script.clone(),
+            sort_keys,
+            distinct,
+            estimated_rows: (matching_rows * sample.estimated_total_rows)
+                .checked_div(sample.rows)
+                .unwrap_or(0),
+            matching_sampled_rows: matching_rows,
+        });
+    }
+
+    plan
+}
+
+/// Describes where the value of a column comes from.
+fn describe_column(
+    name: &str,
+    columns: &[String],
+    input_headers: &[String],
+    config: &FilterConfig,
+) -> String {
+    let joined_columns: usize = config.lookups.iter().map(|l| l.columns.len()).sum();
+    match columns.iter().position(|c| c == name) {
+        Some(index) if index < input_headers.len() => {
+            format!("'{}' (input column {})", name, index)
+        }
+        Some(index) if index < input_headers.len() + joined_columns => {
+            format!("'{}' (joined column {})", name, index)
+        }
+        Some(index) => format!("'{}' (computed column {})", name, index),
+        None => format!("'{}' (not part of the input file)", name),
+    }
+}
+
+/// Describes the condition a filter applies to its column, if any.
+fn describe_condition(filter: &ColumnFilter) -> Option<String> {
+    let mut files = Vec::new();
+    if let Some(values_file) = &filter.values_file {
+        files.push(format!("'{}'", values_file));
+    }
+    if let Some(values_from) = &filter.values_from {
+        files.push(format!(
+            "column '{}' of '{}'",
+            values_from.column, values_from.file
+        ));
+    }
+    let loaded_from = if files.is_empty() {
+        String::new()
+    } else {
+        format!(" (loaded from {})", files.join(" and "))
+    };
+
+    match (&filter.values, &filter.min, &filter.max) {
+        (None, _, _) if filter.values_bloom_filter.is_some() => Some(format!(
+            "is one of the values of a bloom filter that allows false positives{}",
+            loaded_from
+        )),
+        (Some(values), _, _) => Some(format!("is one of {} values{}", values.len(), loaded_from)),
+        (None, Some(min), Some(max)) => Some(format!("is between '{}' and '{}'", min, max)),
+        (None, Some(min), None) => Some(format!("is at least '{}'", min)),
+        (None, None, Some(max)) => Some(format!("is at most '{}'", max)),
+        (None, None, None) => None,
+    }
+}
@@ -0,0 +1,535 @@
+use config::{ColumnFilter, ComputedColumn, FilterConfig, OutputColumn};
+use expression::{Expression, Token, Type};
+use std::fmt;
+
+/// A one-off extraction given on the command line instead of in a configuration file.
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    /// Conditions combined with `and`, e.g. `country in (DE, AT) and amount >= 100`
+    pub where_clause: Option<String>,
+    /// Comma separated output columns. Defaults to all input columns.
+    pub select: Option<String>,
+    /// Comma separated sort columns, each optionally followed by `:asc` or `:desc`
+    pub sort_by: Option<String>,
+    /// The name of the output file
+    pub output: String,
+}
+
+/// An error in one of the parameters of a [`Query`] or in an SQL statement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryError {
+    /// The name of the parameter or file, e.g. `--where`
+    pub parameter: String,
+    /// The value of the parameter or the contents of the file
+    pub source: String,
+    pub message: String,
+    /// The character offset of the offending token in the source
+    pub position: usize,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Only the line containing the offending token is shown
+        let mut line_start = 0;
+        let mut line_number = 1;
+        for (index, c) in self.source.chars().enumerate().take(self.position) {
+            if c == '\n' {
+                line_start = index + 1;
+                line_number += 1;
+            }
+        }
+        let line: String = self
+            .source
+            .chars()
+            .skip(line_start)
+            .take_while(|&c| c != '\n')
+            .collect();
+
+        write!(f, "Cannot parse {}", self.parameter)?;
+        if self.source.contains('\n') {
+            write!(f, " (line {})", line_number)?;
+        }
+        writeln!(f, ": {}", self.message)?;
+        writeln!(f, "  {}", line.trim_end())?;
+        write!(f, "  {}^", " ".repeat(self.position - line_start))
+    }
+}
+
+/// The source of a query that is being parsed, which errors refer to.
+pub(crate) struct Source<'a> {
+    /// The name of the parameter or file
+    pub parameter: &'a str,
+    pub text: &'a str,
+    pub chars: Vec<char>,
+}
+
+impl<'a> Source<'a> {
+    pub fn new(parameter: &'a str, text: &'a str) -> Self {
+        Source {
+            parameter,
+            text,
+            chars: text.chars().collect(),
+        }
+    }
+
+    /// Creates an error pointing at the given character offset.
+    pub fn error(&self, message: String, position: usize) -> QueryError {
+        QueryError {
+            parameter: self.parameter.to_string(),
+            source: self.text.to_string(),
+            message,
+            position,
+        }
+    }
+
+    /// Creates an error pointing at the token with the given index, or at the given end offset
+    /// if there is no such token.
+    pub fn error_at(
+        &self,
+        message: &str,
+        tokens: &[(Token, usize)],
+        index: usize,
+        end: usize,
+    ) -> QueryError {
+        let position = tokens.get(index).map_or(end, |(_, position)| *position);
+        self.error(message.to_string(), position)
+    }
+
+    /// Splits the source into tokens.
+    pub fn tokenize(&self) -> Result<Vec<(Token, usize)>, QueryError> {
+        expression::tokenize(self.text).map_err(|e| self.error(e.message, e.position))
+    }
+}
+
+/// Creates an empty configuration item for a query.
+pub(crate) fn empty_config(output: String) -> FilterConfig {
+    FilterConfig {
+        filters: Vec::new(),
+        lookups: Vec::new(),
+        computed_columns: Vec::new(),
+        script: None,
+        output,
+        output_columns: None,
+        transforms: Vec::new(),
+        sort_columns: None,
+        descending_columns: Vec::new(),
+        distinct: None,
+        aggregate: None,
+    }
+}
+
+/// Builds the configuration item of a query. Conditions on a single column with `=`, `in` or
+/// `between` become value lists and ranges, all other conditions become computed columns that
+/// rows are filtered by.
+///
+/// # Arguments
+/// * `query` - The query
+/// * `input_headers` - The header row of the input file
+pub fn build_config(query: &Query, input_headers: &[String]) -> Result<FilterConfig, QueryError> {
+    let mut config = empty_config(query.output.clone());
+
+    if let Some(text) = &query.where_clause {
+        let source = Source::new("--where", text);
+        let tokens = source.tokenize()?;
+        compile_conditions(
+            &source,
+            &tokens,
+            source.chars.len(),
+            input_headers,
+            &mut config,
+        )?;
+    }
+
+    let output_columns = match &query.select {
+        Some(text) => {
+            let source = Source::new("--select", text);
+            let tokens = source.tokenize()?;
+            let mut columns = Vec::new();
+            for (column, position, rest) in split_list(&source, &tokens)? {
+                if let Some((token, position)) = rest.first() {
+                    let message = format!("Unexpected token '{}'", token);
+                    return Err(source.error(message, *position));
+                }
+                check_column(&source, &column, input_headers, position)?;
+                columns.push(column);
+            }
+            columns
+        }
+        None => input_headers.to_vec(),
+    };
+
+    if let Some(text) = &query.sort_by {
+        let source = Source::new("--sort-by", text);
+        let tokens = source.tokenize()?;
+        let mut sort_columns = Vec::new();
+        for (column, position, rest) in split_list(&source, &tokens)? {
+            let descending = match rest {
+                [] => false,
+                [(Token::Colon, _), (Token::Identifier(direction), _)]
+                    if direction.eq_ignore_ascii_case("asc") =>
+                {
+                    false
+                }
+                [(Token::Colon, _), (Token::Identifier(direction), _)]
+                    if direction.eq_ignore_ascii_case("desc") =>
+                {
+                    true
+                }
+                [(Token::Colon, _), (Token::Identifier(direction), _), ..]
+                    if direction.eq_ignore_ascii_case("asc")
+                        || direction.eq_ignore_ascii_case("desc") =>
+                {
+                    return Err(source.error_at("Expected ','", rest, 2, source.chars.len()));
+                }
+                [(Token::Colon, _), ..] => {
+                    return Err(source.error_at(
+                        "Expected 'asc' or 'desc'",
+                        rest,
+                        1,
+                        source.chars.len(),
+                    ));
+                }
+                [(token, position), ..] => {
+                    let message = format!("Unexpected token '{}'", token);
+                    return Err(source.error(message, *position));
+                }
+            };
+            if !output_columns.contains(&column) {
+                return Err(source.error(
+                    format!("Sort column '{}' is not an output column", column),
+                    position,
+                ));
+            }
+            if descending {
+                config.descending_columns.push(column.clone());
+            }
+            sort_columns.push(column);
+        }
+        config.sort_columns = Some(sort_columns);
+    }
+
+    config.output_columns = Some(
+        output_columns
+            .into_iter()
+            .map(|name| OutputColumn {
+                name,
+                source: None,
+                expression: None,
+            })
+            .collect(),
+    );
+    Ok(config)
+}
+
+/// Compiles conditions combined with `and` and adds a filter for each of them.
+///
+/// # Arguments
+/// * `source` - The source the tokens were read from
+/// * `tokens` - The tokens of the conditions
+/// * `end` - The character offset after the last condition
+/// * `input_headers` - The header row of the input file
+/// * `config` - The configuration item the filters are added to
+pub(crate) fn compile_conditions(
+    source: &Source,
+    tokens: &[(Token, usize)],
+    end: usize,
+    input_headers: &[String],
+    config: &mut FilterConfig,
+) -> Result<(), QueryError> {
+    for (start, stop) in split_conditions(tokens) {
+        let condition = &tokens[start..stop];
+        if condition.is_empty() {
+            return Err(source.error_at("Expected a condition", tokens, stop, end));
+        }
+        let position = condition[0].1;
+        let condition_end = tokens.get(stop).map_or(end, |(_, p)| *p);
+
+        if let Some(filter) =
+            parse_column_condition(source, condition, condition_end, input_headers)?
+        {
+            check_column(source, &filter.column, input_headers, position)?;
+            config.filters.push(filter);
+            continue;
+        }
+
+        // Positions of expression errors are relative to the condition
+        let expression_source: String = source.chars[position..condition_end].iter().collect();
+        let expression = expression::parse(&expression_source)
+            .map_err(|e| source.error(e.message, position + e.position))?;
+        for column in expression.columns() {
+            let column_position = condition
+                .iter()
+                .find(|(t, _)| column_name(t) == Some(column))
+                .map_or(position, |(_, p)| *p);
+            check_column(source, column, input_headers, column_position).map_err(|mut e| {
+                e.message += " (text values need quotes in conditions other than = and in)";
+                e
+            })?;
+        }
+        match expression.type_check(&|_| Type::Field) {
+            Ok(Type::Bool) | Ok(Type::Field) => {}
+            Ok(_) => {
+                return Err(source.error(
+                    String::from("Expected a condition, but found a value"),
+                    position,
+                ))
+            }
+            Err(e) => return Err(source.error(e, position)),
+        }
+        add_expression_filter(expression, input_headers, config);
+    }
+
+    Ok(())
+}
+
+/// Splits tokens into conditions separated by `and`. Returns the start and end index of every
+/// condition. Neither `and` inside of parentheses nor the `and` of `between` separate
+/// conditions.
+fn split_conditions(tokens: &[(Token, usize)]) -> Vec<(usize, usize)> {
+    let mut conditions = Vec::new();
+    let mut depth = 0;
+    let mut in_between = false;
+    let mut start = 0;
+    for (index, (token, _)) in tokens.iter().enumerate() {
+        match token {
+            Token::LeftParen => depth += 1,
+            Token::RightParen => depth -= 1,
+            Token::Identifier(i) if depth == 0 && i.eq_ignore_ascii_case("between") => {
+                in_between = true;
+            }
+            Token::Identifier(i) if depth == 0 && i.eq_ignore_ascii_case("and") => {
+                if in_between {
+                    in_between = false;
+                } else {
+                    conditions.push((start, index));
+                    start = index + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    if !tokens.is_empty() {
+        conditions.push((start, tokens.len()));
+    }
+    conditions
+}
+
+/// Parses a condition that compares a column with a list of values or a range of text values,
+/// i.e. `column = 'value'`, `column in (value, ...)` or `column between 'a' and 'b'`. Returns
+/// `None` if the condition has another form. Comparisons with numbers or other columns are left
+/// to expressions.
+///
+/// # Arguments
+/// * `source` - The source the tokens were read from
+/// * `condition` - The tokens of the condition
+/// * `end` - The character offset after the condition
+/// * `input_headers` - The header row of the input file
+fn parse_column_condition(
+    source: &Source,
+    condition: &[(Token, usize)],
+    end: usize,
+    input_headers: &[String],
+) -> Result<Option<ColumnFilter>, QueryError> {
+    let column = match condition.first().and_then(|(t, _)| column_name(t)) {
+        Some(column) => column.to_string(),
+        None => return Ok(None),
+    };
+    let mut filter = ColumnFilter {
+        column,
+        include: false,
+        values: None,
+        values_file: None,
+        values_from: None,
+        bloom_filter: None,
+        values_bloom_filter: None,
+        min: None,
+        max: None,
+    };
+
+    match condition.get(1) {
+        Some((Token::Equal, _)) => match &condition[2..] {
+            [(Token::Text(value), _)] => {
+                filter.values = Some(std::iter::once(value.clone()).collect())
+            }
+            [(Token::Identifier(value), _)] if !input_headers.contains(value) => {
+                filter.values = Some(std::iter::once(value.clone()).collect())
+            }
+            _ => return Ok(None),
+        },
+        Some((Token::Identifier(i), _)) if i.eq_ignore_ascii_case("in") => {
+            let values = parse_value_list(source, &condition[2..], end)?;
+            filter.values = Some(values.into_iter().collect());
+        }
+        Some((Token::Identifier(i), _)) if i.eq_ignore_ascii_case("between") => {
+            match &condition[2..] {
+                [(Token::Text(min), _), (Token::Identifier(and), _), (Token::Text(max), _)]
+                    if and.eq_ignore_ascii_case("and") =>
+                {
+                    filter.min = Some(min.clone());
+                    filter.max = Some(max.clone());
+                }
+                _ => return Ok(None),
+            }
+        }
+        _ => return Ok(None),
+    }
+
+    Ok(Some(filter))
+}
+
+/// Parses a parenthesized, comma separated list of values.
+///
+/// # Arguments
+/// * `source` - The source the tokens were read from
+/// * `tokens` - The tokens following the `in` keyword
+/// * `end` - The character offset after the condition
+fn parse_value_list(
+    source: &Source,
+    tokens: &[(Token, usize)],
+    end: usize,
+) -> Result<Vec<String>, QueryError> {
+    if tokens.first().map(|(t, _)| t) != Some(&Token::LeftParen) {
+        return Err(source.error_at("Expected '(' after 'in'", tokens, 0, end));
+    }
+
+    let mut values = Vec::new();
+    let mut index = 1;
+    loop {
+        let (value, length) = parse_value(&source.chars, &tokens[index..])
+            .ok_or_else(|| source.error_at("Expected a value", tokens, index, end))?;
+        values.push(value);
+        index += length;
+        match tokens.get(index).map(|(t, _)| t) {
+            Some(Token::Comma) => index += 1,
+            Some(Token::RightParen) => break,
+            _ => return Err(source.error_at("Expected ',' or ')'", tokens, index, end)),
+        }
+    }
+
+    if index + 1 < tokens.len() {
+        return Err(source.error_at("Expected 'and'", tokens, index + 1, end));
+    }
+    Ok(values)
+}
+
+/// Parses a literal value at the start of the given tokens. Bare words are values as well, so
+/// that `country in (DE, AT)` does not need quotes. Numbers keep their original spelling.
+/// Returns the value and the number of tokens it consists of.
+fn parse_value(chars: &[char], tokens: &[(Token, usize)]) -> Option<(String, usize)> {
+    let number = |position: usize| -> String {
+        chars[position..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit() || **c == '.')
+            .collect()
+    };
+
+    match tokens {
+        [(Token::Text(t), _), ..]
+        | [(Token::Identifier(t), _), ..]
+        | [(Token::QuotedIdentifier(t), _), ..] => Some((t.clone(), 1)),
+        [(Token::Number(_), position), ..] => Some((number(*position), 1)),
+        [(Token::Minus, _), (Token::Number(_), position), ..] => {
+            Some((format!("-{}", number(*position)), 2))
+        }
+        _ => None,
+    }
+}
+
+/// Adds a computed column holding the result of a condition along with a filter that only lets
+/// rows pass for which the condition is true.
+fn add_expression_filter(
+    expression: Expression,
+    input_headers: &[String],
+    config: &mut FilterConfig,
+) {
+    // The name of the computed column must not conflict with any other column
+    let mut index = config.computed_columns.len() + 1;
+    let name = loop {
+        let name = format!("_where_{}", index);
+        if !input_headers.contains(&name) {
+            break name;
+        }
+        index += 1;
+    };
+
+    config.computed_columns.push(ComputedColumn {
+        name: name.clone(),
+        expression,
+    });
+    config.filters.push(ColumnFilter {
+        column: name,
+        include: false,
+        values: Some(std::iter::once(String::from("true")).collect()),
+        values_file: None,
+        values_from: None,
+        bloom_filter: None,
+        values_bloom_filter: None,
+        min: None,
+        max: None,
+    });
+}
+
+/// A column name in a list, along with its character offset and the tokens following it.
+type ListEntry<'t> = (String, usize, &'t [(Token, usize)]);
+
+/// Splits the tokens of a comma separated list of column names, which may be quoted with
+/// backticks as in conditions. Returns every column name along with its character offset and the
+/// tokens that follow it up to the next comma.
+///
+/// # Arguments
+/// * `source` - The source the tokens were read from
+/// * `tokens` - The tokens of the list
+fn split_list<'t>(
+    source: &Source,
+    tokens: &'t [(Token, usize)],
+) -> Result<Vec<ListEntry<'t>>, QueryError> {
+    let mut entries = Vec::new();
+    let mut start = 0;
+    loop {
+        let end = tokens[start..]
+            .iter()
+            .position(|(token, _)| *token == Token::Comma)
+            .map_or(tokens.len(), |index| start + index);
+        let entry = tokens[start..end]
+            .split_first()
+            .and_then(|((token, position), rest)| {
+                Some((column_name(token)?.to_string(), *position, rest))
+            });
+        match entry {
+            Some(entry) => entries.push(entry),
+            None => {
+                return Err(source.error_at(
+                    "Expected a column name",
+                    tokens,
+                    start,
+                    source.chars.len(),
+                ));
+            }
+        }
+        if end == tokens.len() {
+            return Ok(entries);
+        }
+        start = end + 1;
+    }
+}
+
+/// Makes sure a column is part of the input file.
+pub(crate) fn check_column(
+    source: &Source,
+    column: &str,
+    input_headers: &[String],
+    position: usize,
+) -> Result<(), QueryError> {
+    match input_headers.iter().any(|h| h == column) {
+        true => Ok(()),
+        false => Err(source.error(format!("Unknown column '{}'", column), position)),
+    }
+}
+
+/// Returns the column name a token refers to, if any.
+pub(crate) fn column_name(token: &Token) -> Option<&str> {
+    match token {
+        Token::Identifier(name) | Token::QuotedIdentifier(name) => Some(name),
+        _ => None,
+    }
+}
@@ -0,0 +1,113 @@
+use config::FilterConfig;
+use filter::FilterStats;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A machine-readable summary of a run, which is written to a JSON file.
+#[derive(Debug, Serialize)]
+pub struct RunReport {
+    /// Path of the input file
+    pub input: String,
+    /// The number of rows read from the input file
+    pub rows_read: usize,
+    /// The number of rows that did not match any configuration item
+    pub rows_rejected: usize,
+    /// The number of threads used in the filter stage
+    pub filter_threads: usize,
+    /// The number of threads used in the sort and distinct stages
+    pub sort_threads: usize,
+    pub timings: StageTimings,
+    /// One entry per configuration item, in the order of the configuration file
+    pub outputs: Vec<OutputReport>,
+}
+
+/// The duration of all processing stages in milliseconds.
+#[derive(Debug, Default, Serialize)]
+pub struct StageTimings {
+    pub filter_ms: u128,
+    /// Absent if sorting is disabled
+    pub sort_ms: Option<u128>,
+    pub distinct_ms: u128,
+    pub total_ms: u128,
+}
+
+/// The summary of one output file.
+#[derive(Debug, Serialize)]
+pub struct OutputReport {
+    /// The name of the output file as defined in the configuration
+    pub output: String,
+    /// Path of the output file
+    pub path: String,
+    /// The number of input rows that matched the filters of the configuration item
+    pub matched_rows: usize,
+    /// The number of rows in the final output file, excluding the header row. Differs from
+    /// `matched_rows` if rows are deduplicated or aggregated.
+    pub rows_written: usize,
+    /// The size of the final output file
+    pub bytes_written: u64,
+}
+
+impl RunReport {
+    /// Creates the report after all stages have finished.
+    ///
+    /// # Arguments
+    /// * `csv_file_path` - Path to the input file
+    /// * `all_filter_configs` - A vector containing all configuration items
+    /// * `output_dir_path` - Path to the directory that data was written to
+    /// * `stats` - The record counts of the filter stage
+    /// * `threads` - The number of threads of the filter and of the sort stage
+    /// * `timings` - The duration of all stages
+    ///
+    /// # Panics
+    /// This function will panic if an output file cannot be read.
+    pub fn new(
+        csv_file_path: &str,
+        all_filter_configs: &[Arc<FilterConfig>],
+        output_dir_path: &str,
+        stats: FilterStats,
+        threads: (usize, usize),
+        timings: StageTimings,
+    ) -> Self {
+        let outputs = all_filter_configs
+            .iter()
+            .map(|config| {
+                let path = Path::new(output_dir_path).join(&config.output);
+                let rows_written = filter::count_records(&path).unwrap_or_else(|e| {
+                    panic!("Cannot read output file '{}': {}", path.display(), e)
+                });
+                let bytes_written = fs::metadata(&path).map(|m| m.len()).unwrap_or_else(|e| {
+                    panic!("Cannot read output file '{}': {}", path.display(), e)
+                });
+                OutputReport {
+                    output: config.output.clone(),
+                    path: path.display().to_string(),
+                    matched_rows: stats.matched_rows.get(&config.output).copied().unwrap_or(0),
+                    rows_written,
+                    bytes_written,
+                }
+            })
+            .collect();
+
+        RunReport {
+            input: csv_file_path.to_string(),
+            rows_read: stats.rows_read,
+            rows_rejected: stats.rows_rejected,
+            filter_threads: threads.0,
+            sort_threads: threads.1,
+            timings,
+            outputs,
+        }
+    }
+
+    /// Writes the report as JSON to a file.
+    ///
+    /// # Panics
+    /// This function will panic if the report file cannot be written.
+    pub fn write(&self, report_file_path: &str) {
+        let json = serde_json::to_string_pretty(self).expect("Cannot serialize report");
+        fs::write(report_file_path, json + "\n")
+            .unwrap_or_else(|e| panic!("Cannot write report file '{}': {}", report_file_path, e));
+    }
+}
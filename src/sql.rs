@@ -0,0 +1,493 @@
+use crate::query::{self, QueryError, Source};
+use config::{Distinct, FilterConfig, Keep, OutputColumn};
+use expression::{Token, Type};
+use std::path::Path;
+
+/// SQL-like statements that each describe one output file, e.g.
+/// `SELECT a, b AS bee INTO 'out.csv' FROM input WHERE c BETWEEN '2020' AND '2021' ORDER BY a DESC`.
+#[derive(Debug, Clone, Default)]
+pub struct SqlQuery {
+    /// The name errors refer to, e.g. `--query` or the path of an `.sql` file
+    pub name: String,
+    /// One or more statements separated by semicolons
+    pub statements: String,
+    /// The name of the output file of a single statement without `INTO`
+    pub output: String,
+}
+
+/// Keywords of SQL constructs that cannot be expressed by configuration items, along with the
+/// error reported for them.
+const UNSUPPORTED_KEYWORDS: [(&str, &str); 13] = [
+    ("join", "Joins are not supported"),
+    ("group", "GROUP BY is not supported"),
+    ("having", "HAVING is not supported"),
+    ("union", "Combining statements is not supported"),
+    ("intersect", "Combining statements is not supported"),
+    ("except", "Combining statements is not supported"),
+    ("limit", "LIMIT is not supported"),
+    ("offset", "OFFSET is not supported"),
+    ("like", "LIKE is not supported"),
+    ("is", "IS NULL is not supported, compare with '' instead"),
+    ("exists", "Subqueries are not supported"),
+    (
+        "case",
+        "CASE is not supported, use if(condition, then, else) instead",
+    ),
+    ("with", "WITH is not supported"),
+];
+
+/// Functions that aggregate rows, which require GROUP BY.
+const AGGREGATE_FUNCTIONS: [&str; 5] = ["count", "sum", "avg", "min", "max"];
+
+/// Compiles SQL-like statements into configuration items, one per statement.
+///
+/// # Arguments
+/// * `sql` - The statements
+/// * `input_headers` - The header row of the input file
+pub fn compile(sql: &SqlQuery, input_headers: &[String]) -> Result<Vec<FilterConfig>, QueryError> {
+    // Comments and semicolons are blanked out, so that offsets of tokens still refer to the
+    // original text
+    let (sanitized, boundaries) = sanitize(&sql.statements);
+    let mut source = Source::new(&sql.name, &sql.statements);
+    source.chars = sanitized.chars().collect();
+    let tokens = match expression::tokenize(&sanitized) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            // Unsupported constructs like qualified column names of joins often cannot be
+            // tokenized, in which case the construct is the more helpful error
+            let start = boundaries
+                .iter()
+                .rev()
+                .find(|b| **b < e.position)
+                .map_or(0, |b| b + 1);
+            let prefix: String = source.chars[start..e.position].iter().collect();
+            let unsupported = expression::tokenize(&prefix).ok().and_then(|tokens| {
+                find_unsupported(&tokens).map(|(message, index)| {
+                    source.error(message.to_string(), start + tokens[index].1)
+                })
+            });
+            return Err(unsupported.unwrap_or_else(|| source.error(e.message, e.position)));
+        }
+    };
+
+    let mut statements = Vec::new();
+    let mut start = 0;
+    for end in boundaries.into_iter().chain([source.chars.len()]) {
+        let statement: Vec<(Token, usize)> = tokens
+            .iter()
+            .filter(|(_, position)| *position >= start && *position < end)
+            .cloned()
+            .collect();
+        if !statement.is_empty() {
+            statements.push((statement, end));
+        }
+        start = end;
+    }
+    if statements.is_empty() {
+        return Err(source.error(String::from("Expected a SELECT statement"), 0));
+    }
+
+    let several = statements.len() > 1;
+    let mut configs = Vec::new();
+    for (tokens, end) in &statements {
+        let mut parser = StatementParser {
+            source: &source,
+            tokens,
+            pos: 0,
+            end: *end,
+            input_headers,
+        };
+        let (config, into) = parser.parse()?;
+        let config = match into {
+            Some(output) => FilterConfig { output, ..config },
+            None if several => {
+                return Err(source.error(
+                    String::from(
+                        "Every statement needs an output file (INTO name) if there are several",
+                    ),
+                    tokens[0].1,
+                ))
+            }
+            None => FilterConfig {
+                output: sql.output.clone(),
+                ..config
+            },
+        };
+        configs.push(config);
+    }
+    Ok(configs)
+}
+
+/// Blanks out comments and semicolons outside of quotes. Returns the sanitized text, which has
+/// the same number of characters, and the offsets of all semicolons.
+fn sanitize(text: &str) -> (String, Vec<usize>) {
+    let mut sanitized = String::with_capacity(text.len());
+    let mut boundaries = Vec::new();
+    let mut quote: Option<char> = None;
+    let mut in_comment = false;
+    let mut chars = text.chars().enumerate().peekable();
+
+    while let Some((index, c)) = chars.next() {
+        let blank = match (quote, c) {
+            _ if in_comment => {
+                in_comment = c != '\n';
+                in_comment
+            }
+            (Some(q), _) => {
+                if c == q {
+                    quote = None;
+                }
+                false
+            }
+            (None, '\'' | '"' | '`') => {
+                quote = Some(c);
+                false
+            }
+            (None, '-') if chars.peek().map(|(_, c)| *c) == Some('-') => {
+                in_comment = true;
+                true
+            }
+            (None, ';') => {
+                boundaries.push(index);
+                true
+            }
+            _ => false,
+        };
+        sanitized.push(if blank { ' ' } else { c });
+    }
+
+    (sanitized, boundaries)
+}
+
+/// Finds the first construct of a statement that cannot be expressed by configuration items.
+/// Returns the error message along with the index of the offending token.
+fn find_unsupported(tokens: &[(Token, usize)]) -> Option<(&'static str, usize)> {
+    for (index, (token, _)) in tokens.iter().enumerate() {
+        let keyword = match token {
+            Token::Identifier(i) => i.to_lowercase(),
+            _ => continue,
+        };
+        if keyword == "select" && index > 0 {
+            return Some(("Subqueries are not supported", index));
+        }
+        if let Some((_, message)) = UNSUPPORTED_KEYWORDS.iter().find(|(k, _)| *k == keyword) {
+            return Some((message, index));
+        }
+        let is_call = tokens.get(index + 1).map(|(t, _)| t) == Some(&Token::LeftParen);
+        if is_call && AGGREGATE_FUNCTIONS.contains(&keyword.as_str()) {
+            return Some(("Aggregate functions are not supported", index));
+        }
+    }
+    None
+}
+
+/// Parses a single statement.
+struct StatementParser<'a> {
+    source: &'a Source<'a>,
+    tokens: &'a [(Token, usize)],
+    pos: usize,
+    /// The character offset after the statement
+    end: usize,
+    input_headers: &'a [String],
+}
+
+impl StatementParser<'_> {
+    /// Parses the statement. Returns its configuration item along with the output file given
+    /// by `INTO`, if any.
+    fn parse(&mut self) -> Result<(FilterConfig, Option<String>), QueryError> {
+        self.check_supported()?;
+        let mut config = query::empty_config(String::new());
+
+        self.expect_keyword("select")?;
+        if self.keyword_at(0, "distinct") {
+            self.pos += 1;
+            config.distinct = Some(Distinct {
+                columns: None,
+                keep: Keep::First,
+                spill_to_disk: false,
+            });
+        }
+
+        let select_end = self
+            .find_keyword(&["into", "from"])
+            .ok_or_else(|| self.error("Expected FROM", self.tokens.len()))?;
+        let output_columns = self.parse_select_list(select_end)?;
+        self.pos = select_end;
+
+        let into = if self.keyword_at(0, "into") {
+            self.pos += 1;
+            Some(self.parse_output_file()?)
+        } else {
+            None
+        };
+
+        self.expect_keyword("from")?;
+        match self.tokens.get(self.pos) {
+            Some((Token::Identifier(_), _)) | Some((Token::QuotedIdentifier(_), _)) => {
+                self.pos += 1
+            }
+            Some((Token::LeftParen, _)) => {
+                return Err(self.error("Subqueries are not supported", self.pos))
+            }
+            _ => return Err(self.error("Expected the name of the input", self.pos)),
+        }
+        if self.tokens.get(self.pos).map(|(t, _)| t) == Some(&Token::Comma) {
+            return Err(self.error("Joins are not supported", self.pos));
+        }
+
+        if self.keyword_at(0, "where") {
+            self.pos += 1;
+            let where_end = self.find_keyword(&["order"]).unwrap_or(self.tokens.len());
+            let end = self.tokens.get(where_end).map_or(self.end, |(_, p)| *p);
+            if where_end == self.pos {
+                return Err(self.error("Expected a condition", self.pos));
+            }
+            query::compile_conditions(
+                self.source,
+                &self.tokens[self.pos..where_end],
+                end,
+                self.input_headers,
+                &mut config,
+            )?;
+            self.pos = where_end;
+        }
+
+        if self.keyword_at(0, "order") {
+            self.pos += 1;
+            self.expect_keyword("by")?;
+            self.parse_order_by(&output_columns, &mut config)?;
+        }
+
+        if self.pos < self.tokens.len() {
+            let message = format!("Unexpected token '{}'", self.tokens[self.pos].0);
+            return Err(self.error(&message, self.pos));
+        }
+
+        config.output_columns = Some(output_columns);
+        Ok((config, into))
+    }
+
+    /// Makes sure the statement does not use constructs that cannot be expressed by
+    /// configuration items.
+    fn check_supported(&self) -> Result<(), QueryError> {
+        if let Some((message, index)) = find_unsupported(self.tokens) {
+            return Err(self.error(message, index));
+        }
+        match self.tokens.first() {
+            Some((Token::Identifier(i), _)) if i.eq_ignore_ascii_case("select") => Ok(()),
+            _ => Err(self.error("Only SELECT statements are supported", 0)),
+        }
+    }
+
+    /// Parses the output columns between `SELECT` and the given token index. `*` selects all
+    /// input columns.
+    fn parse_select_list(&self, end: usize) -> Result<Vec<OutputColumn>, QueryError> {
+        let mut output_columns = Vec::new();
+        for (start, stop) in self.split_at_commas(self.pos, end) {
+            let item = &self.tokens[start..stop];
+            if item.is_empty() {
+                return Err(self.error("Expected a column", stop));
+            }
+            if item[0].0 == Token::Star && item.len() == 1 {
+                output_columns.extend(self.input_headers.iter().map(|name| OutputColumn {
+                    name: name.clone(),
+                    source: None,
+                    expression: None,
+                }));
+                continue;
+            }
+
+            // An alias follows `AS` at the end of the item
+            let (body_end, alias) = match item {
+                [.., (Token::Identifier(keyword), _), (name, _)]
+                    if keyword.eq_ignore_ascii_case("as") =>
+                {
+                    match query::column_name(name) {
+                        Some(name) => (stop - 2, Some(name.to_string())),
+                        None => return Err(self.error("Expected a column name", stop - 1)),
+                    }
+                }
+                _ => (stop, None),
+            };
+            if body_end == start {
+                return Err(self.error("Expected a column", start));
+            }
+
+            let body = &self.tokens[start..body_end];
+            if let [(token, position)] = body {
+                if let Some(column) = query::column_name(token) {
+                    query::check_column(self.source, column, self.input_headers, *position)?;
+                    output_columns.push(OutputColumn {
+                        name: alias.clone().unwrap_or_else(|| column.to_string()),
+                        source: alias.as_ref().map(|_| column.to_string()),
+                        expression: None,
+                    });
+                    continue;
+                }
+            }
+
+            let position = body[0].1;
+            let body_end_position = self.position(body_end);
+            let text: String = self.source.chars[position..body_end_position]
+                .iter()
+                .collect();
+            let expression = expression::parse(&text)
+                .map_err(|e| self.source.error(e.message, position + e.position))?;
+            for column in expression.columns() {
+                let column_position = body
+                    .iter()
+                    .find(|(t, _)| query::column_name(t) == Some(column))
+                    .map_or(position, |(_, p)| *p);
+                query::check_column(self.source, column, self.input_headers, column_position)?;
+            }
+            if let Err(e) = expression.type_check(&|_| Type::Field) {
+                return Err(self.source.error(e, position));
+            }
+            let name = alias.ok_or_else(|| {
+                self.source.error(
+                    format!("Computed columns need a name, e.g. {} AS name", text.trim()),
+                    position,
+                )
+            })?;
+            output_columns.push(OutputColumn {
+                name,
+                source: None,
+                expression: Some(expression),
+            });
+        }
+        Ok(output_columns)
+    }
+
+    /// Parses the output file following `INTO`. Names without an extension get `.csv`
+    /// appended.
+    fn parse_output_file(&mut self) -> Result<String, QueryError> {
+        let output = match self.tokens.get(self.pos) {
+            Some((Token::Text(output), _)) => output.clone(),
+            Some((Token::Identifier(name), _)) | Some((Token::QuotedIdentifier(name), _)) => {
+                match Path::new(name).extension() {
+                    Some(_) => name.clone(),
+                    None => format!("{}.csv", name),
+                }
+            }
+            _ => return Err(self.error("Expected the name of the output file", self.pos)),
+        };
+        self.pos += 1;
+        Ok(output)
+    }
+
+    /// Parses the sort columns following `ORDER BY`. Sort columns may be referred to by the name
+    /// of their output column or by the input column they are copied from.
+    fn parse_order_by(
+        &mut self,
+        output_columns: &[OutputColumn],
+        config: &mut FilterConfig,
+    ) -> Result<(), QueryError> {
+        let mut sort_columns = Vec::new();
+        for (start, stop) in self.split_at_commas(self.pos, self.tokens.len()) {
+            let (column, position) = match self.tokens.get(start) {
+                Some((token, position)) if start < stop => match query::column_name(token) {
+                    Some(column) => (column, *position),
+                    None => return Err(self.error("Expected a column", start)),
+                },
+                _ => return Err(self.error("Expected a column", start)),
+            };
+            let descending = match &self.tokens[start + 1..stop] {
+                [] => false,
+                [(Token::Identifier(d), _)] if d.eq_ignore_ascii_case("asc") => false,
+                [(Token::Identifier(d), _)] if d.eq_ignore_ascii_case("desc") => true,
+                _ => return Err(self.error("Expected ASC, DESC, ',' or the end", start + 1)),
+            };
+
+            let name = output_columns
+                .iter()
+                .find(|c| c.name == column)
+                .or_else(|| {
+                    output_columns
+                        .iter()
+                        .find(|c| c.expression.is_none() && c.source_column() == column)
+                })
+                .map(|c| c.name.clone())
+                .ok_or_else(|| {
+                    self.source.error(
+                        format!("ORDER BY column '{}' is not selected", column),
+                        position,
+                    )
+                })?;
+            if descending {
+                config.descending_columns.push(name.clone());
+            }
+            sort_columns.push(name);
+        }
+        config.sort_columns = Some(sort_columns);
+        self.pos = self.tokens.len();
+        Ok(())
+    }
+
+    /// Splits the tokens between the given indexes at commas outside of parentheses. Returns the
+    /// start and end index of every part.
+    fn split_at_commas(&self, start: usize, end: usize) -> Vec<(usize, usize)> {
+        let mut parts = Vec::new();
+        let mut depth = 0;
+        let mut part_start = start;
+        for index in start..end {
+            match self.tokens[index].0 {
+                Token::LeftParen => depth += 1,
+                Token::RightParen => depth -= 1,
+                Token::Comma if depth == 0 => {
+                    parts.push((part_start, index));
+                    part_start = index + 1;
+                }
+                _ => {}
+            }
+        }
+        parts.push((part_start, end));
+        parts
+    }
+
+    /// Returns the index of the first of the given keywords outside of parentheses, starting at
+    /// the current token.
+    fn find_keyword(&self, keywords: &[&str]) -> Option<usize> {
+        let mut depth = 0;
+        for index in self.pos..self.tokens.len() {
+            match &self.tokens[index].0 {
+                Token::LeftParen => depth += 1,
+                Token::RightParen => depth -= 1,
+                Token::Identifier(i)
+                    if depth == 0 && keywords.iter().any(|k| i.eq_ignore_ascii_case(k)) =>
+                {
+                    return Some(index)
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// Checks if the token at the given offset from the current one is the given keyword.
+    fn keyword_at(&self, offset: usize, keyword: &str) -> bool {
+        match self.tokens.get(self.pos + offset) {
+            Some((Token::Identifier(i), _)) => i.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), QueryError> {
+        if !self.keyword_at(0, keyword) {
+            let message = format!("Expected {}", keyword.to_uppercase());
+            return Err(self.error(&message, self.pos));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Returns the character offset of the token with the given index, or the end of the
+    /// statement if there is no such token.
+    fn position(&self, index: usize) -> usize {
+        self.tokens.get(index).map_or(self.end, |(_, p)| *p)
+    }
+
+    /// Creates an error pointing at the token with the given index.
+    fn error(&self, message: &str, index: usize) -> QueryError {
+        self.source.error_at(message, self.tokens, index, self.end)
+    }
+}
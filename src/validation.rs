@@ -0,0 +1,694 @@
+use crate::output::{
+    self, CHECKPOINT_FILE_NAME, JOURNAL_FILE_NAME, MANIFEST_FILE_NAME, STAGING_DIR_NAME,
+};
+use config::{AggregateFunction, ColumnFilter, ConfigFormat, Diagnostic, FilterConfig, Transform};
+use expression::Type;
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::path::{Component, Path};
+
+/// Validates all [`FilterConfig`] items of a configuration file. Returns all errors and warnings
+/// that were found, each pointing at the offending element by its JSON path.
+///
+/// # Arguments
+/// * `configs` - The configuration items to validate
+/// * `format` - The format of the configuration file, which determines the JSON paths
+pub fn validate_configs(configs: &[FilterConfig], format: ConfigFormat) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    for (index, config) in configs.iter().enumerate() {
+        let mut validator = Validator {
+            config,
+            path: format.item_path(index),
+            diagnostics: &mut diagnostics,
+        };
+        validator.validate();
+
+        // Makes sure every output file is only written by one config, since they would
+        // otherwise share the same file writer
+        if let Some(first) = configs[..index]
+            .iter()
+            .position(|c| c.output == config.output)
+        {
+            diagnostics.push(Diagnostic::error(
+                format!("{}.output", format.item_path(index)),
+                format!(
+                    "Output file '{}' is already written by the config at {}",
+                    config.output,
+                    format.item_path(first)
+                ),
+            ));
+        }
+    }
+
+    diagnostics
+}
+
+/// Collects the diagnostics of one [`FilterConfig`].
+struct Validator<'a> {
+    config: &'a FilterConfig,
+    /// The JSON path of the config
+    path: String,
+    diagnostics: &'a mut Vec<Diagnostic>,
+}
+
+impl<'a> Validator<'a> {
+    fn error(&mut self, path: &str, message: String) {
+        let path = format!("{}{}", self.path, path);
+        self.diagnostics.push(Diagnostic::error(path, message));
+    }
+
+    fn warning(&mut self, path: &str, message: String) {
+        let path = format!("{}{}", self.path, path);
+        self.diagnostics.push(Diagnostic::warning(path, message));
+    }
+
+    fn validate(&mut self) {
+        let config = self.config;
+        let included_columns = config.output_column_names();
+
+        // Makes sure there is at least one column that will be included per output file
+        if included_columns.is_empty() {
+            self.error(
+                "",
+                format!(
+                    "Config for output file '{}' does not contain any output columns",
+                    &config.output
+                ),
+            );
+        }
+
+        self.validate_output_path();
+        self.validate_filters();
+
+        // Makes sure every lookup joins at least one column
+        for (index, lookup) in config.lookups.iter().enumerate() {
+            if lookup.columns.is_empty() {
+                self.error(
+                    &format!(".lookups[{}].columns", index),
+                    format!(
+                        "Config for output file '{}' defines a lookup of file '{}' without columns",
+                        &config.output, lookup.file
+                    ),
+                );
+            }
+        }
+
+        let computed_types = self.validate_computed_columns();
+
+        // Makes sure scripts are written in Rhai, the only supported scripting language
+        if let Some(script) = &config.script {
+            let extension = Path::new(script).extension().and_then(|e| e.to_str());
+            if extension.map(str::to_lowercase).as_deref() != Some("rhai") {
+                self.error(
+                    ".script",
+                    format!(
+                        "Config for output file '{}' references script '{}', but only Rhai scripts (.rhai) are supported",
+                        &config.output, script
+                    ),
+                );
+            }
+        }
+
+        // Makes sure all aggregations except "count" define the column they aggregate
+        if let Some(aggregate) = &config.aggregate {
+            for (index, aggregation) in aggregate.aggregations.iter().enumerate() {
+                if aggregation.column.is_none() && aggregation.function != AggregateFunction::Count
+                {
+                    self.error(
+                        &format!(".aggregate.aggregations[{}]", index),
+                        format!(
+                            "Config for output file '{}' defines a '{}' aggregation without a column",
+                            &config.output,
+                            aggregation.function.name()
+                        ),
+                    );
+                }
+            }
+        }
+
+        self.validate_output_columns(&computed_types);
+        self.validate_transforms(&included_columns);
+
+        // Makes sure all configs only use sort columns that do exist in the corresponding output file
+        for (index, column) in config.sort_columns.iter().flatten().enumerate() {
+            if !included_columns.contains(column) {
+                self.error(
+                    &format!(".sort_columns[{}]", index),
+                    format!(
+                        "Config for output file '{}' contains sort column '{}' which is not part of the output file",
+                        &config.output, column
+                    ),
+                );
+            }
+        }
+
+        // Makes sure descending columns are sort columns
+        for (index, column) in config.descending_columns.iter().enumerate() {
+            if !config.sort_columns.iter().flatten().any(|c| c == column) {
+                self.error(
+                    &format!(".descending_columns[{}]", index),
+                    format!(
+                        "Config for output file '{}' contains descending column '{}' which is not a sort column",
+                        &config.output, column
+                    ),
+                );
+            }
+        }
+
+        // Makes sure all configs only use distinct columns that do exist in the corresponding output file
+        if let Some(distinct_columns) = config.distinct.as_ref().and_then(|d| d.columns.as_ref()) {
+            if distinct_columns.is_empty() {
+                self.error(
+                    ".distinct.columns",
+                    format!(
+                        "Config for output file '{}' defines an empty list of distinct columns",
+                        &config.output
+                    ),
+                );
+            }
+
+            for (index, column) in distinct_columns.iter().enumerate() {
+                if !included_columns.contains(column) {
+                    self.error(
+                        &format!(".distinct.columns[{}]", index),
+                        format!(
+                            "Config for output file '{}' contains distinct column '{}' which is not part of the output file",
+                            &config.output, column
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Makes sure the output file stays inside the output directory. Only plain relative paths
+    /// are accepted, since absolute paths and parent directory components could point anywhere.
+    fn validate_output_path(&mut self) {
+        let output = &self.config.output;
+        let path = Path::new(output);
+        let message = if output.is_empty() {
+            Some(String::from("The output file name must not be empty"))
+        } else if path
+            .components()
+            .any(|c| matches!(c, Component::Prefix(_) | Component::RootDir))
+        {
+            Some(format!(
+                "Output file '{}' must be a relative path inside the output directory",
+                output
+            ))
+        } else if path.components().all(|c| c == Component::CurDir) {
+            Some(format!(
+                "Output file '{}' must name a file inside the output directory",
+                output
+            ))
+        } else if path.components().any(|c| c == Component::ParentDir) {
+            Some(format!(
+                "Output file '{}' must not refer to a parent directory",
+                output
+            ))
+        } else if path.components().next() == Some(Component::Normal(STAGING_DIR_NAME.as_ref()))
+            || path == Path::new(MANIFEST_FILE_NAME)
+            || path == Path::new(CHECKPOINT_FILE_NAME)
+            || path == Path::new(JOURNAL_FILE_NAME)
+        {
+            Some(format!("Output file name '{}' is reserved", output))
+        } else {
+            None
+        };
+
+        if let Some(message) = message {
+            self.error(".output", message);
+        }
+    }
+
+    fn validate_filters(&mut self) {
+        let config = self.config;
+        // The "include" attribute only has an effect if the output columns are not defined otherwise
+        let include_matters = config.output_columns.is_none() && config.aggregate.is_none();
+
+        for (index, cf) in config.filters.iter().enumerate() {
+            let path = format!(".filters[{}]", index);
+            let has_range = cf.min.is_some() || cf.max.is_some();
+
+            // Makes sure no config uses "min"/"max" values along with explicitly defined "values"
+            if cf.has_values() && has_range {
+                self.error(
+                    &path,
+                    format!(
+                        "Config for output file '{}' defines values and a range (min/max)",
+                        &config.output
+                    ),
+                );
+            }
+
+            if let (Some(min), Some(max)) = (&cf.min, &cf.max) {
+                if min > max {
+                    self.error(
+                        &path,
+                        format!(
+                            "Config for output file '{}' defines min '{}' greater than max '{}' for column '{}'",
+                            &config.output, min, max, cf.column
+                        ),
+                    );
+                }
+            }
+
+            if cf.values.as_ref().is_some_and(|v| v.is_empty()) {
+                self.warning(
+                    &format!("{}.values", path),
+                    format!(
+                        "Config for output file '{}' defines an empty list of values for column '{}', so no row can match",
+                        &config.output, cf.column
+                    ),
+                );
+            }
+
+            let has_effect = cf.has_values() || has_range || (include_matters && cf.include);
+            if !has_effect {
+                self.warning(
+                    &path,
+                    format!(
+                        "Config for output file '{}' defines a filter for column '{}' that has no effect",
+                        &config.output, cf.column
+                    ),
+                );
+            }
+
+            // Makes sure bloom filters are only used with values
+            if let Some(bloom_filter) = &cf.bloom_filter {
+                if !cf.has_values() {
+                    self.error(
+                        &format!("{}.bloom_filter", path),
+                        format!(
+                            "Config for output file '{}' defines a bloom filter for column '{}' without values",
+                            &config.output, cf.column
+                        ),
+                    );
+                }
+
+                let rate = bloom_filter.false_positive_rate;
+                if !(rate > 0.0 && rate < 1.0) {
+                    self.error(
+                        &format!("{}.bloom_filter.false_positive_rate", path),
+                        format!(
+                            "Config for output file '{}' defines a bloom filter false positive rate for column '{}' that is not between 0 and 1",
+                            &config.output, cf.column
+                        ),
+                    );
+                }
+            }
+
+            // Makes sure multiple filters of the same column can match at the same time
+            let previous: Vec<&ColumnFilter> = config.filters[..index]
+                .iter()
+                .filter(|f| f.column == cf.column)
+                .collect();
+            if !previous.is_empty() && !can_match(previous.into_iter().chain(Some(cf))) {
+                self.warning(
+                    &path,
+                    format!(
+                        "Config for output file '{}' defines filters for column '{}' that can never match at the same time",
+                        &config.output, cf.column
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Makes sure computed columns have unique names and only reference input columns or computed
+    /// columns declared before them, and that their expressions are well-typed. Returns the types
+    /// of all computed columns.
+    fn validate_computed_columns(&mut self) -> HashMap<&'a str, Type> {
+        let config = self.config;
+        let mut computed_types: HashMap<&str, Type> = HashMap::new();
+
+        for (index, computed_column) in config.computed_columns.iter().enumerate() {
+            let path = format!(".computed_columns[{}]", index);
+            let name = computed_column.name.as_str();
+            if computed_types.contains_key(name) {
+                self.error(
+                    &format!("{}.name", path),
+                    format!(
+                        "Config for output file '{}' defines computed column '{}' more than once",
+                        &config.output, name
+                    ),
+                );
+                continue;
+            }
+
+            let is_declared_later = |c: &str| {
+                config.computed_columns[index..]
+                    .iter()
+                    .any(|cc| cc.name == c)
+            };
+            if let Some(c) = computed_column
+                .expression
+                .columns()
+                .into_iter()
+                .find(|c| !computed_types.contains_key(c) && is_declared_later(c))
+            {
+                self.error(
+                    &format!("{}.expression", path),
+                    format!(
+                        "Config for output file '{}' contains computed column '{}' which references computed column '{}' before it is declared",
+                        &config.output, name, c
+                    ),
+                );
+            }
+
+            let column_type = computed_column
+                .expression
+                .type_check(&|c| computed_types.get(c).copied().unwrap_or(Type::Field))
+                .unwrap_or_else(|e| {
+                    self.error(
+                        &format!("{}.expression", path),
+                        format!(
+                            "Config for output file '{}' contains computed column '{}' with an invalid expression: {}",
+                            &config.output, name, e
+                        ),
+                    );
+                    Type::Field
+                });
+            computed_types.insert(name, column_type);
+        }
+
+        computed_types
+    }
+
+    /// Makes sure explicitly defined output columns have unique names and either copy a source
+    /// column or compute a well-typed expression
+    fn validate_output_columns(&mut self, computed_types: &HashMap<&str, Type>) {
+        let config = self.config;
+        let output_columns = match &config.output_columns {
+            Some(output_columns) => output_columns,
+            None => return,
+        };
+
+        if config.aggregate.is_some() {
+            self.error(
+                ".output_columns",
+                format!(
+                    "Config for output file '{}' defines output columns for an aggregated output file",
+                    &config.output
+                ),
+            );
+        }
+
+        for (index, output_column) in output_columns.iter().enumerate() {
+            let path = format!(".output_columns[{}]", index);
+            if output_columns[..index]
+                .iter()
+                .any(|c| c.name == output_column.name)
+            {
+                self.error(
+                    &format!("{}.name", path),
+                    format!(
+                        "Config for output file '{}' defines output column '{}' more than once",
+                        &config.output, output_column.name
+                    ),
+                );
+            }
+
+            if let Some(expression) = &output_column.expression {
+                if output_column.source.is_some() {
+                    self.error(
+                        &path,
+                        format!(
+                            "Config for output file '{}' defines a source and an expression for output column '{}'",
+                            &config.output, output_column.name
+                        ),
+                    );
+                }
+
+                if let Err(e) = expression
+                    .type_check(&|c| computed_types.get(c).copied().unwrap_or(Type::Field))
+                {
+                    self.error(
+                        &format!("{}.expression", path),
+                        format!(
+                            "Config for output file '{}' contains output column '{}' with an invalid expression: {}",
+                            &config.output, output_column.name, e
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Makes sure transforms only apply to output columns, at most once per column, and that hash
+    /// transforms define exactly one key source
+    fn validate_transforms(&mut self, included_columns: &[String]) {
+        let config = self.config;
+        if config.aggregate.is_some() && !config.transforms.is_empty() {
+            self.error(
+                ".transforms",
+                format!(
+                    "Config for output file '{}' defines transforms for an aggregated output file",
+                    &config.output
+                ),
+            );
+        }
+
+        for (index, column_transform) in config.transforms.iter().enumerate() {
+            let path = format!(".transforms[{}]", index);
+            if !included_columns.contains(&column_transform.column) {
+                self.error(
+                    &format!("{}.column", path),
+                    format!(
+                        "Config for output file '{}' contains transform for column '{}' which is not part of the output file",
+                        &config.output, column_transform.column
+                    ),
+                );
+            }
+
+            if config.transforms[..index]
+                .iter()
+                .any(|t| t.column == column_transform.column)
+            {
+                self.error(
+                    &format!("{}.column", path),
+                    format!(
+                        "Config for output file '{}' defines more than one transform for column '{}'",
+                        &config.output, column_transform.column
+                    ),
+                );
+            }
+
+            if let Transform::Hash { key_env, key_file } = &column_transform.transform {
+                if key_env.is_some() == key_file.is_some() {
+                    self.error(
+                        &format!("{}.transform", path),
+                        format!(
+                            "Config for output file '{}' must define either 'key_env' or 'key_file' for the hash transform of column '{}'",
+                            &config.output, column_transform.column
+                        ),
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Checks if there is a value that passes all given filters of the same column. Only inline
+/// values and ranges are considered, since value files are not loaded yet.
+fn can_match<'a, I: Iterator<Item = &'a ColumnFilter>>(filters: I) -> bool {
+    let mut values: Option<Vec<&String>> = None;
+    let mut min: Option<&String> = None;
+    let mut max: Option<&String> = None;
+
+    for filter in filters {
+        if let Some(filter_values) = &filter.values {
+            values = Some(match values {
+                Some(v) => v
+                    .into_iter()
+                    .filter(|v| filter_values.contains(*v))
+                    .collect(),
+                None => filter_values.iter().collect(),
+            });
+        }
+        if let Some(m) = &filter.min {
+            min = Some(min.map_or(m, |current| current.max(m)));
+        }
+        if let Some(m) = &filter.max {
+            max = Some(max.map_or(m, |current| current.min(m)));
+        }
+    }
+
+    let in_range = |v: &String| min.is_none_or(|m| v >= m) && max.is_none_or(|m| v <= m);
+    match values {
+        Some(v) => v.into_iter().any(in_range),
+        None => match (min, max) {
+            (Some(min), Some(max)) => min <= max,
+            _ => true,
+        },
+    }
+}
+
+/// Validates that all columns referenced by [`FilterConfig`] items are part of an input file (or
+/// are joined or computed columns).
+///
+/// # Arguments
+/// * `configs` - The configuration items to validate
+/// * `format` - The format of the configuration file, which determines the JSON paths
+/// * `input_headers` - The header row of the input file
+pub fn validate_headers<C: Borrow<FilterConfig>>(
+    configs: &[C],
+    format: ConfigFormat,
+    input_headers: &[String],
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    for (index, config) in configs.iter().enumerate() {
+        let mut validator = Validator {
+            config: config.borrow(),
+            path: format.item_path(index),
+            diagnostics: &mut diagnostics,
+        };
+        validator.validate_headers(input_headers);
+    }
+
+    diagnostics
+}
+
+/// Validates that the output files of all [`FilterConfig`] items stay inside the output
+/// directory once symbolic links inside it are resolved. Returns an error for every output file
+/// that escapes it.
+///
+/// # Arguments
+/// * `configs` - The configuration items to validate
+/// * `format` - The format of the configuration file, which determines the JSON paths
+/// * `output_dir_path` - Path to the directory containing all output files
+pub fn validate_output_dir<C: Borrow<FilterConfig>>(
+    configs: &[C],
+    format: ConfigFormat,
+    output_dir_path: &str,
+) -> Vec<Diagnostic> {
+    configs
+        .iter()
+        .enumerate()
+        .filter_map(|(index, config)| {
+            let path = Path::new(output_dir_path).join(&config.borrow().output);
+            output::resolve_confined(output_dir_path, &path)
+                .err()
+                .map(|message| {
+                    Diagnostic::error(format!("{}.output", format.item_path(index)), message)
+                })
+        })
+        .collect()
+}
+
+/// Validates that rows can be appended to the output files of all [`FilterConfig`] items, which
+/// are merged into existing sorted files. Returns an error for every configuration item that
+/// sorts by the original values of transformed columns, since only the transformed values are
+/// part of existing output files.
+///
+/// # Arguments
+/// * `configs` - The configuration items to validate
+/// * `format` - The format of the configuration file, which determines the JSON paths
+pub fn validate_append<C: Borrow<FilterConfig>>(
+    configs: &[C],
+    format: ConfigFormat,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    for (index, config) in configs.iter().enumerate() {
+        let config = config.borrow();
+        for column in config.original_sort_columns() {
+            diagnostics.push(Diagnostic::error(
+                format!("{}.sort_columns", format.item_path(index)),
+                format!(
+                    "Cannot append to output file '{}', because it is sorted by the original values of transformed column '{}'",
+                    config.output, column
+                ),
+            ));
+        }
+    }
+
+    diagnostics
+}
+
+impl Validator<'_> {
+    fn validate_headers(&mut self, input_headers: &[String]) {
+        let config = self.config;
+        let columns = filter::resolve_columns(input_headers, config);
+        let is_known = |column: &str| columns.iter().any(|c| c == column);
+        let missing = |column: &str| {
+            format!(
+                "Config for output file '{}' references column '{}' which is not part of the input file",
+                &config.output, column
+            )
+        };
+
+        // Makes sure joined and computed columns do not replace input columns
+        for (index, column) in columns.iter().enumerate().skip(input_headers.len()) {
+            if columns[..index].contains(column) {
+                self.error(
+                    "",
+                    format!(
+                        "Config for output file '{}' defines column '{}' which conflicts with another column",
+                        &config.output, column
+                    ),
+                );
+            }
+        }
+
+        // Filters of unknown columns are skipped while processing, unless their column is written out
+        let include_matters = config.output_columns.is_none() && config.aggregate.is_none();
+        for (index, cf) in config.filters.iter().enumerate() {
+            if is_known(&cf.column) {
+                continue;
+            }
+            let path = format!(".filters[{}].column", index);
+            if include_matters && cf.include {
+                self.error(&path, missing(&cf.column));
+            } else {
+                self.warning(
+                    &path,
+                    format!(
+                        "Config for output file '{}' defines a filter for column '{}' which is ignored, because the column is not part of the input file",
+                        &config.output, cf.column
+                    ),
+                );
+            }
+        }
+
+        let mut referenced: Vec<(String, &str)> = Vec::new();
+        for (index, lookup) in config.lookups.iter().enumerate() {
+            referenced.push((format!(".lookups[{}].key", index), &lookup.key));
+        }
+        for (index, computed_column) in config.computed_columns.iter().enumerate() {
+            for column in computed_column.expression.columns() {
+                referenced.push((format!(".computed_columns[{}].expression", index), column));
+            }
+        }
+        for (index, output_column) in config.output_columns.iter().flatten().enumerate() {
+            let path = format!(".output_columns[{}]", index);
+            match &output_column.expression {
+                Some(expression) => {
+                    for column in expression.columns() {
+                        referenced.push((format!("{}.expression", path), column));
+                    }
+                }
+                None => referenced.push((path, output_column.source_column())),
+            }
+        }
+        if let Some(aggregate) = &config.aggregate {
+            for (index, column) in aggregate.group_by.iter().enumerate() {
+                referenced.push((format!(".aggregate.group_by[{}]", index), column));
+            }
+            for (index, aggregation) in aggregate.aggregations.iter().enumerate() {
+                if let Some(column) = &aggregation.column {
+                    let path = format!(".aggregate.aggregations[{}].column", index);
+                    referenced.push((path, column));
+                }
+            }
+        }
+
+        for (path, column) in referenced {
+            if !is_known(column) {
+                self.error(&path, missing(column));
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use crate::report::RunReport;
+use crate::Options;
+use log::{error, info};
+use notify::event::{AccessKind, AccessMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The time without further changes after which a change is handled, since editors and
+/// appending processes usually change files in several steps.
+const DEBOUNCE_INTERVAL: Duration = Duration::from_millis(300);
+
+/// The time between two checks if a run has finished.
+const RUN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Processes files whenever the configuration file, the input file or a file referenced by the
+/// configuration changes. A run that is in progress when a change is detected is cancelled
+/// without changing any output files. After every run, the row counts of all output files are
+/// printed along with their difference to the previous run. Runs until the process is killed.
+///
+/// # Arguments
+/// * `csv_file_path` - Path to the CSV file that should be processed
+/// * `config_file_path` - Path to the configuration file
+/// * `output_dir_path` - Path to the directory that data should be written to
+/// * `options` - Settings that control how files are processed
+///
+/// # Panics
+/// This function will panic if a dry run is requested, since dry runs do not process any files.
+pub fn watch(
+    csv_file_path: &str,
+    config_file_path: &str,
+    output_dir_path: &str,
+    options: &Options,
+) -> ! {
+    if options.dry_run {
+        panic!("Watch mode cannot be combined with a dry run");
+    }
+
+    let mut previous_rows: Option<HashMap<String, usize>> = None;
+    loop {
+        let files = watched_files(csv_file_path, config_file_path, options);
+        let mut watcher = Watcher::new(&files);
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        let run = {
+            let (csv_file_path, config_file_path, output_dir_path) = (
+                csv_file_path.to_string(),
+                config_file_path.to_string(),
+                output_dir_path.to_string(),
+            );
+            let options = options.clone();
+            let cancel = cancel.clone();
+            thread::spawn(move || {
+                crate::run(
+                    &csv_file_path,
+                    &config_file_path,
+                    &output_dir_path,
+                    &options,
+                    Some(&cancel),
+                )
+            })
+        };
+
+        // Waits for the run to finish unless a file changes in the meantime
+        let mut changed = false;
+        while !run.is_finished() {
+            if wait_for_change(&mut watcher, RUN_POLL_INTERVAL) {
+                info!("Change detected, cancelling the current run");
+                cancel.store(true, Ordering::Relaxed);
+                changed = true;
+                break;
+            }
+        }
+
+        match run.join() {
+            Ok(Some(report)) if !changed => {
+                print!("{}", format_row_counts(&report, previous_rows.as_ref()));
+                previous_rows = Some(
+                    report
+                        .outputs
+                        .iter()
+                        .map(|o| (o.output.clone(), o.rows_written))
+                        .collect(),
+                );
+            }
+            Ok(_) => {}
+            Err(_) => error!("Run failed, waiting for changes"),
+        }
+
+        if !changed {
+            info!("Watching {} files for changes", files.len());
+            while !wait_for_change(&mut watcher, Duration::from_secs(3600)) {}
+        }
+    }
+}
+
+/// Returns all files whose changes trigger a new run: the configuration file, the input file and
+/// all lookup, value and script files referenced by the configuration.
+fn watched_files(csv_file_path: &str, config_file_path: &str, options: &Options) -> Vec<PathBuf> {
+    let mut files = vec![
+        PathBuf::from(config_file_path),
+        PathBuf::from(csv_file_path),
+    ];
+
+    // Invalid configurations are reported by the run, which is why they are ignored here
+    let format = crate::resolve_format(config_file_path, options.config_format);
+    let mut parsed = std::fs::read_to_string(config_file_path)
+        .ok()
+        .and_then(|source| config::deserialize_format(&source, format).ok());
+    let config_dir = Path::new(config_file_path)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    for config in parsed.iter_mut().flat_map(|p| &mut p.configs) {
+        config.resolve_paths(config_dir);
+        files.extend(config.lookups.iter().map(|l| PathBuf::from(&l.file)));
+        files.extend(config.script.iter().map(PathBuf::from));
+        for column_filter in &config.filters {
+            files.extend(column_filter.values_file.iter().map(PathBuf::from));
+            files.extend(
+                column_filter
+                    .values_from
+                    .iter()
+                    .map(|v| PathBuf::from(&v.file)),
+            );
+        }
+    }
+
+    files.sort();
+    files.dedup();
+    files
+}
+
+/// Formats the row counts of all output files of a run, along with their difference to the
+/// previous run.
+///
+/// # Arguments
+/// * `report` - The report of the run
+/// * `previous_rows` - Maps a filename to its row count in the previous run
+fn format_row_counts(report: &RunReport, previous_rows: Option<&HashMap<String, usize>>) -> String {
+    let width = report
+        .outputs
+        .iter()
+        .map(|o| o.output.chars().count())
+        .max()
+        .unwrap_or(0);
+
+    let mut text = String::new();
+    for output in &report.outputs {
+        let previous = previous_rows.and_then(|p| p.get(&output.output));
+        let difference = match previous {
+            None => String::from("new"),
+            Some(&previous) if previous == output.rows_written => String::from("unchanged"),
+            Some(&previous) => format!("{:+}", output.rows_written as i64 - previous as i64),
+        };
+        text.push_str(&format!(
+            "{:<width$}  {:>10} rows  ({})\n",
+            output.output,
+            output.rows_written,
+            difference,
+            width = width
+        ));
+    }
+    text
+}
+
+/// Waits until one of the watched files changes. Returns `false` if nothing changed within the
+/// timeout. Changes that follow within the debounce interval are handled along with the first.
+fn wait_for_change(watcher: &mut Watcher, timeout: Duration) -> bool {
+    if !watcher.changed(timeout) {
+        return false;
+    }
+    while watcher.changed(DEBOUNCE_INTERVAL) {}
+    true
+}
+
+/// Detects changes of files. The directories of all files are watched instead of the files
+/// themselves, since editors often replace files instead of writing to them.
+struct Watcher {
+    /// Watches the directories for as long as it is alive
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    files: Vec<PathBuf>,
+}
+
+impl Watcher {
+    /// Starts watching the directories of the given files. Files whose directory cannot be
+    /// watched are logged and ignored.
+    ///
+    /// # Panics
+    /// This function will panic if changes cannot be watched on this platform.
+    fn new(files: &[PathBuf]) -> Self {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)
+            .unwrap_or_else(|e| panic!("Cannot watch files: {}", e));
+
+        // Paths of events start with the watched directory, which is why it is resolved first
+        let files: Vec<PathBuf> = files.iter().map(|f| resolve_dir(f)).collect();
+        let mut dirs: Vec<&Path> = files.iter().filter_map(|f| f.parent()).collect();
+        dirs.sort();
+        dirs.dedup();
+        for dir in dirs {
+            if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+                error!("Cannot watch directory '{}': {}", dir.display(), e);
+            }
+        }
+
+        Watcher {
+            _watcher: watcher,
+            events,
+            files,
+        }
+    }
+
+    /// Waits for a change of a watched file. Returns `false` if nothing changed within the
+    /// timeout.
+    fn changed(&mut self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.events.recv_timeout(remaining) {
+                Ok(Ok(event)) if is_change(&event, &self.files) => return true,
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => error!("Cannot watch files: {}", e),
+                Err(_) => return false,
+            }
+        }
+    }
+}
+
+/// Resolves the directory of a file, so that the path matches the paths of its events.
+/// Relative paths without a directory refer to the working directory.
+fn resolve_dir(file: &Path) -> PathBuf {
+    let dir = match file.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    dir.join(file.file_name().unwrap_or_default())
+}
+
+/// Checks if an event changes one of the given files. Files being opened or read, e.g. by the
+/// run itself, do not count as changes.
+fn is_change(event: &Event, files: &[PathBuf]) -> bool {
+    let changes_content = match event.kind {
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => true,
+        EventKind::Access(_) => false,
+        _ => true,
+    };
+    changes_content && event.paths.iter().any(|path| files.contains(path))
+}
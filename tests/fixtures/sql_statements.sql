@@ -0,0 +1,10 @@
+-- Rows within a range of names, newest first
+SELECT id, amount AS total INTO 'range.csv'
+FROM input
+WHERE name BETWEEN 'b' AND 'd'
+ORDER BY id DESC;
+
+SELECT DISTINCT country, upper(name) AS label INTO germany
+FROM input
+WHERE country = 'DE'
+ORDER BY label;
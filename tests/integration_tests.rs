@@ -1,7 +1,20 @@
 extern crate csv_filter;
+extern crate csv_filter_config;
+extern crate csv_filter_filter;
 
+extern crate serde_json;
 extern crate tempfile;
 use crate::util::*;
+use csv_filter::{
+    ColumnType, ConfigFormat, IfExists, InitOptions, Options, Query, SqlQuery, StatsOptions,
+    ValueCount, MANIFEST_FILE_NAME,
+};
+use csv_filter_config::FilterConfig;
+use csv_filter_filter::{
+    input_fingerprint, ColumnFilterPredicate, FilterOptions, Predicate, RangePredicate, RecordSink,
+    ValuePredicate,
+};
+use std::sync::{Arc, Mutex};
 
 mod util;
 
@@ -22,14 +35,12 @@ fn writes_included_headers_to_output_files() {
     csv_filter::process(
         &path_to_string(&input_csv.path),
         &path_to_string(&config.path),
-        &path_to_string(&output_dir.path()),
-        false,
-        0,
-        0,
+        &path_to_string(output_dir.path()),
+        &Options::default(),
     );
 
     // Assert
-    assert_eq!(true, expected_output_file_path.exists());
+    assert!(expected_output_file_path.exists());
     assert_eq!(
         &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
         &std::fs::read_to_string(&expected_output_file_path).unwrap()
@@ -51,10 +62,8 @@ fn config_validation_fails_no_included_filters() {
     csv_filter::process(
         &path_to_string(&input_csv.path),
         &path_to_string(&config.path),
-        &path_to_string(&output_dir.path()),
-        false,
-        0,
-        0,
+        &path_to_string(output_dir.path()),
+        &Options::default(),
     );
 
     // Assert
@@ -75,10 +84,11 @@ fn config_validation_fails_no_filters() {
     csv_filter::process(
         &path_to_string(&input_csv.path),
         &path_to_string(&config.path),
-        &path_to_string(&output_dir.path()),
-        true,
-        0,
-        0,
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            ..Options::default()
+        },
     );
 
     // Assert
@@ -100,10 +110,11 @@ fn config_validation_fails_values_and_range_defined() {
     csv_filter::process(
         &path_to_string(&input_csv.path),
         &path_to_string(&config.path),
-        &path_to_string(&output_dir.path()),
-        true,
-        0,
-        0,
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            ..Options::default()
+        },
     );
 
     // Assert
@@ -123,10 +134,11 @@ fn panics_on_missing_input_file() {
     csv_filter::process(
         &path_to_string(&input_file_path),
         &path_to_string(&config.path),
-        &path_to_string(&output_dir.path()),
-        true,
-        0,
-        0,
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            ..Options::default()
+        },
     );
 
     // Assert
@@ -146,10 +158,11 @@ fn panics_on_missing_config_file() {
     csv_filter::process(
         &path_to_string(&input_csv.path),
         &path_to_string(&config_file_path),
-        &path_to_string(&output_dir.path()),
-        true,
-        0,
-        0,
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            ..Options::default()
+        },
     );
 
     // Assert
@@ -172,14 +185,15 @@ fn filters_min_max() {
     csv_filter::process(
         &path_to_string(&input_csv.path),
         &path_to_string(&config.path),
-        &path_to_string(&output_dir.path()),
-        true,
-        0,
-        0,
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            ..Options::default()
+        },
     );
 
     // Assert
-    assert_eq!(true, expected_output_file_path.exists());
+    assert!(expected_output_file_path.exists());
     assert_eq!(
         &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
         &std::fs::read_to_string(&expected_output_file_path).unwrap()
@@ -202,14 +216,15 @@ fn filters_values() {
     csv_filter::process(
         &path_to_string(&input_csv.path),
         &path_to_string(&config.path),
-        &path_to_string(&output_dir.path()),
-        true,
-        0,
-        0,
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            ..Options::default()
+        },
     );
 
     // Assert
-    assert_eq!(true, expected_output_file_path.exists());
+    assert!(expected_output_file_path.exists());
     assert_eq!(
         &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
         &std::fs::read_to_string(&expected_output_file_path).unwrap()
@@ -234,15 +249,16 @@ fn filters_multiple_configs_with_multiple_filters() {
     csv_filter::process(
         &path_to_string(&input_csv.path),
         &path_to_string(&config.path),
-        &path_to_string(&output_dir.path()),
-        true,
-        0,
-        0,
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            ..Options::default()
+        },
     );
 
     // Assert
-    assert_eq!(true, expected_output_file_path_1.exists());
-    assert_eq!(true, expected_output_file_path_2.exists());
+    assert!(expected_output_file_path_1.exists());
+    assert!(expected_output_file_path_2.exists());
     assert_eq!(
         &std::fs::read_to_string(&expected_output_csv_1.path).unwrap(),
         &std::fs::read_to_string(&expected_output_file_path_1).unwrap()
@@ -268,14 +284,12 @@ fn sorts_files() {
     csv_filter::process(
         &path_to_string(&input_csv.path),
         &path_to_string(&config.path),
-        &path_to_string(&output_dir.path()),
-        false,
-        0,
-        0,
+        &path_to_string(output_dir.path()),
+        &Options::default(),
     );
 
     // Assert
-    assert_eq!(true, expected_output_file_path.exists());
+    assert!(expected_output_file_path.exists());
     assert_eq!(
         &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
         &std::fs::read_to_string(&expected_output_file_path).unwrap()
@@ -285,7 +299,7 @@ fn sorts_files() {
 /// This test ensures that the program panics if a [`ColumnFilter`] definition
 /// includes sort columns which are not part of the corresponding output file.
 #[test]
-#[should_panic(expected = "contains sort column \\'col3\\' which is not part of the output file")]
+#[should_panic(expected = "contains sort column 'col3' which is not part of the output file")]
 fn config_validation_fails_not_all_sort_columns_included() {
     // Arrange
     let config = Fixture::copy("invalid_not_included_sort_column.json");
@@ -296,12 +310,2515 @@ fn config_validation_fails_not_all_sort_columns_included() {
     csv_filter::process(
         &path_to_string(&input_csv.path),
         &path_to_string(&config.path),
-        &path_to_string(&output_dir.path()),
-        false,
-        0,
-        0,
+        &path_to_string(output_dir.path()),
+        &Options::default(),
     );
 
     // Assert
     // See macro 'should_panic'
 }
+
+/// This test ensures that reading the input file through a memory-mapped region produces
+/// exactly the same output as the default buffered reading.
+#[test]
+fn filters_memory_mapped_input() {
+    // Arrange
+    let config = Fixture::copy("multiple.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let expected_output_csv_1 = Fixture::copy("multiple_output_1.csv");
+    let expected_output_csv_2 = Fixture::copy("multiple_output_2.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let expected_output_file_path_1 = output_dir.path().join("f1.csv");
+    let expected_output_file_path_2 = output_dir.path().join("f2.csv");
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            use_mmap: true,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv_1.path).unwrap(),
+        &std::fs::read_to_string(&expected_output_file_path_1).unwrap()
+    );
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv_2.path).unwrap(),
+        &std::fs::read_to_string(&expected_output_file_path_2).unwrap()
+    );
+}
+
+/// This test ensures that duplicate rows are removed from an output file if the corresponding
+/// [`FilterConfig`] defines a `distinct` attribute without key columns (i.e. whole rows are
+/// compared). The first occurrence of each row is kept.
+#[test]
+fn removes_duplicate_rows() {
+    // Arrange
+    let config = Fixture::copy("distinct_row.json");
+    let input_csv = Fixture::copy("distinct_input.csv");
+    let expected_output_csv = Fixture::copy("distinct_row_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let expected_output_file_path = output_dir.path().join("f1.csv");
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        &std::fs::read_to_string(&expected_output_file_path).unwrap()
+    );
+}
+
+/// This test ensures that only the last row per deduplication key is kept and the original row
+/// order is preserved, if duplicates are removed by spilling rows to temporary bucket files.
+#[test]
+fn removes_duplicate_keys_keeping_last_with_spill_to_disk() {
+    // Arrange
+    let config = Fixture::copy("distinct_keep_last_spill.json");
+    let input_csv = Fixture::copy("distinct_input.csv");
+    let expected_output_csv = Fixture::copy("distinct_keep_last_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let expected_output_file_path = output_dir.path().join("f1.csv");
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        &std::fs::read_to_string(&expected_output_file_path).unwrap()
+    );
+    let file_names: Vec<String> = std::fs::read_dir(output_dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(2, file_names.len());
+    assert!(file_names.contains(&String::from(MANIFEST_FILE_NAME)));
+}
+
+/// This test ensures that, with a single filter thread, the row kept per deduplication key is
+/// the last one of the input file, whether rows are deduplicated in memory or spilled to disk.
+#[test]
+fn removes_duplicate_keys_keeping_last_input_row() {
+    // Arrange
+    let config = Fixture::copy("distinct_keep_last.json");
+    let input_csv = Fixture::copy("distinct_input.csv");
+    let expected_output_csv = Fixture::copy("distinct_keep_last_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            filter_parallelism: 1,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    let expected_output = std::fs::read_to_string(&expected_output_csv.path).unwrap();
+    for output in &["f1.csv", "f2.csv"] {
+        assert_eq!(
+            expected_output,
+            std::fs::read_to_string(output_dir.path().join(output)).unwrap()
+        );
+    }
+}
+
+/// This test ensures that duplicates are removed from output files which are sorted.
+#[test]
+fn removes_duplicate_keys_while_sorting() {
+    // Arrange
+    let config = Fixture::copy("distinct_sort.json");
+    let input_csv = Fixture::copy("distinct_input.csv");
+    let expected_output_csv = Fixture::copy("distinct_sort_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let expected_output_file_path = output_dir.path().join("f1.csv");
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Assert
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        &std::fs::read_to_string(&expected_output_file_path).unwrap()
+    );
+}
+
+/// This test ensures that sorted output files are deduplicated in sort order, so that `keep`
+/// refers to the order of the rows in the sorted output file rather than the input order.
+#[test]
+fn removes_duplicate_keys_in_sort_order() {
+    // Arrange
+    let config = Fixture::copy("distinct_sort_descending.json");
+    let input_csv = Fixture::copy("distinct_input.csv");
+    let expected_output_csv = Fixture::copy("distinct_sort_descending_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let expected_output_file_path = output_dir.path().join("f1.csv");
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Assert
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        &std::fs::read_to_string(&expected_output_file_path).unwrap()
+    );
+}
+
+/// This test ensures that the program panics if a [`FilterConfig`] defines distinct columns
+/// which are not part of the corresponding output file.
+#[test]
+#[should_panic(expected = "contains distinct column 'col2' which is not part of the output file")]
+fn config_validation_fails_not_all_distinct_columns_included() {
+    // Arrange
+    let config = Fixture::copy("invalid_not_included_distinct_column.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Assert
+    // See macro 'should_panic'
+}
+
+/// This test ensures that a [`FilterConfig`] with an `aggregate` attribute writes out one row per
+/// group containing the aggregated values of all matching rows, even if multiple threads
+/// compute partial aggregates concurrently.
+#[test]
+fn writes_aggregated_output_file() {
+    // Arrange
+    let config = Fixture::copy("aggregate.json");
+    let input_csv = Fixture::copy("aggregate_input.csv");
+    let expected_output_csv = Fixture::copy("aggregate_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let expected_output_file_path = output_dir.path().join("f1.csv");
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            filter_parallelism: 4,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        &std::fs::read_to_string(&expected_output_file_path).unwrap()
+    );
+}
+
+/// This test ensures that sums of integers are exact integers and that values which are not
+/// numbers are skipped instead of failing the run.
+#[test]
+fn sums_integers_exactly_and_skips_non_numeric_values() {
+    // Arrange
+    let config = Fixture::copy("aggregate_sum.json");
+    let input_csv = Fixture::copy("aggregate_sum_input.csv");
+    let expected_output_csv = Fixture::copy("aggregate_sum_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let expected_output_file_path = output_dir.path().join("f1.csv");
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            filter_parallelism: 2,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        &std::fs::read_to_string(&expected_output_file_path).unwrap()
+    );
+}
+
+/// This test ensures that the program panics if an aggregation other than `count` does not
+/// define the column it aggregates.
+#[test]
+#[should_panic(expected = "defines a 'sum' aggregation without a column")]
+fn config_validation_fails_aggregation_without_column() {
+    // Arrange
+    let config = Fixture::copy("invalid_aggregation_without_column.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    // See macro 'should_panic'
+}
+
+/// This test ensures that computed columns are evaluated for every row and can be used like
+/// input columns in [`ColumnFilter`] definitions and `sort_columns`.
+#[test]
+fn writes_computed_columns() {
+    // Arrange
+    let config = Fixture::copy("computed.json");
+    let input_csv = Fixture::copy("computed_input.csv");
+    let expected_output_csv = Fixture::copy("computed_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let expected_output_file_path = output_dir.path().join("f1.csv");
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Assert
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        &std::fs::read_to_string(&expected_output_file_path).unwrap()
+    );
+}
+
+/// This test ensures that computed columns are evaluated correctly if the input file repeats a
+/// column name, i.e. has more columns than distinct column names.
+#[test]
+fn writes_computed_columns_with_duplicate_input_headers() {
+    // Arrange
+    let config = Fixture::copy("computed.json");
+    let input_csv = Fixture::copy("computed_duplicate_headers_input.csv");
+    let expected_output_csv = Fixture::copy("computed_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let expected_output_file_path = output_dir.path().join("f1.csv");
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Assert
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        &std::fs::read_to_string(&expected_output_file_path).unwrap()
+    );
+}
+
+/// This test ensures that a computed column is left empty for rows with empty or non-numeric
+/// values or unparseable dates, so that the rows are rejected by filters of the column, and
+/// that a warning with the line number is logged, both when filtering and in a dry run.
+#[test]
+fn leaves_computed_columns_empty_for_invalid_values() {
+    // Arrange
+    let config = Fixture::copy("computed.json");
+    let input_csv = Fixture::copy("computed_invalid_values_input.csv");
+    let expected_output_csv = Fixture::copy("computed_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let expected_output_file_path = output_dir.path().join("f1.csv");
+
+    // Act
+    let output = run_cli(&[
+        "run",
+        "--configuration",
+        config.to_str().unwrap(),
+        "--input",
+        input_csv.to_str().unwrap(),
+        "--output",
+        output_dir.path().to_str().unwrap(),
+    ]);
+    let plan = csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            dry_run: true,
+            if_exists: IfExists::Overwrite,
+            ..Options::default()
+        },
+    )
+    .expect("A dry run should return a plan");
+
+    // Assert
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Cannot compute value of column 'net' in line 5"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("Cannot compute value of column 'day' in line 6"),
+        "{}",
+        stderr
+    );
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        &std::fs::read_to_string(&expected_output_file_path).unwrap()
+    );
+    assert!(!plan.has_errors(), "{}", plan);
+    assert_eq!(plan.outputs[0].matching_sampled_rows, 2);
+}
+
+/// This test ensures that the program panics if the expression of a computed column applies
+/// a function to an operand of the wrong type.
+#[test]
+#[should_panic(expected = "contains computed column 'flag' with an invalid expression")]
+fn config_validation_fails_computed_column_type_error() {
+    // Arrange
+    let config = Fixture::copy("invalid_computed_column_type.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    // See macro 'should_panic'
+}
+
+/// This test ensures that `output_columns` define name and order of the output columns, that the
+/// same input column can be written more than once and that sort columns refer to output names.
+#[test]
+fn writes_renamed_and_reordered_output_columns() {
+    // Arrange
+    let config = Fixture::copy("output_columns.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let expected_output_csv = Fixture::copy("output_columns_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let expected_output_file_path = output_dir.path().join("f1.csv");
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Assert
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        &std::fs::read_to_string(&expected_output_file_path).unwrap()
+    );
+}
+
+/// This test ensures that an output column is left empty for rows whose expression cannot be
+/// evaluated, e.g. because of arithmetic on empty or non-numeric values, and that a warning with
+/// the line number is logged.
+#[test]
+fn leaves_output_columns_empty_for_invalid_values() {
+    // Arrange
+    let config = Fixture::copy("output_columns_invalid_values.json");
+    let input_csv = Fixture::copy("query_invalid_amount_input.csv");
+    let expected_output_csv = Fixture::copy("output_columns_invalid_values_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let expected_output_file_path = output_dir.path().join("f1.csv");
+
+    // Act
+    let output = run_cli(&[
+        "run",
+        "--configuration",
+        config.to_str().unwrap(),
+        "--input",
+        input_csv.to_str().unwrap(),
+        "--output",
+        output_dir.path().to_str().unwrap(),
+    ]);
+
+    // Assert
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Cannot compute value of output column 'doubled' in line 7"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("Cannot compute value of output column 'doubled' in line 8"),
+        "{}",
+        stderr
+    );
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        &std::fs::read_to_string(&expected_output_file_path).unwrap()
+    );
+}
+
+/// This test ensures that the program panics if two output columns have the same name.
+#[test]
+#[should_panic(expected = "defines output column 'first' more than once")]
+fn config_validation_fails_duplicate_output_column() {
+    // Arrange
+    let config = Fixture::copy("invalid_duplicate_output_column.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    // See macro 'should_panic'
+}
+
+/// This test ensures that transforms scrub output column values, while sort columns that are
+/// sorted by original value are still ordered by the values from the input file.
+#[test]
+fn transforms_output_columns() {
+    // Arrange
+    std::env::set_var("CSV_FILTER_TEST_HASH_KEY", "secret");
+    let config = Fixture::copy("pii.json");
+    let input_csv = Fixture::copy("pii_input.csv");
+    let expected_output_csv = Fixture::copy("pii_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let expected_output_file_path = output_dir.path().join("f1.csv");
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Assert
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        &std::fs::read_to_string(&expected_output_file_path).unwrap()
+    );
+}
+
+/// This test ensures that the program panics if rows would be appended to an output file that
+/// is sorted by the original values of a transformed column, since the original values of the
+/// existing rows are unknown.
+#[test]
+#[should_panic(expected = "sorted by the original values of transformed column 'email'")]
+fn append_fails_for_output_sorted_by_original_values() {
+    // Arrange
+    std::env::set_var("CSV_FILTER_TEST_HASH_KEY", "secret");
+    let config = Fixture::copy("pii.json");
+    let input_csv = Fixture::copy("pii_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            if_exists: IfExists::Append,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    // See macro 'should_panic'
+}
+
+/// This test ensures that tokens are persisted to the lookup file and reused by later runs.
+#[test]
+fn tokenizes_output_columns_with_persisted_lookup_file() {
+    // Arrange
+    let config = Fixture::copy("tokenize.json");
+    let lookup_file_path = config.path.with_file_name("tokens.csv");
+    let input_csv = Fixture::copy("pii_input.csv");
+    let expected_output_csv = Fixture::copy("pii_tokenize_output.csv");
+    let output_dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+
+    // Act
+    for output_dir in &output_dirs {
+        csv_filter::process(
+            &path_to_string(&input_csv.path),
+            &path_to_string(&config.path),
+            &path_to_string(output_dir.path()),
+            &Options {
+                no_sort: true,
+                ..Options::default()
+            },
+        );
+    }
+
+    // Assert
+    for output_dir in &output_dirs {
+        assert_eq!(
+            &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+            &std::fs::read_to_string(output_dir.path().join("f1.csv")).unwrap()
+        );
+    }
+    assert_eq!(
+        "value,token\ncarol@example.com,token_1\nalice@example.com,token_2\nbob@example.com,token_3\n",
+        &std::fs::read_to_string(&lookup_file_path).unwrap()
+    );
+}
+
+/// This test ensures that the program panics with a proper message if a token lookup file lacks
+/// the column of the tokens.
+#[test]
+#[should_panic(expected = "expected the columns 'value' and 'token'")]
+fn tokenize_fails_for_lookup_file_with_one_column() {
+    // Arrange
+    let config = Fixture::copy("tokenize.json");
+    std::fs::write(
+        config.path.with_file_name("tokens.csv"),
+        "value\nalice@example.com\n",
+    )
+    .unwrap();
+    let input_csv = Fixture::copy("pii_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Assert
+    // See macro 'should_panic'
+}
+
+/// This test ensures that filter values can be loaded from a text file and from a column of a
+/// CSV file.
+#[test]
+fn filters_values_loaded_from_files() {
+    // Arrange
+    let config = Fixture::copy_with("values_file.json", &["values_file.txt", "values_from.csv"]);
+    let input_csv = Fixture::copy("values_input.csv");
+    let expected_output_csv = Fixture::copy("values_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        &std::fs::read_to_string(output_dir.path().join("f1.csv")).unwrap()
+    );
+}
+
+/// This test ensures that the `filter(row)` function of a script rejects rows and that the row
+/// returned by its `map(row)` function is written.
+#[test]
+fn filters_and_maps_rows_with_script() {
+    // Arrange
+    let config = Fixture::copy_with("script.json", &["script.rhai"]);
+    let input_csv = Fixture::copy("script_input.csv");
+    let expected_output_csv = Fixture::copy("script_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            filter_parallelism: 2,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    let mut output = std::fs::read_to_string(output_dir.path().join("f1.csv")).unwrap();
+    let expected = std::fs::read_to_string(&expected_output_csv.path).unwrap();
+    // Rows may be written in any order by multiple threads
+    let mut lines: Vec<&str> = output.lines().skip(1).collect();
+    lines.sort_unstable();
+    output = format!("id,name\n{}\n", lines.join("\n"));
+    assert_eq!(expected, output);
+}
+
+/// This test ensures that scripts are sandboxed and cannot import modules from files.
+#[test]
+#[should_panic(expected = "Cannot run 'filter' of script file")]
+fn script_cannot_import_modules() {
+    // Arrange
+    let config = Fixture::copy_with("script_sandbox.json", &["script_sandbox.rhai"]);
+    let input_csv = Fixture::copy("script_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Assert
+    // See macro 'should_panic'
+}
+
+/// This test ensures that scripts cannot allocate without bound, e.g. by doubling a string.
+#[test]
+#[should_panic(expected = "Length of string too large")]
+fn script_cannot_allocate_unbounded_strings() {
+    // Arrange
+    let config = Fixture::copy_with("script_unbounded.json", &["script_unbounded.rhai"]);
+    let input_csv = Fixture::copy("script_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Assert
+    // See macro 'should_panic'
+}
+
+/// This test ensures that filters with bloom filters filter rows exactly like value sets.
+#[test]
+fn filters_values_using_bloom_filters() {
+    // Arrange
+    let config = Fixture::copy_with(
+        "values_file_bloom.json",
+        &["values_file.txt", "values_from.csv"],
+    );
+    let input_csv = Fixture::copy("values_input.csv");
+    let expected_output_csv = Fixture::copy("values_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        &std::fs::read_to_string(output_dir.path().join("f1.csv")).unwrap()
+    );
+}
+
+/// This test ensures that values letting a bloom filter pass although they are not listed (false
+/// positives) are never written, unless the configuration explicitly allows false positives.
+#[test]
+fn bloom_filter_false_positives_are_not_written() {
+    // Arrange
+    let config = Fixture::copy_with("bloom_false_positives.json", &["bloom_values.txt"]);
+    let input_csv = Fixture::copy("bloom_input.csv");
+    let expected_output_csv = Fixture::copy("bloom_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    let expected_output = std::fs::read_to_string(&expected_output_csv.path).unwrap();
+    let exact_output = std::fs::read_to_string(output_dir.path().join("f1.csv")).unwrap();
+    let lossy_output = std::fs::read_to_string(output_dir.path().join("f2.csv")).unwrap();
+    assert_eq!(expected_output, exact_output);
+    // The high false positive rate makes the bloom filter let unlisted values pass
+    assert!(lossy_output.lines().count() > exact_output.lines().count());
+}
+
+/// This test ensures that columns joined from a lookup file can be filtered, written out and
+/// sorted, and that rows without a match are kept by left joins and dropped by inner joins.
+#[test]
+fn joins_columns_from_lookup_file() {
+    // Arrange
+    let config = Fixture::copy_with("lookup.json", &["lookup_countries.csv"]);
+    let input_csv = Fixture::copy("lookup_input.csv");
+    let expected_left_output_csv = Fixture::copy("lookup_left_output.csv");
+    let expected_inner_output_csv = Fixture::copy("lookup_inner_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Assert
+    assert_eq!(
+        &std::fs::read_to_string(&expected_left_output_csv.path).unwrap(),
+        &std::fs::read_to_string(output_dir.path().join("f1.csv")).unwrap()
+    );
+    assert_eq!(
+        &std::fs::read_to_string(&expected_inner_output_csv.path).unwrap(),
+        &std::fs::read_to_string(output_dir.path().join("f2.csv")).unwrap()
+    );
+}
+
+/// This test ensures that YAML, TOML and JSON5 (including JSON with comments) configuration files
+/// are detected by their extension and produce the same output as the JSON configuration.
+#[test]
+fn reads_yaml_toml_and_json5_configs() {
+    for config_file in &["default.yaml", "default.toml", "default.jsonc"] {
+        // Arrange
+        let config = Fixture::copy(config_file);
+        let input_csv = Fixture::copy("default_input.csv");
+        let expected_output_csv = Fixture::copy("default_output.csv");
+        let output_dir = tempfile::tempdir().unwrap();
+
+        // Act
+        csv_filter::process(
+            &path_to_string(&input_csv.path),
+            &path_to_string(&config.path),
+            &path_to_string(output_dir.path()),
+            &Options::default(),
+        );
+
+        // Assert
+        assert_eq!(
+            &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+            &std::fs::read_to_string(output_dir.path().join("f1.csv")).unwrap(),
+            "Unexpected output for config file '{}'",
+            config_file
+        );
+    }
+}
+
+/// This test ensures that the format given in the options takes precedence over the extension.
+#[test]
+fn reads_config_in_explicitly_defined_format() {
+    // Arrange
+    let config = Fixture::blank("config.txt");
+    std::fs::copy(
+        Fixture::copy("default.yaml").path.as_path(),
+        config.path.as_path(),
+    )
+    .unwrap();
+    let input_csv = Fixture::copy("default_input.csv");
+    let expected_output_csv = Fixture::copy("default_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            config_format: Some(csv_filter::ConfigFormat::Yaml),
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        &std::fs::read_to_string(output_dir.path().join("f1.csv")).unwrap()
+    );
+}
+
+/// This test ensures that deserialization errors of YAML configuration files point at the
+/// offending line and column.
+#[test]
+#[should_panic(expected = "error at $[0].filters[1].include (line 5, column 16): invalid type")]
+fn config_deserialization_fails_with_yaml_location() {
+    // Arrange
+    let config = Fixture::copy("invalid_type.yaml");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Assert
+    // See macro 'should_panic'
+}
+
+/// This test ensures that deserialization errors of TOML configuration files point at the
+/// offending line and column.
+#[test]
+#[should_panic(
+    expected = "error at $.config[0].filters[0].include (line 6, column 11): invalid type"
+)]
+fn config_deserialization_fails_with_toml_location() {
+    // Arrange
+    let config = Fixture::copy("invalid_type.toml");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Assert
+    // See macro 'should_panic'
+}
+
+/// Runs the processor with a configuration file and returns the panic message.
+fn process_panic_message(config_file: &str) -> String {
+    let config = Fixture::copy(config_file);
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    let error = std::panic::catch_unwind(|| {
+        csv_filter::process(
+            &path_to_string(&input_csv.path),
+            &path_to_string(&config.path),
+            &path_to_string(output_dir.path()),
+            &Options::default(),
+        )
+    })
+    .expect_err("Processing should fail");
+
+    match error.downcast::<String>() {
+        Ok(message) => *message,
+        Err(_) => String::new(),
+    }
+}
+
+/// This test ensures that validation reports all problems of all configuration items at once,
+/// each with the JSON path of the offending element.
+#[test]
+fn config_validation_reports_all_errors() {
+    // Act
+    let message = process_panic_message("invalid_multiple_errors.json");
+
+    // Assert
+    assert!(
+        message.contains("Invalid configuration file"),
+        "{}",
+        message
+    );
+    assert!(!message.contains("sort_colums"));
+    assert!(message.contains("error at $[0].filters[0]: Config for output file 'f1.csv' defines min 'val7' greater than max 'val1'"));
+    assert!(message.contains("error at $[0].sort_columns[0]: Config for output file 'f1.csv' contains sort column 'col2'"));
+    assert!(message.contains(
+        "error at $[1].output: Output file 'f1.csv' is already written by the config at $[0]"
+    ));
+}
+
+/// This test ensures that unknown keys are reported as warnings along with the error if
+/// deserialization fails.
+#[test]
+fn config_deserialization_reports_unknown_keys() {
+    // Act
+    let message = process_panic_message("invalid_unknown_key.json");
+
+    // Assert
+    assert!(
+        message.contains("warning at $[0].filters[0].inlcude: Unknown key"),
+        "{}",
+        message
+    );
+    assert!(
+        message.contains("error at $[0].filters[0] (line 7, column 7): missing field `include`")
+    );
+}
+
+/// This test ensures that the configuration is validated against the headers of an input file
+/// and that columns missing from the input file are reported.
+#[test]
+fn validation_reports_columns_missing_from_input_file() {
+    // Arrange
+    let config = Fixture::copy("invalid_missing_column.json");
+    let input_csv = Fixture::copy("default_input.csv");
+
+    // Act
+    let without_input = csv_filter::validate(&path_to_string(&config.path), None, None);
+    let with_input = csv_filter::validate(
+        &path_to_string(&config.path),
+        Some(&path_to_string(&input_csv.path)),
+        None,
+    );
+
+    // Assert
+    assert!(without_input.is_empty(), "{:?}", without_input);
+    assert_eq!(with_input.len(), 1, "{:?}", with_input);
+    assert!(with_input[0].is_error());
+    assert!(
+        with_input[0]
+            .to_string()
+            .starts_with("error at $[0].filters[1]"),
+        "{}",
+        with_input[0]
+    );
+    assert!(with_input[0].to_string().contains("'col4'"));
+}
+
+/// This test ensures that a valid configuration does not yield any errors. Unknown keys are only
+/// reported as warnings.
+#[test]
+fn validation_accepts_valid_configuration() {
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let input_csv = Fixture::copy("default_input.csv");
+
+    // Act
+    let diagnostics = csv_filter::validate(
+        &path_to_string(&config.path),
+        Some(&path_to_string(&input_csv.path)),
+        None,
+    );
+
+    // Assert
+    assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+    assert_eq!(
+        "warning at $[0].uuid: Unknown key",
+        diagnostics[0].to_string()
+    );
+}
+
+/// This test ensures that a dry run neither creates the output directory nor any output files.
+#[test]
+fn dry_run_creates_no_files() {
+    // Arrange
+    let config = Fixture::copy("multiple.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let temp_dir = tempfile::tempdir().unwrap();
+    let output_dir = temp_dir.path().join("output");
+
+    // Act
+    let plan = csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(&output_dir),
+        &Options {
+            dry_run: true,
+            ..Options::default()
+        },
+    )
+    .expect("A dry run should return a plan");
+
+    // Assert
+    assert!(!output_dir.exists());
+    assert!(!plan.has_errors(), "{}", plan);
+    assert_eq!(plan.input.unwrap().columns, 3);
+    assert_eq!(plan.outputs.len(), 2);
+    assert!(plan.outputs[0].path.ends_with("f1.csv"));
+    assert!(plan.outputs[1].path.ends_with("f2.csv"));
+}
+
+/// This test ensures that a dry run describes outputs that already exist, even if existing
+/// output files are an error, and reports an input file that cannot be read instead of panicking.
+#[test]
+fn dry_run_describes_existing_outputs_and_missing_input() {
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    std::fs::write(output_dir.path().join("f1.csv"), "col1,col2\n").unwrap();
+    let options = Options {
+        dry_run: true,
+        if_exists: IfExists::Error,
+        ..Options::default()
+    };
+
+    // Act
+    let plan = csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &options,
+    )
+    .unwrap();
+    let missing_input_plan = csv_filter::process(
+        &path_to_string(&output_dir.path().join("missing.csv")),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &options,
+    )
+    .unwrap();
+
+    // Assert
+    assert!(!plan.has_errors(), "{}", plan);
+    assert_eq!(plan.outputs.len(), 1);
+    assert!(missing_input_plan.has_errors());
+    assert!(missing_input_plan.input.is_none());
+    assert!(missing_input_plan.diagnostics[0]
+        .to_string()
+        .contains("Cannot read headers of input file"));
+}
+
+/// This test ensures that input files are profiled correctly, independent of the number of
+/// threads used.
+#[test]
+fn collects_input_file_stats() {
+    // Arrange
+    let input_csv = Fixture::copy("stats_input.csv");
+
+    for parallelism in 1..=3 {
+        // Act
+        let stats = csv_filter::stats(
+            &path_to_string(&input_csv.path),
+            &StatsOptions {
+                top_values: 2,
+                parallelism,
+                use_mmap: false,
+            },
+        );
+
+        // Assert
+        assert_eq!(stats.rows, 4);
+        let types: Vec<ColumnType> = stats.columns.iter().map(|c| c.inferred_type).collect();
+        assert_eq!(
+            types,
+            vec![
+                ColumnType::Integer,
+                ColumnType::String,
+                ColumnType::Float,
+                ColumnType::Boolean,
+                ColumnType::Date,
+                ColumnType::String,
+            ]
+        );
+
+        let id = &stats.columns[0];
+        assert_eq!(id.min.as_deref(), Some("1"));
+        assert_eq!(id.max.as_deref(), Some("10"));
+        assert_eq!(id.distinct, 4);
+
+        let name = &stats.columns[1];
+        assert_eq!(name.min.as_deref(), Some("alice"));
+        assert_eq!(name.max.as_deref(), Some("carol"));
+        assert_eq!(name.distinct, 3);
+        assert_eq!(
+            name.top_values,
+            vec![
+                ValueCount {
+                    value: String::from("alice"),
+                    count: 2
+                },
+                ValueCount {
+                    value: String::from("bob"),
+                    count: 1
+                },
+            ]
+        );
+
+        let amount = &stats.columns[2];
+        assert_eq!(amount.min.as_deref(), Some("-4"));
+        assert_eq!(amount.max.as_deref(), Some("100"));
+
+        let note = &stats.columns[5];
+        assert_eq!(note.empty, 2);
+        assert_eq!(note.null, 1);
+        assert_eq!(note.distinct, 1);
+    }
+}
+
+/// This test ensures that generated starter configurations can be read in all formats, include
+/// every column of the input file and list the values of low-cardinality columns.
+#[test]
+fn generates_starter_configuration() {
+    // Arrange
+    let input_csv = Fixture::copy("stats_input.csv");
+    let temp_dir = tempfile::tempdir().unwrap();
+
+    for (format, extension) in &[
+        (ConfigFormat::Json, "json"),
+        (ConfigFormat::Yaml, "yaml"),
+        (ConfigFormat::Toml, "toml"),
+    ] {
+        let config_path = temp_dir.path().join(format!("config.{}", extension));
+
+        // Act
+        let config = csv_filter::init(
+            &path_to_string(&input_csv.path),
+            &InitOptions {
+                max_values: 2,
+                format: *format,
+                ..InitOptions::default()
+            },
+        );
+        std::fs::write(&config_path, &config).unwrap();
+        let diagnostics = csv_filter::validate(
+            &path_to_string(&config_path),
+            Some(&path_to_string(&input_csv.path)),
+            None,
+        );
+
+        // Assert
+        assert!(diagnostics.is_empty(), "{:?}\n{}", diagnostics, config);
+        for column in &["id", "name", "amount", "active", "created", "note"] {
+            assert!(config.contains(column), "{}", config);
+        }
+        assert!(config.contains("output.csv"));
+        assert!(config.contains("false") && config.contains("true"));
+        assert!(!config.contains("alice"), "{}", config);
+    }
+}
+
+/// This test ensures that the run report contains the record counts of the whole run and of
+/// every output file.
+#[test]
+fn writes_run_report() {
+    // Arrange
+    let config = Fixture::copy("report.json");
+    let input_csv = Fixture::copy("distinct_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let report_path = output_dir.path().join("report.json");
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            filter_parallelism: 2,
+            report: Some(path_to_string(&report_path)),
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&report_path).unwrap()).unwrap();
+    assert_eq!(report["rows_read"], 6);
+    assert_eq!(report["rows_rejected"], 1);
+    assert_eq!(report["filter_threads"], 2);
+    assert_eq!(report["sort_threads"], 1);
+    assert!(report["timings"]["filter_ms"].is_u64());
+    assert!(report["timings"]["sort_ms"].is_u64());
+
+    let f1 = &report["outputs"][0];
+    assert_eq!(f1["output"], "f1.csv");
+    assert_eq!(f1["matched_rows"], 5);
+    assert_eq!(f1["rows_written"], 2);
+    assert_eq!(
+        f1["bytes_written"],
+        std::fs::metadata(output_dir.path().join("f1.csv"))
+            .unwrap()
+            .len()
+    );
+
+    let f2 = &report["outputs"][1];
+    assert_eq!(f2["output"], "f2.csv");
+    assert_eq!(f2["matched_rows"], 0);
+    assert_eq!(f2["rows_written"], 0);
+}
+
+/// This test ensures that output files are moved to the output directory together with a
+/// manifest listing them, and that no staged files are left behind.
+#[test]
+fn writes_manifest_after_all_outputs_are_complete() {
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let output_file_path = output_dir.path().join("f1.csv");
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Assert
+    let manifest: serde_json::Value = serde_json::from_str(
+        &std::fs::read_to_string(output_dir.path().join(MANIFEST_FILE_NAME)).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(manifest["input"], path_to_string(&input_csv.path));
+    assert!(manifest["completed_at"].is_string());
+    assert_eq!(manifest["outputs"][0]["output"], "f1.csv");
+    assert_eq!(
+        manifest["outputs"][0]["bytes"],
+        std::fs::metadata(&output_file_path).unwrap().len()
+    );
+    assert_eq!(2, std::fs::read_dir(output_dir.path()).unwrap().count());
+}
+
+/// This test ensures that output files of a previous run stay intact if a run fails, and that
+/// the manifest is removed since the output directory is no longer known to be complete.
+#[test]
+fn keeps_previous_outputs_if_run_fails() {
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let expected_output_csv = Fixture::copy("default_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Act
+    let result = std::panic::catch_unwind(|| {
+        csv_filter::process(
+            &path_to_string(&output_dir.path().join("missing.csv")),
+            &path_to_string(&config.path),
+            &path_to_string(output_dir.path()),
+            &Options::default(),
+        )
+    });
+
+    // Assert
+    assert!(result.is_err());
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        &std::fs::read_to_string(output_dir.path().join("f1.csv")).unwrap()
+    );
+    assert!(!output_dir.path().join(MANIFEST_FILE_NAME).exists());
+}
+
+/// This test ensures that the program panics before processing if an output file already exists
+/// and existing files must not be replaced.
+#[test]
+#[should_panic(expected = "already exists")]
+fn panics_on_existing_output_file() {
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    std::fs::write(output_dir.path().join("f1.csv"), "col1,col3\n").unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            if_exists: IfExists::Error,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    // See macro 'should_panic'
+}
+
+/// This test ensures that existing output files are left untouched in skip mode.
+#[test]
+fn skips_existing_output_file() {
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let output_file_path = output_dir.path().join("f1.csv");
+    std::fs::write(&output_file_path, "col1,col3\nold,old\n").unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            if_exists: IfExists::Skip,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    assert_eq!(
+        "col1,col3\nold,old\n",
+        &std::fs::read_to_string(&output_file_path).unwrap()
+    );
+}
+
+/// This test ensures that rows are appended to an existing unsorted output file without
+/// repeating its header row.
+#[test]
+fn appends_rows_to_existing_output_file() {
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let output_file_path = output_dir.path().join("f1.csv");
+    std::fs::write(&output_file_path, "col1,col3\nold,old\n").unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            if_exists: IfExists::Append,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    assert_eq!(
+        "col1,col3\nold,old\nval1,val3\nval4,val6\nval7,val9\n",
+        &std::fs::read_to_string(&output_file_path).unwrap()
+    );
+}
+
+/// This test ensures that rows are appended to an existing unsorted output file in place instead
+/// of rewriting it, and that a missing line break after its last row is added first.
+#[cfg(unix)]
+#[test]
+fn appends_rows_in_place_to_existing_output_file() {
+    use std::os::unix::fs::MetadataExt;
+
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let output_file_path = output_dir.path().join("f1.csv");
+    std::fs::write(&output_file_path, "col1,col3\nold,old").unwrap();
+    let inode = std::fs::metadata(&output_file_path).unwrap().ino();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            if_exists: IfExists::Append,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    assert_eq!(
+        "col1,col3\nold,old\nval1,val3\nval4,val6\nval7,val9\n",
+        &std::fs::read_to_string(&output_file_path).unwrap()
+    );
+    assert_eq!(inode, std::fs::metadata(&output_file_path).unwrap().ino());
+}
+
+/// This test ensures that the commit of a run that was interrupted while appending rows in place
+/// is completed by the next run, replacing the partially appended rows instead of keeping them.
+#[test]
+fn completes_interrupted_append_from_journal() {
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let output_file_path = output_dir.path().join("f1.csv");
+    let staging_dir = output_dir.path().join(".csv-filter-staging");
+    let staged_file_path = staging_dir.join("f1.csv");
+    std::fs::create_dir(&staging_dir).unwrap();
+    std::fs::write(&staged_file_path, "col1,col3\nnew,new\n").unwrap();
+    std::fs::write(&output_file_path, "col1,col3\nold,old\nne").unwrap();
+    let journal = serde_json::json!({
+        "input": path_to_string(&input_csv.path),
+        "steps": [
+            {
+                "output": "f1.csv",
+                "source": staged_file_path,
+                "target": output_file_path,
+                "append_at": "col1,col3\nold,old\n".len()
+            }
+        ]
+    });
+    std::fs::write(output_dir.path().join("_JOURNAL.json"), journal.to_string()).unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            if_exists: IfExists::Append,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    assert_eq!(
+        "col1,col3\nold,old\nnew,new\nval1,val3\nval4,val6\nval7,val9\n",
+        &std::fs::read_to_string(&output_file_path).unwrap()
+    );
+    assert!(!output_dir.path().join("_JOURNAL.json").exists());
+    assert!(output_dir.path().join(MANIFEST_FILE_NAME).exists());
+}
+
+/// This test ensures that duplicates are removed across the rows of an existing output file and
+/// the appended rows, so that appending does not produce duplicates.
+#[test]
+fn removes_duplicates_across_existing_and_appended_rows() {
+    // Arrange
+    let config = Fixture::copy("distinct_keep_last.json");
+    let input_csv = Fixture::copy("distinct_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let output_file_path = output_dir.path().join("f1.csv");
+    std::fs::write(&output_file_path, "id,name,amount\n2,b,5\n4,d,40\n").unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            if_exists: IfExists::Append,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    assert_eq!(
+        "id,name,amount\n4,d,40\n3,c,30\n2,b,25\n1,a,10\n",
+        &std::fs::read_to_string(&output_file_path).unwrap()
+    );
+}
+
+/// This test ensures that appended rows are merged into an existing sorted output file, so that
+/// the file stays sorted.
+#[test]
+fn merges_appended_rows_into_sorted_output_file() {
+    // Arrange
+    let config = Fixture::copy("sort.json");
+    let input_csv = Fixture::copy("sort_input.csv");
+    let existing_csv = Fixture::copy("sort_existing.csv");
+    let expected_output_csv = Fixture::copy("sort_appended_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let output_file_path = output_dir.path().join("f1.csv");
+    std::fs::copy(&existing_csv.path, &output_file_path).unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            if_exists: IfExists::Append,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        &std::fs::read_to_string(&output_file_path).unwrap()
+    );
+}
+
+/// This test ensures that the program panics if rows should be appended to an existing output
+/// file whose header row does not match the configured output columns.
+#[test]
+#[should_panic(expected = "do not match the configured columns")]
+fn panics_on_appending_to_output_file_with_different_columns() {
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    std::fs::write(output_dir.path().join("f1.csv"), "col1,col2\n").unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            if_exists: IfExists::Append,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    // See macro 'should_panic'
+}
+
+/// This test ensures that output files which are not plain relative paths inside the output
+/// directory are reported as validation errors.
+#[test]
+fn validation_reports_output_paths_escaping_output_directory() {
+    // Arrange
+    let config = Fixture::copy("invalid_output_path.json");
+
+    // Act
+    let diagnostics = csv_filter::validate(&path_to_string(&config.path), None, None);
+
+    // Assert
+    let messages: Vec<String> = diagnostics.iter().map(|d| d.to_string()).collect();
+    assert_eq!(messages.len(), 6, "{:?}", messages);
+    assert!(messages[0].starts_with("error at $[0].output"));
+    assert!(messages[0].contains("must not refer to a parent directory"));
+    assert!(messages[1].starts_with("error at $[1].output"));
+    assert!(messages[1].contains("must be a relative path"));
+    assert!(messages[2].starts_with("error at $[2].output"));
+    assert!(messages[3].starts_with("error at $[3].output"));
+    assert!(messages[3].contains("is reserved"));
+    assert!(messages[4].starts_with("error at $[5].output"));
+    assert!(messages[4].contains("must name a file"));
+    assert!(messages[5].starts_with("error at $[6].output"));
+    assert!(messages[5].contains("must not be empty"));
+}
+
+/// This test ensures that output files are not written outside of the output directory through
+/// a symbolic link inside the output directory.
+#[cfg(unix)]
+#[test]
+fn panics_on_output_file_escaping_through_symbolic_link() {
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let outside_dir = tempfile::tempdir().unwrap();
+    let outside_file_path = outside_dir.path().join("f1.csv");
+    std::fs::write(&outside_file_path, "col1,col3\n").unwrap();
+    std::os::unix::fs::symlink(&outside_file_path, output_dir.path().join("f1.csv")).unwrap();
+
+    // Act
+    let result = std::panic::catch_unwind(|| {
+        csv_filter::process(
+            &path_to_string(&input_csv.path),
+            &path_to_string(&config.path),
+            &path_to_string(output_dir.path()),
+            &Options {
+                if_exists: IfExists::Append,
+                ..Options::default()
+            },
+        )
+    });
+
+    // Assert
+    let message = *result.unwrap_err().downcast::<String>().unwrap();
+    assert!(
+        message.contains("escapes the output directory"),
+        "{}",
+        message
+    );
+    assert_eq!(
+        "col1,col3\n",
+        std::fs::read_to_string(&outside_file_path).unwrap()
+    );
+}
+
+/// This test ensures that a dry run reports an output file escaping the output directory through
+/// a symbolic link as an error of the plan, pointing at the output of its configuration item.
+#[cfg(unix)]
+#[test]
+fn dry_run_reports_output_file_escaping_through_symbolic_link() {
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let outside_dir = tempfile::tempdir().unwrap();
+    std::os::unix::fs::symlink(outside_dir.path(), output_dir.path().join("linked")).unwrap();
+    let config_json = std::fs::read_to_string(&config.path)
+        .unwrap()
+        .replace("\"f1.csv\"", "\"linked/f1.csv\"");
+    std::fs::write(&config.path, config_json).unwrap();
+
+    // Act
+    let plan = csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            dry_run: true,
+            ..Options::default()
+        },
+    )
+    .unwrap();
+
+    // Assert
+    assert!(plan.has_errors());
+    let message = plan.diagnostics[0].to_string();
+    assert!(message.starts_with("error at $[0].output"), "{}", message);
+    assert!(
+        message.contains("escapes the output directory"),
+        "{}",
+        message
+    );
+}
+
+/// This test ensures that writing checkpoints does not change the output files and that the
+/// checkpoint file is removed once the run has finished.
+#[test]
+fn writes_same_output_files_with_checkpoints() {
+    // Arrange
+    let config = Fixture::copy("multiple.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let expected_output_csv_1 = Fixture::copy("multiple_output_1.csv");
+    let expected_output_csv_2 = Fixture::copy("multiple_output_2.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            no_sort: true,
+            filter_parallelism: 2,
+            checkpoint_interval: 1,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv_1.path).unwrap(),
+        &std::fs::read_to_string(output_dir.path().join("f1.csv")).unwrap()
+    );
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv_2.path).unwrap(),
+        &std::fs::read_to_string(output_dir.path().join("f2.csv")).unwrap()
+    );
+    assert!(!output_dir.path().join("_CHECKPOINT.json").exists());
+}
+
+/// This test ensures that an interrupted run is resumed from its checkpoint: rows written after
+/// the checkpoint are discarded and the input file is read from the checkpointed position on.
+#[test]
+fn resumes_from_checkpoint() {
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let expected_output_csv = Fixture::copy("default_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let report_path = output_dir.path().join("report.json");
+
+    // The first row has been processed before the checkpoint, a partial row after it
+    let staging_dir = output_dir.path().join(".csv-filter-staging");
+    std::fs::create_dir(&staging_dir).unwrap();
+    std::fs::write(staging_dir.join("f1.csv"), "col1,col3\nval1,val3\nval4,v").unwrap();
+    let checkpoint = serde_json::json!({
+        "input": path_to_string(&input_csv.path),
+        "input_bytes": std::fs::metadata(&input_csv.path).unwrap().len(),
+        "input_sha256": input_fingerprint(&path_to_string(&input_csv.path), 30).unwrap(),
+        "byte": 30,
+        "line": 3,
+        "record": 2,
+        "output_lengths": { "f1.csv": 20 },
+        "stats": { "rows_read": 1, "rows_rejected": 0, "matched_rows": { "f1.csv": 1 } }
+    });
+    std::fs::write(
+        output_dir.path().join("_CHECKPOINT.json"),
+        checkpoint.to_string(),
+    )
+    .unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            resume: true,
+            report: Some(path_to_string(&report_path)),
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    assert_eq!(
+        &std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        &std::fs::read_to_string(output_dir.path().join("f1.csv")).unwrap()
+    );
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&report_path).unwrap()).unwrap();
+    assert_eq!(report["rows_read"], 3);
+    assert_eq!(report["outputs"][0]["matched_rows"], 3);
+    assert!(!output_dir.path().join("_CHECKPOINT.json").exists());
+    assert!(!staging_dir.exists());
+}
+
+/// This test ensures that a checkpoint is not resumed from if the rows before the checkpointed
+/// position have been changed, even though the size of the input file is the same.
+#[test]
+#[should_panic(expected = "Cannot resume from checkpoint")]
+fn rejects_checkpoint_of_changed_input_with_same_size() {
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let staging_dir = output_dir.path().join(".csv-filter-staging");
+    std::fs::create_dir(&staging_dir).unwrap();
+    std::fs::write(staging_dir.join("f1.csv"), "col1,col3\nval1,val3\n").unwrap();
+    let checkpoint = serde_json::json!({
+        "input": path_to_string(&input_csv.path),
+        "input_bytes": std::fs::metadata(&input_csv.path).unwrap().len(),
+        "input_sha256": input_fingerprint(&path_to_string(&input_csv.path), 30).unwrap(),
+        "byte": 30,
+        "line": 3,
+        "record": 2,
+        "output_lengths": { "f1.csv": 20 },
+        "stats": { "rows_read": 1, "rows_rejected": 0, "matched_rows": { "f1.csv": 1 } }
+    });
+    std::fs::write(
+        output_dir.path().join("_CHECKPOINT.json"),
+        checkpoint.to_string(),
+    )
+    .unwrap();
+    let input = std::fs::read_to_string(&input_csv.path).unwrap();
+    std::fs::write(&input_csv.path, input.replacen("val1", "valX", 1)).unwrap();
+
+    // Act
+    csv_filter::process(
+        &path_to_string(&input_csv.path),
+        &path_to_string(&config.path),
+        &path_to_string(output_dir.path()),
+        &Options {
+            resume: true,
+            ..Options::default()
+        },
+    );
+
+    // Assert
+    // See macro 'should_panic'
+}
+
+/// This test ensures that incremental runs only process rows appended to the input file since
+/// the last run and append them to the output files. A row that is still being appended, i.e.
+/// the last line without a line break, is left for the next run.
+#[test]
+fn processes_only_appended_rows_incrementally() {
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let work_dir = tempfile::tempdir().unwrap();
+    let input_path = work_dir.path().join("input.csv");
+    let state_path = work_dir.path().join("state.json");
+    let report_path = work_dir.path().join("report.json");
+    let output_dir = work_dir.path().join("output");
+    let options = Options {
+        no_sort: true,
+        state: Some(path_to_string(&state_path)),
+        report: Some(path_to_string(&report_path)),
+        ..Options::default()
+    };
+    let run = || {
+        csv_filter::process(
+            &path_to_string(&input_path),
+            &path_to_string(&config.path),
+            &path_to_string(&output_dir),
+            &options,
+        )
+    };
+    let rows_read = || {
+        let report: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&report_path).unwrap()).unwrap();
+        report["rows_read"].clone()
+    };
+    std::fs::write(&input_path, "col1,col2,col3\nval1,val2,val3\n").unwrap();
+    run();
+
+    // Act
+    std::fs::write(
+        &input_path,
+        "col1,col2,col3\nval1,val2,val3\nval4,val5,val6\nval7,val8",
+    )
+    .unwrap();
+    run();
+    let rows_read_with_partial_row = rows_read();
+    let output_with_partial_row = std::fs::read_to_string(output_dir.join("f1.csv")).unwrap();
+
+    std::fs::write(
+        &input_path,
+        "col1,col2,col3\nval1,val2,val3\nval4,val5,val6\nval7,val8,val9\n",
+    )
+    .unwrap();
+    run();
+
+    // Assert
+    assert_eq!(rows_read_with_partial_row, 1);
+    assert_eq!("col1,col3\nval1,val3\nval4,val6\n", output_with_partial_row);
+    assert_eq!(rows_read(), 1);
+    assert_eq!(
+        "col1,col3\nval1,val3\nval4,val6\nval7,val9\n",
+        std::fs::read_to_string(output_dir.join("f1.csv")).unwrap()
+    );
+}
+
+/// This test ensures that the state file is committed along with the output files, so that an
+/// incremental run that was interrupted while moving files is completed by the next run without
+/// appending any row twice.
+#[test]
+fn commits_state_file_along_with_output_files() {
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let work_dir = tempfile::tempdir().unwrap();
+    let input_path = work_dir.path().join("input.csv");
+    let state_path = work_dir.path().join("state.json");
+    let output_dir = work_dir.path().join("output");
+    let output_file_path = output_dir.join("f1.csv");
+    let options = Options {
+        no_sort: true,
+        state: Some(path_to_string(&state_path)),
+        ..Options::default()
+    };
+    let run = || {
+        csv_filter::process(
+            &path_to_string(&input_path),
+            &path_to_string(&config.path),
+            &path_to_string(&output_dir),
+            &options,
+        )
+    };
+    std::fs::write(&input_path, "col1,col2,col3\nval1,val2,val3\n").unwrap();
+    run();
+    let previous_state = std::fs::read_to_string(&state_path).unwrap();
+    std::fs::write(
+        &input_path,
+        "col1,col2,col3\nval1,val2,val3\nval4,val5,val6\n",
+    )
+    .unwrap();
+    run();
+    let state = std::fs::read_to_string(&state_path).unwrap();
+
+    // Reverts the second run to the point where it was interrupted while appending rows
+    let staging_dir = output_dir.join(".csv-filter-staging");
+    let staged_file_path = staging_dir.join("f1.csv");
+    let staged_state_path = work_dir.path().join("state.json.tmp");
+    std::fs::create_dir(&staging_dir).unwrap();
+    std::fs::write(&staged_file_path, "col1,col3\nval4,val6\n").unwrap();
+    std::fs::write(&staged_state_path, &state).unwrap();
+    std::fs::write(&state_path, &previous_state).unwrap();
+    std::fs::write(&output_file_path, "col1,col3\nval1,val3\nval4").unwrap();
+    let journal = serde_json::json!({
+        "input": path_to_string(&input_path),
+        "steps": [
+            {
+                "output": "f1.csv",
+                "source": staged_file_path,
+                "target": output_file_path,
+                "append_at": "col1,col3\nval1,val3\n".len()
+            },
+            {
+                "output": null,
+                "source": staged_state_path,
+                "target": state_path,
+                "append_at": null
+            }
+        ]
+    });
+    std::fs::write(output_dir.join("_JOURNAL.json"), journal.to_string()).unwrap();
+
+    // Act
+    run();
+
+    // Assert
+    assert_eq!(
+        "col1,col3\nval1,val3\nval4,val6\n",
+        std::fs::read_to_string(&output_file_path).unwrap()
+    );
+    assert!(!staged_state_path.exists());
+    assert!(!output_dir.join("_JOURNAL.json").exists());
+}
+
+/// This test ensures that the whole input file is processed again if it has been rotated, i.e.
+/// if it no longer starts with the rows consumed by the last run.
+#[test]
+fn processes_rotated_input_file_completely() {
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let work_dir = tempfile::tempdir().unwrap();
+    let input_path = work_dir.path().join("input.csv");
+    let output_dir = work_dir.path().join("output");
+    let options = Options {
+        no_sort: true,
+        state: Some(path_to_string(&work_dir.path().join("state.json"))),
+        ..Options::default()
+    };
+    std::fs::write(&input_path, "col1,col2,col3\nval1,val2,val3\n").unwrap();
+    csv_filter::process(
+        &path_to_string(&input_path),
+        &path_to_string(&config.path),
+        &path_to_string(&output_dir),
+        &options,
+    );
+
+    // Act
+    std::fs::write(
+        &input_path,
+        "col1,col2,col3\nnew1,new2,new3\nnew4,new5,new6\n",
+    )
+    .unwrap();
+    csv_filter::process(
+        &path_to_string(&input_path),
+        &path_to_string(&config.path),
+        &path_to_string(&output_dir),
+        &options,
+    );
+
+    // Assert
+    assert_eq!(
+        "col1,col3\nnew1,new3\nnew4,new6\n",
+        std::fs::read_to_string(output_dir.join("f1.csv")).unwrap()
+    );
+}
+
+/// This test ensures that a query given on the command line is processed like a configuration
+/// item: value lists and expressions filter rows, only the selected columns are written and rows
+/// are sorted in descending order.
+#[test]
+fn processes_inline_query() {
+    // Arrange
+    let input_csv = Fixture::copy("query_input.csv");
+    let expected_output_csv = Fixture::copy("query_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let query = Query {
+        where_clause: Some(String::from("country in (DE, AT) and amount >= 100")),
+        select: Some(String::from("id, amount")),
+        sort_by: Some(String::from("id:desc")),
+        output: String::from("result.csv"),
+    };
+
+    // Act
+    let result = csv_filter::process_query(
+        &path_to_string(&input_csv.path),
+        &query,
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Assert
+    assert!(result.is_ok());
+    assert_eq!(
+        std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        std::fs::read_to_string(output_dir.path().join("result.csv")).unwrap()
+    );
+}
+
+/// This test ensures that rows with an empty or non-numeric value are rejected by a numeric
+/// condition in `--where` instead of aborting the run.
+#[test]
+fn processes_inline_query_with_invalid_numbers() {
+    // Arrange
+    let input_csv = Fixture::copy("query_invalid_amount_input.csv");
+    let expected_output_csv = Fixture::copy("query_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let query = Query {
+        where_clause: Some(String::from("country in (DE, AT) and amount >= 100")),
+        select: Some(String::from("id, amount")),
+        sort_by: Some(String::from("id:desc")),
+        output: String::from("result.csv"),
+    };
+
+    // Act
+    let result = csv_filter::process_query(
+        &path_to_string(&input_csv.path),
+        &query,
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Assert
+    assert!(result.is_ok());
+    assert_eq!(
+        std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        std::fs::read_to_string(output_dir.path().join("result.csv")).unwrap()
+    );
+}
+
+/// This test ensures that column names in `--select` and `--sort-by` may be quoted with
+/// backticks like in `--where`, so that columns containing commas can be selected and sorted by.
+#[test]
+fn processes_inline_query_with_quoted_columns() {
+    // Arrange
+    let input_csv = Fixture::copy("query_quoted_columns_input.csv");
+    let expected_output_csv = Fixture::copy("query_quoted_columns_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let query = Query {
+        select: Some(String::from("id, `amount, net`")),
+        sort_by: Some(String::from("`amount, net`:desc")),
+        output: String::from("result.csv"),
+        ..Query::default()
+    };
+
+    // Act
+    let result = csv_filter::process_query(
+        &path_to_string(&input_csv.path),
+        &query,
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Assert
+    assert!(result.is_ok());
+    assert_eq!(
+        std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        std::fs::read_to_string(output_dir.path().join("result.csv")).unwrap()
+    );
+}
+
+/// This test ensures that an invalid sort direction is reported at its position.
+#[test]
+fn reports_position_of_invalid_sort_direction() {
+    // Arrange
+    let input_csv = Fixture::copy("query_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let query = Query {
+        select: Some(String::from("id, amount")),
+        sort_by: Some(String::from("id, amount:down")),
+        output: String::from("result.csv"),
+        ..Query::default()
+    };
+
+    // Act
+    let error = csv_filter::process_query(
+        &path_to_string(&input_csv.path),
+        &query,
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    )
+    .unwrap_err();
+
+    // Assert
+    assert_eq!("Expected 'asc' or 'desc'", error.message);
+    assert_eq!(11, error.position);
+}
+
+/// This test ensures that an invalid query is reported along with the position of the offending
+/// token and that no output file is written.
+#[test]
+fn reports_position_of_invalid_query_token() {
+    // Arrange
+    let input_csv = Fixture::copy("query_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let query = Query {
+        where_clause: Some(String::from("country in (DE, AT and amount >= 100")),
+        output: String::from("result.csv"),
+        ..Query::default()
+    };
+
+    // Act
+    let error = csv_filter::process_query(
+        &path_to_string(&input_csv.path),
+        &query,
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    )
+    .unwrap_err();
+
+    // Assert
+    assert_eq!("Expected ',' or ')'", error.message);
+    assert_eq!(19, error.position);
+    assert!(error
+        .to_string()
+        .ends_with(&format!("\n  {}^", " ".repeat(19))));
+    assert!(!output_dir.path().join("result.csv").exists());
+}
+
+/// This test ensures that every statement of an SQL file is turned into an output file, with
+/// ranges, aliases, computed columns and descending sort columns.
+#[test]
+fn processes_sql_statements() {
+    // Arrange
+    let input_csv = Fixture::copy("query_input.csv");
+    let statements = Fixture::copy("sql_statements.sql");
+    let expected_range_csv = Fixture::copy("sql_range_output.csv");
+    let expected_germany_csv = Fixture::copy("sql_germany_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let sql = SqlQuery {
+        name: path_to_string(&statements.path),
+        statements: std::fs::read_to_string(&statements.path).unwrap(),
+        output: String::from("output.csv"),
+    };
+
+    // Act
+    let result = csv_filter::process_sql(
+        &path_to_string(&input_csv.path),
+        &sql,
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Assert
+    assert!(result.is_ok());
+    assert_eq!(
+        std::fs::read_to_string(&expected_range_csv.path).unwrap(),
+        std::fs::read_to_string(output_dir.path().join("range.csv")).unwrap()
+    );
+    assert_eq!(
+        std::fs::read_to_string(&expected_germany_csv.path).unwrap(),
+        std::fs::read_to_string(output_dir.path().join("germany.csv")).unwrap()
+    );
+}
+
+/// This test ensures that rows with an empty or non-numeric value are rejected by a numeric
+/// `WHERE` condition of an SQL statement instead of aborting the run.
+#[test]
+fn processes_sql_statement_with_invalid_numbers() {
+    // Arrange
+    let input_csv = Fixture::copy("query_invalid_amount_input.csv");
+    let expected_output_csv = Fixture::copy("sql_invalid_amount_output.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let sql = SqlQuery {
+        name: String::from("--query"),
+        statements: String::from(
+            "SELECT id, amount FROM input WHERE amount > 100 ORDER BY id DESC",
+        ),
+        output: String::from("result.csv"),
+    };
+
+    // Act
+    let result = csv_filter::process_sql(
+        &path_to_string(&input_csv.path),
+        &sql,
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    );
+
+    // Assert
+    assert!(result.is_ok());
+    assert_eq!(
+        std::fs::read_to_string(&expected_output_csv.path).unwrap(),
+        std::fs::read_to_string(output_dir.path().join("result.csv")).unwrap()
+    );
+}
+
+/// This test ensures that unsupported SQL constructs like joins are reported at the position of
+/// the offending keyword.
+#[test]
+fn reports_unsupported_sql_construct() {
+    // Arrange
+    let input_csv = Fixture::copy("query_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let sql = SqlQuery {
+        name: String::from("--query"),
+        statements: String::from("SELECT id FROM input JOIN other ON input.id = other.id"),
+        output: String::from("result.csv"),
+    };
+
+    // Act
+    let error = csv_filter::process_sql(
+        &path_to_string(&input_csv.path),
+        &sql,
+        &path_to_string(output_dir.path()),
+        &Options::default(),
+    )
+    .unwrap_err();
+
+    // Assert
+    assert_eq!("Joins are not supported", error.message);
+    assert_eq!(21, error.position);
+    assert!(!output_dir.path().join("result.csv").exists());
+}
+
+/// A predicate that rejects all rows whose first column has a given value, in addition to the
+/// built-in filters.
+struct ExcludingPredicate(&'static str);
+
+impl Predicate for ExcludingPredicate {
+    fn matches<'a>(
+        &self,
+        config: &FilterConfig,
+        column_value: &dyn Fn(&str) -> Option<&'a str>,
+    ) -> bool {
+        column_value("col1") != Some(self.0) && ColumnFilterPredicate.matches(config, column_value)
+    }
+}
+
+/// A sink that keeps all rows in memory.
+#[derive(Default)]
+struct MemorySink {
+    rows: Mutex<Vec<(String, Vec<String>)>>,
+}
+
+impl RecordSink for MemorySink {
+    fn write_headers(&self, output: &str, headers: Vec<String>) {
+        self.write_record(output, headers);
+    }
+
+    fn write_record(&self, output: &str, record: Vec<String>) {
+        self.rows.lock().unwrap().push((output.to_string(), record));
+    }
+}
+
+/// This test ensures that the filter stage can be used with a custom predicate and a custom sink
+/// instead of the built-in filters and output files.
+#[test]
+fn filters_with_custom_predicate_and_sink() {
+    // Arrange
+    let config = Fixture::copy("values.json");
+    let input_csv = Fixture::copy("values_input.csv");
+    let configs: Vec<FilterConfig> =
+        serde_json::from_str(&std::fs::read_to_string(&config.path).unwrap()).unwrap();
+    let configs: Vec<Arc<FilterConfig>> = configs.into_iter().map(Arc::new).collect();
+    let mut sink = MemorySink::default();
+
+    // Act
+    let stats = csv_filter_filter::filter(
+        &path_to_string(&input_csv.path),
+        &configs,
+        &ExcludingPredicate("val16"),
+        &mut sink,
+        &FilterOptions {
+            max_threads: 1,
+            ..FilterOptions::default()
+        },
+    );
+
+    // Assert
+    let row = |values: &[&str]| {
+        (
+            String::from("f1.csv"),
+            values
+                .iter()
+                .map(|v| v.to_string())
+                .collect::<Vec<String>>(),
+        )
+    };
+    assert_eq!(
+        vec![
+            row(&["col1", "col2", "col3"]),
+            row(&["val4", "val5", "val6"])
+        ],
+        sink.rows.into_inner().unwrap()
+    );
+    assert_eq!(1, stats.matched_rows["f1.csv"]);
+}
+
+/// This test ensures that the built-in value and range predicates can be used on their own: the
+/// value predicate checks value lists, while the range predicate ignores them.
+#[test]
+fn filters_with_value_and_range_predicates() {
+    // Arrange
+    let config = Fixture::copy("values.json");
+    let input_csv = Fixture::copy("values_input.csv");
+    let configs: Vec<FilterConfig> =
+        serde_json::from_str(&std::fs::read_to_string(&config.path).unwrap()).unwrap();
+    let configs: Vec<Arc<FilterConfig>> = configs.into_iter().map(Arc::new).collect();
+    let options = FilterOptions {
+        max_threads: 1,
+        ..FilterOptions::default()
+    };
+
+    // Act
+    let value_stats = csv_filter_filter::filter(
+        &path_to_string(&input_csv.path),
+        &configs,
+        &ValuePredicate,
+        &mut MemorySink::default(),
+        &options,
+    );
+    let range_stats = csv_filter_filter::filter(
+        &path_to_string(&input_csv.path),
+        &configs,
+        &RangePredicate,
+        &mut MemorySink::default(),
+        &options,
+    );
+
+    // Assert
+    assert_eq!(2, value_stats.matched_rows["f1.csv"]);
+    assert_eq!(8, range_stats.matched_rows["f1.csv"]);
+}
+
+/// This test ensures that `-q`, `-v` and `-vv` filter log messages by level, that all messages
+/// are written to stderr and that nothing is written to stdout. Since stderr is not a terminal,
+/// no progress indicator is drawn either.
+#[test]
+fn filters_log_messages_by_verbosity() {
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let run = |flags: &[&str]| {
+        let mut args = flags.to_vec();
+        args.extend_from_slice(&[
+            "run",
+            "--configuration",
+            config.to_str().unwrap(),
+            "--input",
+            input_csv.to_str().unwrap(),
+            "--output",
+            output_dir.path().to_str().unwrap(),
+            "--if-exists",
+            "overwrite",
+        ]);
+        let output = run_cli(&args);
+        assert!(output.status.success(), "{:?}", output);
+        assert!(output.stdout.is_empty(), "{:?}", output);
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    // Act
+    let quiet = run(&["-q"]);
+    let default = run(&[]);
+    let verbose = run(&["-v"]);
+    let very_verbose = run(&["-vv"]);
+
+    // Assert
+    assert_eq!("", quiet);
+    assert!(default.contains("INFO "), "{}", default);
+    assert!(!default.contains("DEBUG"), "{}", default);
+    assert!(verbose.contains("DEBUG"), "{}", verbose);
+    assert!(!verbose.contains("TRACE"), "{}", verbose);
+    assert!(very_verbose.contains("TRACE"), "{}", very_verbose);
+    for stderr in &[default, verbose, very_verbose] {
+        assert!(!stderr.contains('\x1b'), "{}", stderr);
+    }
+}
+
+/// This test ensures that `--log-format json` writes every log message to stderr as one JSON
+/// object per line, with the timestamp, level, target and message of the record.
+#[test]
+fn writes_log_messages_as_json() {
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+
+    // Act
+    let output = run_cli(&[
+        "--log-format",
+        "json",
+        "run",
+        "--configuration",
+        config.to_str().unwrap(),
+        "--input",
+        input_csv.to_str().unwrap(),
+        "--output",
+        output_dir.path().to_str().unwrap(),
+    ]);
+
+    // Assert
+    assert!(output.status.success(), "{:?}", output);
+    assert!(output.stdout.is_empty(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stderr.is_empty());
+    for line in stderr.lines() {
+        let record: serde_json::Value = serde_json::from_str(line).unwrap();
+        assert!(
+            record["level"] == "INFO" || record["level"] == "WARN",
+            "{}",
+            line
+        );
+        assert!(
+            record["timestamp"].as_str().unwrap().ends_with('Z'),
+            "{}",
+            line
+        );
+        assert!(record["target"].is_string(), "{}", line);
+        assert!(record["message"].is_string(), "{}", line);
+    }
+}
+
+/// This test ensures that watch mode processes the input file again once the input file or the
+/// configuration file changes, and that the row counts of every run are printed along with their
+/// difference to the previous run. Rewriting a file with the same content triggers a run as well.
+#[test]
+fn watch_reruns_on_input_and_config_changes() {
+    // Arrange
+    let config = Fixture::copy("default.json");
+    let values_config = Fixture::copy("values.json");
+    let input_csv = Fixture::copy("default_input.csv");
+    let output_dir = tempfile::tempdir().unwrap();
+    let watch = spawn_cli(&[
+        "run",
+        "--configuration",
+        config.to_str().unwrap(),
+        "--input",
+        input_csv.to_str().unwrap(),
+        "--output",
+        output_dir.path().to_str().unwrap(),
+        "--watch",
+    ]);
+    let first_run = watch.next_line();
+
+    // Act
+    let mut input = std::fs::read_to_string(&input_csv.path).unwrap();
+    input.push_str("\nval10,val11,val12\n");
+    std::fs::write(&input_csv.path, input).unwrap();
+    let input_changed_run = watch.next_line();
+    std::fs::copy(&values_config.path, &config.path).unwrap();
+    let config_changed_run = watch.next_line();
+    std::fs::copy(&values_config.path, &config.path).unwrap();
+    let rewritten_run = watch.next_line();
+
+    // Assert
+    assert_eq!("f1.csv           3 rows  (new)", first_run);
+    assert_eq!("f1.csv           4 rows  (+1)", input_changed_run);
+    assert_eq!("f1.csv           1 rows  (-3)", config_changed_run);
+    assert_eq!("f1.csv           1 rows  (unchanged)", rewritten_run);
+}
@@ -1,7 +1,12 @@
 extern crate tempfile;
 use self::tempfile::TempDir;
+use std::io::{BufRead, BufReader};
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
 use std::{env, fs};
 
 pub struct Fixture {
@@ -13,7 +18,7 @@ impl Fixture {
     pub fn blank(fixture_filename: &str) -> Self {
         let tempdir = tempfile::tempdir().unwrap();
         let mut path = PathBuf::from(&tempdir.path());
-        path.push(&fixture_filename);
+        path.push(fixture_filename);
 
         Fixture {
             _temp_dir: tempdir,
@@ -23,15 +28,32 @@ impl Fixture {
 
     pub fn copy(fixture_filename: &str) -> Self {
         let fixture = Fixture::blank(fixture_filename);
-        let root_dir = &env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR");
-        let mut source = PathBuf::from(root_dir);
-        source.push("tests");
-        source.push("fixtures");
-        source.push(&fixture_filename);
-        fs::copy(&source, &fixture.path)
-            .expect(&format!("Cannot copy fixture '{}'", fixture_filename));
+        copy_fixture(fixture_filename, &fixture.path);
         fixture
     }
+
+    // Copies the files referenced by a fixture into the same directory, so that the relative
+    // paths of the fixture resolve
+    pub fn copy_with(fixture_filename: &str, referenced_filenames: &[&str]) -> Self {
+        let fixture = Fixture::copy(fixture_filename);
+        for referenced_filename in referenced_filenames {
+            copy_fixture(
+                referenced_filename,
+                &fixture.path.with_file_name(referenced_filename),
+            );
+        }
+        fixture
+    }
+}
+
+fn copy_fixture(fixture_filename: &str, target: &Path) {
+    let root_dir = &env::var("CARGO_MANIFEST_DIR").expect("$CARGO_MANIFEST_DIR");
+    let mut source = PathBuf::from(root_dir);
+    source.push("tests");
+    source.push("fixtures");
+    source.push(fixture_filename);
+    fs::copy(&source, target)
+        .unwrap_or_else(|_| panic!("Cannot copy fixture '{}'", fixture_filename));
 }
 
 impl Deref for Fixture {
@@ -45,3 +67,52 @@ impl Deref for Fixture {
 pub fn path_to_string(path: &Path) -> String {
     path.to_str().unwrap().to_string()
 }
+
+// Runs the command line tool with piped stdout and stderr, so that neither is a terminal
+pub fn run_cli(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_csv-filter"))
+        .args(args)
+        .output()
+        .expect("Cannot run csv-filter")
+}
+
+// A command line tool running in the background, which is killed once dropped
+pub struct BackgroundCli {
+    child: Child,
+    lines: Receiver<String>,
+}
+
+impl BackgroundCli {
+    // Waits for the next line the tool writes to stdout
+    pub fn next_line(&self) -> String {
+        self.lines
+            .recv_timeout(Duration::from_secs(30))
+            .expect("No output of csv-filter within 30 seconds")
+    }
+}
+
+impl Drop for BackgroundCli {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub fn spawn_cli(args: &[&str]) -> BackgroundCli {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_csv-filter"))
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Cannot run csv-filter");
+    let stdout = child.stdout.take().unwrap();
+    let (sender, lines) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            if sender.send(line.unwrap()).is_err() {
+                break;
+            }
+        }
+    });
+    BackgroundCli { child, lines }
+}